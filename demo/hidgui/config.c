@@ -0,0 +1,574 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <ctype.h>
+#include <errno.h>
+#include <sys/stat.h>
+
+#include "config.h"
+
+void default_options(options *opts)
+{
+    memset(opts, 0, sizeof(*opts));
+
+    snprintf(opts->hosts[0], sizeof(opts->hosts[0]), "%s", TARGET_IP);
+    opts->host_count = 1;
+    opts->default_host = 1;
+    opts->port = TARGET_PORT;
+    opts->stats = 0;
+    opts->type_delay = TYPE_DELAY_MS;
+    snprintf(opts->target_layout, sizeof(opts->target_layout), "us");
+    opts->release_timeout = RELEASE_TIMEOUT;
+    snprintf(opts->backend, sizeof(opts->backend), "sdl");
+    snprintf(opts->lock_sync, sizeof(opts->lock_sync), "warn");
+    opts->wake_wait = WAKE_WAIT;
+    opts->turbo_rate = TURBO_RATE;
+    opts->repeat_rate = REPEAT_RATE;
+    opts->sensitivity = 1.0;
+    opts->motion_interval = MOTION_INTERVAL_MS;
+    opts->scroll_speed = 1.0;
+    opts->hscroll = 1;
+    opts->width = WINDOW_WIDTH;
+    opts->height = WINDOW_HEIGHT;
+    snprintf(opts->grab_key, sizeof(opts->grab_key), "G");
+}
+
+static int parse_bool(const char *value)
+{
+    return strcmp(value, "true") == 0 || strcmp(value, "1") == 0;
+}
+
+// set an option by name, as found in the config file.
+// returns -1 if the option is unknown
+int set_option(options *opts, const char *key, const char *value)
+{
+    if (strcmp(key, "host") == 0) {
+        if (opts->host_count >= MAX_TARGETS) {
+            printf("Too many hosts, at most %d supported\n", MAX_TARGETS);
+            return -1;
+        }
+        snprintf(
+            opts->hosts[opts->host_count],
+            sizeof(opts->hosts[0]),
+            "%s",
+            value);
+        opts->host_count++;
+        opts->default_host = 0;
+    } else if (strcmp(key, "port") == 0) {
+        opts->port = atoi(value);
+    } else if (strcmp(key, "audit") == 0) {
+        snprintf(opts->audit, sizeof(opts->audit), "%s", value);
+    } else if (strcmp(key, "audit_keys") == 0) {
+        opts->audit_keys = parse_bool(value);
+    } else if (strcmp(key, "stats") == 0) {
+        opts->stats = parse_bool(value);
+    } else if (strcmp(key, "sensitivity") == 0) {
+        opts->sensitivity = atof(value);
+    } else if (strcmp(key, "scroll_speed") == 0) {
+        opts->scroll_speed = atof(value);
+    } else if (strcmp(key, "invert_scroll") == 0) {
+        opts->invert_scroll = parse_bool(value);
+    } else if (strcmp(key, "invert_hscroll") == 0) {
+        opts->invert_hscroll = parse_bool(value);
+    } else if (strcmp(key, "hscroll") == 0) {
+        opts->hscroll = parse_bool(value);
+    } else if (strcmp(key, "swap_scroll") == 0) {
+        opts->swap_scroll = parse_bool(value);
+    } else if (strcmp(key, "absolute") == 0) {
+        opts->absolute = parse_bool(value);
+    } else if (strcmp(key, "target_screen") == 0) {
+        snprintf(opts->target_screen, sizeof(opts->target_screen), "%s", value);
+    } else if (strcmp(key, "target_desktop") == 0) {
+        snprintf(opts->target_desktop, sizeof(opts->target_desktop), "%s", value);
+    } else if (strcmp(key, "turbo_keys") == 0) {
+        snprintf(opts->turbo_keys, sizeof(opts->turbo_keys), "%s", value);
+    } else if (strcmp(key, "turbo_rate") == 0) {
+        opts->turbo_rate = atoi(value);
+    } else if (strcmp(key, "repeat_delay") == 0) {
+        opts->repeat_delay = atoi(value);
+    } else if (strcmp(key, "max_pps") == 0) {
+        opts->max_pps = atoi(value);
+    } else if (strcmp(key, "split_delay") == 0) {
+        opts->split_delay = atoi(value);
+    } else if (strcmp(key, "motion_interval") == 0) {
+        opts->motion_interval = atoi(value);
+    } else if (strcmp(key, "repeat_rate") == 0) {
+        opts->repeat_rate = atoi(value);
+    } else if (strcmp(key, "sticky_keys") == 0) {
+        opts->sticky_keys = parse_bool(value);
+    } else if (strcmp(key, "keep_awake") == 0) {
+        opts->keep_awake = atoi(value);
+    } else if (strcmp(key, "backend") == 0) {
+        snprintf(opts->backend, sizeof(opts->backend), "%s", value);
+    } else if (strcmp(key, "full_capture") == 0) {
+        opts->full_capture = parse_bool(value);
+    } else if (strcmp(key, "local_media_keys") == 0) {
+        snprintf(opts->local_media_keys, sizeof(opts->local_media_keys), "%s", value);
+    } else if (strcmp(key, "shared_media_keys") == 0) {
+        snprintf(opts->shared_media_keys, sizeof(opts->shared_media_keys), "%s", value);
+    } else if (strcmp(key, "lock_sync") == 0) {
+        snprintf(opts->lock_sync, sizeof(opts->lock_sync), "%s", value);
+    } else if (strcmp(key, "wake_mac") == 0) {
+        snprintf(opts->wake_mac, sizeof(opts->wake_mac), "%s", value);
+    } else if (strcmp(key, "wake") == 0) {
+        opts->wake = parse_bool(value);
+    } else if (strcmp(key, "wake_wait") == 0) {
+        opts->wake_wait = atoi(value);
+    } else if (strcmp(key, "release_timeout") == 0) {
+        opts->release_timeout = atoi(value);
+    } else if (strcmp(key, "type_delay") == 0) {
+        opts->type_delay = atoi(value);
+    } else if (strcmp(key, "target_layout") == 0) {
+        snprintf(opts->target_layout, sizeof(opts->target_layout), "%s", value);
+    } else if (strcmp(key, "fullscreen") == 0) {
+        opts->fullscreen = parse_bool(value);
+    } else if (strcmp(key, "borderless") == 0) {
+        opts->borderless = parse_bool(value);
+    } else if (strcmp(key, "width") == 0) {
+        opts->width = atoi(value);
+    } else if (strcmp(key, "height") == 0) {
+        opts->height = atoi(value);
+    } else if (strcmp(key, "remap") == 0) {
+        snprintf(opts->remap, sizeof(opts->remap), "%s", value);
+    } else if (strcmp(key, "grab_key") == 0) {
+        snprintf(opts->grab_key, sizeof(opts->grab_key), "%s", value);
+    } else if (strcmp(key, "leader_key") == 0) {
+        snprintf(opts->leader_key, sizeof(opts->leader_key), "%s", value);
+    } else if (strcmp(key, "compose_key") == 0) {
+        snprintf(opts->compose_key, sizeof(opts->compose_key), "%s", value);
+    } else {
+        return -1;
+    }
+
+    return 0;
+}
+
+static char *trim(char *s)
+{
+    char *end;
+
+    while (isspace((unsigned char) *s)) {
+        s++;
+    }
+
+    end = s + strlen(s);
+    while (end > s && isspace((unsigned char) end[-1])) {
+        *--end = '\0';
+    }
+
+    return s;
+}
+
+// strings are quoted
+static char *unquote(char *s)
+{
+    size_t len = strlen(s);
+
+    if (len >= 2 && s[0] == '"' && s[len - 1] == '"') {
+        s[len - 1] = '\0';
+        s++;
+    }
+
+    return s;
+}
+
+// split a "key = value" line, returns -1 if there's no =
+static int split_line(char *s, char **key, char **value)
+{
+    char *eq;
+
+    // keys may be quoted and contain =, so look for it after the key
+    if (*s == '"') {
+        eq = strchr(s + 1, '"');
+        eq = eq != NULL ? strchr(eq, '=') : NULL;
+    } else {
+        eq = strchr(s, '=');
+    }
+
+    if (eq == NULL) {
+        return -1;
+    }
+
+    *eq = '\0';
+    *key = unquote(trim(s));
+    *value = unquote(trim(eq + 1));
+
+    return 0;
+}
+
+// strip comments and whitespace. # inside quotes is part of the string,
+// as is an escaped \" there
+static char *clean_line(char *line)
+{
+    int quoted = 0;
+
+    for (char *s = line; *s != '\0'; s++) {
+        if (quoted && *s == '\\' && s[1] != '\0') {
+            s++;
+        } else if (*s == '"') {
+            quoted = !quoted;
+        } else if (*s == '#' && !quoted) {
+            *s = '\0';
+            break;
+        }
+    }
+
+    return trim(line);
+}
+
+// returns the settings for a host, added if not there yet
+static host_settings *add_host_settings(options *opts, const char *name)
+{
+    host_settings *host;
+
+    for (int i = 0; i < opts->host_settings_count; i++) {
+        if (strcmp(opts->host_settings[i].name, name) == 0) {
+            return &opts->host_settings[i];
+        }
+    }
+
+    if (opts->host_settings_count >= MAX_HOST_SETTINGS) {
+        return NULL;
+    }
+
+    host = &opts->host_settings[opts->host_settings_count++];
+    snprintf(host->name, sizeof(host->name), "%s", name);
+    host->remap[0] = '\0';
+    host->target_layout[0] = '\0';
+
+    return host;
+}
+
+// add a leader key macro, with \n, \t, \" and \\ escapes in the text.
+// returns -1 if there are too many
+static int add_leader_macro(options *opts, const char *sequence, const char *text)
+{
+    leader_macro *macro;
+    size_t len = 0;
+
+    if (opts->leader_macro_count >= MAX_LEADER_MACROS) {
+        return -1;
+    }
+
+    macro = &opts->leader_macros[opts->leader_macro_count++];
+    snprintf(macro->sequence, sizeof(macro->sequence), "%s", sequence);
+
+    for (; *text != '\0' && len < sizeof(macro->text) - 1; text++) {
+        if (*text == '\\' && text[1] != '\0') {
+            text++;
+            macro->text[len++] = *text == 'n' ? '\n' : *text == 't' ? '\t' : *text;
+        } else {
+            macro->text[len++] = *text;
+        }
+    }
+    macro->text[len] = '\0';
+
+    return 0;
+}
+
+// the remap file for a host, as given on the command line or config file.
+// returns an empty string if there is none
+const char *host_remap(const options *opts, const char *host)
+{
+    for (int i = 0; i < opts->host_settings_count; i++) {
+        if (strcmp(opts->host_settings[i].name, host) == 0 &&
+                opts->host_settings[i].remap[0] != '\0') {
+            return opts->host_settings[i].remap;
+        }
+    }
+
+    return opts->remap;
+}
+
+// the keyboard layout of a host, from its host section or the one for all
+const char *host_layout(const options *opts, const char *host)
+{
+    for (int i = 0; i < opts->host_settings_count; i++) {
+        if (strcmp(opts->host_settings[i].name, host) == 0 &&
+                opts->host_settings[i].target_layout[0] != '\0') {
+            return opts->host_settings[i].target_layout;
+        }
+    }
+
+    return opts->target_layout;
+}
+
+// path of a file in the nethid config directory
+void config_file_path(const char *name, char *path, size_t len)
+{
+    const char *xdg = getenv("XDG_CONFIG_HOME");
+
+    if (xdg != NULL && *xdg) {
+        snprintf(path, len, "%s/nethid/%s", xdg, name);
+    } else {
+        snprintf(path, len, "%s/.config/nethid/%s", getenv("HOME"), name);
+    }
+}
+
+// apply the top level settings from the config file, and the settings of
+// [profile.name] if a profile is given. this understands just enough toml for
+// simple "key = value" lines.
+// returns -1 if the config can't be loaded
+int load_config(const char *path, const char *profile, int required, options *opts)
+{
+    char line[1024];
+    char section[256] = "";
+    char wanted[256];
+    host_settings *host = NULL;
+    int leader = 0;
+    int found = 0;
+    int hosts = 0;
+    int lineno = 0;
+    FILE *f;
+
+    if (profile != NULL) {
+        snprintf(wanted, sizeof(wanted), "profile.%s", profile);
+    }
+
+    f = fopen(path, "r");
+    if (f == NULL) {
+        // the default config file is optional
+        if (!required && profile == NULL) {
+            return 0;
+        }
+        printf("Could not open %s\n", path);
+        return -1;
+    }
+
+    while (fgets(line, sizeof(line), f) != NULL) {
+        char *key, *value, *s;
+
+        lineno++;
+
+        s = clean_line(line);
+        if (*s == '\0') {
+            continue;
+        }
+
+        if (*s == '[') {
+            char *end = strrchr(s, ']');
+            if (end == NULL) {
+                printf("%s:%d: invalid section\n", path, lineno);
+                fclose(f);
+                return -1;
+            }
+            *end = '\0';
+            snprintf(section, sizeof(section), "%s", trim(s + 1));
+            if (profile != NULL && strcmp(section, wanted) == 0) {
+                found = 1;
+                hosts = 0;
+            }
+            host = NULL;
+            leader = strcmp(section, "leader") == 0;
+            if (strncmp(section, "host.", 5) == 0) {
+                host = add_host_settings(opts, unquote(section + 5));
+                if (host == NULL) {
+                    printf("%s:%d: too many host sections\n", path, lineno);
+                    fclose(f);
+                    return -1;
+                }
+            }
+            continue;
+        }
+
+        // host sections apply whichever profile is used
+        if (host != NULL) {
+            if (split_line(s, &key, &value) < 0 ||
+                    (strcmp(key, "remap") != 0 && strcmp(key, "target_layout") != 0)) {
+                printf("%s:%d: expected remap = path or target_layout = name\n", path, lineno);
+                fclose(f);
+                return -1;
+            }
+            if (strcmp(key, "remap") == 0) {
+                snprintf(host->remap, sizeof(host->remap), "%s", value);
+            } else {
+                snprintf(host->target_layout, sizeof(host->target_layout), "%s", value);
+            }
+            continue;
+        }
+
+        // and so do leader key macros
+        if (leader) {
+            if (split_line(s, &key, &value) < 0) {
+                printf("%s:%d: expected keys = text\n", path, lineno);
+                fclose(f);
+                return -1;
+            }
+            if (add_leader_macro(opts, key, value) < 0) {
+                printf("%s:%d: too many leader macros\n", path, lineno);
+                fclose(f);
+                return -1;
+            }
+            continue;
+        }
+
+        // top level settings apply to all profiles
+        if (*section != '\0' && (profile == NULL || strcmp(section, wanted) != 0)) {
+            continue;
+        }
+
+        if (split_line(s, &key, &value) < 0) {
+            printf("%s:%d: expected key = value\n", path, lineno);
+            fclose(f);
+            return -1;
+        }
+
+        // hosts from the config replace the default one, and hosts from the
+        // profile replace those
+        if (strcmp(key, "host") == 0 && hosts++ == 0) {
+            opts->host_count = 0;
+        }
+
+        if (set_option(opts, key, value) < 0) {
+            printf("%s:%d: invalid setting %s\n", path, lineno, key);
+            fclose(f);
+            return -1;
+        }
+    }
+
+    fclose(f);
+
+    if (profile != NULL && !found) {
+        printf("Profile %s not found in %s\n", profile, path);
+        return -1;
+    }
+
+    return 0;
+}
+
+// create the directory of a file and its parent, if needed.
+// returns -1 if that fails
+int create_parent_dir(const char *path)
+{
+    char dir[1024];
+    char *slash;
+
+    snprintf(dir, sizeof(dir), "%s", path);
+    slash = strrchr(dir, '/');
+    if (slash == NULL) {
+        return 0;
+    }
+
+    *slash = '\0';
+    slash = strrchr(dir, '/');
+    if (slash != NULL) {
+        *slash = '\0';
+        mkdir(dir, 0700);
+        *slash = '/';
+    }
+
+    if (mkdir(dir, 0700) < 0 && errno != EEXIST) {
+        return -1;
+    }
+
+    return 0;
+}
+
+// remember the window size for next time, in a file load_config can read.
+// returns -1 if it can't be written
+int save_window_size(const char *path, int width, int height)
+{
+    FILE *f;
+
+    if (create_parent_dir(path) < 0) {
+        return -1;
+    }
+
+    f = fopen(path, "w");
+    if (f == NULL) {
+        return -1;
+    }
+
+    fprintf(f, "# written by hidgui on exit\n");
+    fprintf(f, "width = %d\n", width);
+    fprintf(f, "height = %d\n", height);
+    fclose(f);
+
+    return 0;
+}
+
+// local keys are sdl scancode names or numbers
+static int parse_scancode(const char *s)
+{
+    char *end;
+    long n = strtol(s, &end, 0);
+
+    if (*s != '\0' && *end == '\0') {
+        return n > 0 && n < SDL_NUM_SCANCODES ? (int) n : -1;
+    }
+
+    n = SDL_GetScancodeFromName(s);
+    return n != SDL_SCANCODE_UNKNOWN ? (int) n : -1;
+}
+
+// load a remap file with lines like:
+//
+//   CapsLock = "Escape"          # another key, by sdl name or hid usage id
+//   F12 = "consumer:0xe2"        # consumer control usage (mute)
+//   Insert = "none"              # not sent at all
+//
+// returns -1 if the file can't be loaded
+int load_remap(const char *path, remap_table *remap)
+{
+    char line[1024];
+    int lineno = 0;
+    FILE *f;
+
+    memset(remap, 0, sizeof(*remap));
+
+    f = fopen(path, "r");
+    if (f == NULL) {
+        printf("Could not open %s\n", path);
+        return -1;
+    }
+
+    while (fgets(line, sizeof(line), f) != NULL) {
+        char *key, *value, *s;
+        int from, to;
+
+        lineno++;
+
+        s = clean_line(line);
+        if (*s == '\0') {
+            continue;
+        }
+
+        if (split_line(s, &key, &value) < 0) {
+            printf("%s:%d: expected key = value\n", path, lineno);
+            fclose(f);
+            return -1;
+        }
+
+        from = parse_scancode(key);
+        if (from < 0) {
+            printf("%s:%d: unknown key %s\n", path, lineno, key);
+            fclose(f);
+            return -1;
+        }
+
+        if (strcmp(value, "none") == 0) {
+            remap->keys[from].type = REMAP_DISABLED;
+        } else if (strncmp(value, "consumer:", 9) == 0) {
+            remap->keys[from].type = REMAP_CONSUMER;
+            remap->keys[from].code = strtol(value + 9, NULL, 0);
+        } else if ((to = parse_scancode(value)) >= 0 && to <= 0xff) {
+            remap->keys[from].type = REMAP_KEY;
+            remap->keys[from].code = to;
+            if (to >= 224 && to <= 231) {
+                remap->modifiers |= 1 << (to - 224);
+            }
+        } else {
+            printf("%s:%d: unknown key %s\n", path, lineno, value);
+            fclose(f);
+            return -1;
+        }
+
+        if (from >= 224 && from <= 231) {
+            remap->modifiers |= 1 << (from - 224);
+        }
+    }
+
+    fclose(f);
+
+    return 0;
+}
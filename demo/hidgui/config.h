@@ -0,0 +1,121 @@
+#ifndef __CONFIG_H
+#define __CONFIG_H
+
+#include <stdint.h>
+#include <SDL.h>
+
+// defaults, can be changed from the command line or config file
+#define TARGET_IP "192.168.1.10"
+#define TARGET_PORT 4444
+#define WINDOW_WIDTH 640
+#define WINDOW_HEIGHT 480
+#define TYPE_DELAY_MS 30
+#define RELEASE_TIMEOUT 30
+#define TURBO_RATE 10
+#define REPEAT_RATE 25
+#define MOTION_INTERVAL_MS 4
+#define DISCOVER_TIMEOUT_MS 1000
+#define WAKE_WAIT 120
+
+#define MAX_TARGETS 9
+#define MAX_HOST_SETTINGS 16
+#define MAX_LEADER_MACROS 32
+
+// what a local key is sent as
+enum {
+    REMAP_NONE = 0, // sent as is
+    REMAP_KEY,
+    REMAP_CONSUMER,
+    REMAP_DISABLED,
+};
+
+typedef struct {
+    uint8_t type;
+    uint16_t code;
+} remap_entry;
+
+typedef struct {
+    remap_entry keys[SDL_NUM_SCANCODES];
+    uint8_t modifiers; // modifiers involved, these don't follow the local state
+} remap_table;
+
+// text typed when the leader key is followed by a sequence of keys, from
+// the [leader] section
+typedef struct {
+    char sequence[16];
+    char text[256];
+} leader_macro;
+
+// settings for one host, from a [host.NAME] section
+typedef struct {
+    char name[256];
+    char remap[1024];
+    char target_layout[32];
+} host_settings;
+
+typedef struct {
+    char hosts[MAX_TARGETS][256];
+    int host_count;
+    int default_host; // no hosts were given, only TARGET_IP is there
+    int port;
+    int ip_version; // 4 or 6 from -4 or -6, 0 for either
+    int stats;
+    int type_delay; // ms between characters when typing text
+    char target_layout[32]; // keyboard layout set up on the target, for typing text
+    char turbo_keys[256]; // sdl scancode names, separated with commas
+    int turbo_rate; // presses per second
+    int repeat_delay; // ms before a held key repeats, 0 to leave it to the target
+    int repeat_rate; // repeats per second
+    int sticky_keys; // tapped modifiers apply to the next key, tapped twice they stay on
+    int keep_awake; // seconds without input before nudging the mouse, 0 for never
+    int release_timeout; // seconds without input before held keys are released, 0 for never
+    double sensitivity; // mouse movement multiplier
+    int max_pps; // packets per second at most, 0 for no limit
+    int split_delay; // ms between the packets of movement too big for one, 0 to send them at once
+    int motion_interval; // ms to collect mouse movement before sending it, 0 to send every event
+    double scroll_speed; // wheel multiplier, below 1 for fast trackpads
+    int invert_scroll; // vertical, for natural scrolling
+    int invert_hscroll;
+    int hscroll; // horizontal scrolling is sent
+    int swap_scroll; // vertical and horizontal
+    int absolute; // the window is mapped onto the target screen, like a tablet
+    char target_screen[64]; // WxH+X+Y, the part of the desktop the window maps onto
+    char target_desktop[32]; // WxH, all screens of the target together
+    char backend[16]; // where input comes from, sdl, evdev, eventtap or portal
+    int full_capture; // system shortcuts and media keys go to the target too
+    char local_media_keys[256]; // sdl scancode names, not sent to the target
+    char shared_media_keys[256]; // sent, and left to this machine too
+    char lock_sync[16]; // lock keys differing from the target's: off, warn or fix
+    char wake_mac[32]; // of the target, for wake on lan, empty if not known
+    int wake; // wake the target up before starting
+    int wake_wait; // seconds to wait for the devices after that, 0 to not wait
+    int fullscreen;
+    int borderless;
+    int width;
+    int height;
+    char remap[1024];
+    char record[1024]; // from --record, empty if not recording
+    char audit[1024]; // log of everything sent, empty for none
+    int audit_keys; // the audit log shows which keys, not only how many
+    char grab_key[32]; // sdl scancode name, used with RCTRL
+    char leader_key[32]; // sdl scancode name, empty for none
+    char compose_key[32]; // sdl scancode name, empty for none
+    leader_macro leader_macros[MAX_LEADER_MACROS];
+    int leader_macro_count;
+    char profile[256]; // from --profile, empty if none
+    int calibrate_layout; // type a test on the target and ask what came out
+    host_settings host_settings[MAX_HOST_SETTINGS];
+    int host_settings_count;
+} options;
+
+void default_options(options *opts);
+int set_option(options *opts, const char *key, const char *value);
+void config_file_path(const char *name, char *path, size_t len);
+int load_config(const char *path, const char *profile, int required, options *opts);
+const char *host_remap(const options *opts, const char *host);
+const char *host_layout(const options *opts, const char *host);
+int create_parent_dir(const char *path);
+int save_window_size(const char *path, int width, int height);
+int load_remap(const char *path, remap_table *remap);
+
+#endif
@@ -0,0 +1,500 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <SDL.h>
+
+#include "evdev.h"
+
+uint32_t pen_event = 0;
+
+#ifdef __linux__
+
+#include <dirent.h>
+#include <fcntl.h>
+#include <poll.h>
+#include <unistd.h>
+#include <sys/ioctl.h>
+#include <linux/input.h>
+
+#define MAX_DEVICES 32
+
+// hid keyboard usage -> linux key code, same as hid_keyboard[] in the kernel.
+// sdl scancodes are hid usages, so this is turned around for the lookup
+static const uint8_t hid_to_linux[256] = {
+      0,  0,  0,  0, 30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38,
+     50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45, 21, 44,  2,  3,
+      4,  5,  6,  7,  8,  9, 10, 11, 28,  1, 14, 15, 57, 12, 13, 26,
+     27, 43, 43, 39, 40, 41, 51, 52, 53, 58, 59, 60, 61, 62, 63, 64,
+     65, 66, 67, 68, 87, 88, 99, 70,119,110,102,104,111,107,109,106,
+    105,108,103, 69, 98, 55, 74, 78, 96, 79, 80, 81, 75, 76, 77, 71,
+     72, 73, 82, 83, 86,127,116,117,183,184,185,186,187,188,189,190,
+    191,192,193,194,134,138,130,132,128,129,131,137,133,135,136,113,
+    115,114,  0,  0,  0,121,  0, 89, 93,124, 92, 94, 95,  0,  0,  0,
+    122,123, 90, 91, 85,  0,  0,  0,  0,  0,  0,  0,111,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,179,180,  0,  0,  0,  0,  0,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,  0,  0,111,  0,  0,  0,  0,  0,  0,  0,
+     29, 42, 56,125, 97, 54,100,126,164,166,165,163,161,115,114,113,
+    150,158,159,128,136,177,178,176,142,152,173,140,  0,  0,  0,  0,
+};
+
+static SDL_Scancode linux_to_scancode[256];
+static int keymap_ready = 0;
+
+// sdl modifier state, kept up to date from the key events
+static const struct {
+    int code;
+    int keymod;
+} modifier_keys[] = {
+    { KEY_LEFTCTRL, KMOD_LCTRL },
+    { KEY_LEFTSHIFT, KMOD_LSHIFT },
+    { KEY_LEFTALT, KMOD_LALT },
+    { KEY_LEFTMETA, KMOD_LGUI },
+    { KEY_RIGHTCTRL, KMOD_RCTRL },
+    { KEY_RIGHTSHIFT, KMOD_RSHIFT },
+    { KEY_RIGHTALT, KMOD_RALT },
+    { KEY_RIGHTMETA, KMOD_RGUI },
+};
+
+static int keymod = 0;
+
+typedef struct {
+    int fd;
+    int x; // motion since the last sync
+    int y;
+    int tablet; // has a pen, its position and pressure are absolute
+    struct input_absinfo abs[ABS_TILT_Y + 1];
+    pen_state pen;
+    int pen_changed; // since the last sync
+} evdev_device;
+
+static evdev_device devices[MAX_DEVICES];
+static int device_count = 0;
+
+#define BITS_PER_LONG (sizeof(long) * 8)
+#define NLONGS(n) (((n) + BITS_PER_LONG - 1) / BITS_PER_LONG)
+
+static int test_bit(const unsigned long *bits, int bit)
+{
+    return (bits[bit / BITS_PER_LONG] >> (bit % BITS_PER_LONG)) & 1;
+}
+
+// keyboards, mice and drawing tablets, not e.g. power buttons or sensors
+static int is_input_device(int fd)
+{
+    unsigned long types[NLONGS(EV_MAX + 1)];
+    unsigned long keys[NLONGS(KEY_MAX + 1)];
+    unsigned long rel[NLONGS(REL_MAX + 1)];
+
+    memset(types, 0, sizeof(types));
+    memset(keys, 0, sizeof(keys));
+    memset(rel, 0, sizeof(rel));
+
+    if (ioctl(fd, EVIOCGBIT(0, sizeof(types)), types) < 0) {
+        return 0;
+    }
+
+    if (test_bit(types, EV_KEY)) {
+        ioctl(fd, EVIOCGBIT(EV_KEY, sizeof(keys)), keys);
+        if (test_bit(keys, KEY_A) || test_bit(keys, BTN_LEFT) ||
+                test_bit(keys, BTN_TOOL_PEN)) {
+            return 1;
+        }
+    }
+
+    if (test_bit(types, EV_REL)) {
+        ioctl(fd, EVIOCGBIT(EV_REL, sizeof(rel)), rel);
+        if (test_bit(rel, REL_X)) {
+            return 1;
+        }
+    }
+
+    return 0;
+}
+
+// the ranges of a tablet's axes, which differ from one tablet to another
+static void open_tablet(evdev_device *device)
+{
+    unsigned long keys[NLONGS(KEY_MAX + 1)];
+    static const int axes[] = { ABS_X, ABS_Y, ABS_PRESSURE, ABS_TILT_X, ABS_TILT_Y };
+
+    memset(keys, 0, sizeof(keys));
+    ioctl(device->fd, EVIOCGBIT(EV_KEY, sizeof(keys)), keys);
+    if (!test_bit(keys, BTN_TOOL_PEN)) {
+        return;
+    }
+
+    device->tablet = 1;
+    for (size_t i = 0; i < sizeof(axes) / sizeof(axes[0]); i++) {
+        ioctl(device->fd, EVIOCGABS(axes[i]), &device->abs[axes[i]]);
+    }
+}
+
+// open every keyboard and mouse there is, returns -1 if none were found
+int evdev_open(void)
+{
+    DIR *dir;
+    struct dirent *entry;
+
+    dir = opendir("/dev/input");
+    if (dir == NULL) {
+        printf("Could not open /dev/input\n");
+        return -1;
+    }
+
+    while ((entry = readdir(dir)) != NULL && device_count < MAX_DEVICES) {
+        char path[512];
+        char name[256] = "";
+        int fd;
+
+        if (strncmp(entry->d_name, "event", 5) != 0) {
+            continue;
+        }
+
+        snprintf(path, sizeof(path), "/dev/input/%s", entry->d_name);
+        fd = open(path, O_RDONLY | O_NONBLOCK);
+        if (fd < 0) {
+            continue;
+        }
+
+        if (!is_input_device(fd)) {
+            close(fd);
+            continue;
+        }
+
+        ioctl(fd, EVIOCGNAME(sizeof(name)), name);
+        printf("Reading %s (%s)\n", path, name);
+
+        memset(&devices[device_count], 0, sizeof(devices[0]));
+        devices[device_count].fd = fd;
+        open_tablet(&devices[device_count]);
+        device_count++;
+    }
+
+    closedir(dir);
+
+    if (device_count == 0) {
+        printf("No keyboards or mice found in /dev/input, is it readable?\n");
+        return -1;
+    }
+
+    return 0;
+}
+
+// while grabbed, nothing else on this machine sees the input
+void evdev_grab(int grab)
+{
+    for (int i = 0; i < device_count; i++) {
+        ioctl(devices[i].fd, EVIOCGRAB, grab ? 1 : 0);
+    }
+}
+
+// also used for the portal backend, which gets linux key codes too
+void evdev_push_key(int code, int value)
+{
+    SDL_Event event;
+
+    if (!keymap_ready) {
+        for (int i = 255; i > 0; i--) {
+            if (hid_to_linux[i]) {
+                linux_to_scancode[hid_to_linux[i]] = i;
+            }
+        }
+        keymap_ready = 1;
+    }
+
+    if (code < 0 || code > 255 || linux_to_scancode[code] == SDL_SCANCODE_UNKNOWN) {
+        return;
+    }
+
+    for (size_t i = 0; i < sizeof(modifier_keys) / sizeof(modifier_keys[0]); i++) {
+        if (modifier_keys[i].code == code) {
+            if (value) {
+                keymod |= modifier_keys[i].keymod;
+            } else {
+                keymod &= ~modifier_keys[i].keymod;
+            }
+        }
+    }
+
+    memset(&event, 0, sizeof(event));
+    event.type = value ? SDL_KEYDOWN : SDL_KEYUP;
+    event.key.state = value ? SDL_PRESSED : SDL_RELEASED;
+    event.key.repeat = value == 2;
+    event.key.keysym.scancode = linux_to_scancode[code];
+    event.key.keysym.mod = keymod;
+    SDL_PushEvent(&event);
+}
+
+void evdev_push_button(int code, int value)
+{
+    SDL_Event event;
+    int button;
+
+    switch (code) {
+        case BTN_LEFT:
+            button = SDL_BUTTON_LEFT;
+            break;
+        case BTN_RIGHT:
+            button = SDL_BUTTON_RIGHT;
+            break;
+        case BTN_MIDDLE:
+            button = SDL_BUTTON_MIDDLE;
+            break;
+        case BTN_SIDE:
+            button = SDL_BUTTON_X1;
+            break;
+        case BTN_EXTRA:
+            button = SDL_BUTTON_X2;
+            break;
+        default:
+            return;
+    }
+
+    memset(&event, 0, sizeof(event));
+    event.type = value ? SDL_MOUSEBUTTONDOWN : SDL_MOUSEBUTTONUP;
+    event.button.state = value ? SDL_PRESSED : SDL_RELEASED;
+    event.button.button = button;
+    SDL_PushEvent(&event);
+}
+
+static void push_wheel(int x, int y)
+{
+    SDL_Event event;
+
+    memset(&event, 0, sizeof(event));
+    event.type = SDL_MOUSEWHEEL;
+    event.wheel.x = x;
+    event.wheel.y = y;
+    event.wheel.direction = SDL_MOUSEWHEEL_NORMAL;
+    SDL_PushEvent(&event);
+}
+
+// motion is collected until the device says the report is complete
+static void push_motion(evdev_device *device)
+{
+    SDL_Event event;
+
+    if (device->x == 0 && device->y == 0) {
+        return;
+    }
+
+    memset(&event, 0, sizeof(event));
+    event.type = SDL_MOUSEMOTION;
+    event.motion.xrel = device->x;
+    event.motion.yrel = device->y;
+    SDL_PushEvent(&event);
+
+    device->x = 0;
+    device->y = 0;
+}
+
+// where an axis is, from 0 to 1
+static float abs_fraction(const struct input_absinfo *abs, int value)
+{
+    int range = abs->maximum - abs->minimum;
+
+    return range > 0 ? (float) (value - abs->minimum) / range : 0;
+}
+
+// tilt is in units per radian if the resolution is given, degrees if not
+static int abs_degrees(const struct input_absinfo *abs, int value)
+{
+    return abs->resolution > 0 ? (int) (value * 57.29578f / abs->resolution) : value;
+}
+
+static void pen_key(evdev_device *device, int code, int value)
+{
+    uint8_t flag;
+
+    switch (code) {
+        case BTN_TOUCH:
+            flag = 0x01;
+            break;
+        case BTN_STYLUS:
+            flag = 0x02;
+            break;
+        case BTN_TOOL_RUBBER:
+            // the eraser end is in range
+            flag = 0x04 | 0x08;
+            break;
+        case BTN_TOOL_PEN:
+            flag = 0x08;
+            break;
+        default:
+            return;
+    }
+
+    if (value) {
+        device->pen.flags |= flag;
+    } else {
+        device->pen.flags &= ~flag;
+    }
+    device->pen_changed = 1;
+}
+
+static void pen_axis(evdev_device *device, int code, int value)
+{
+    switch (code) {
+        case ABS_X:
+            device->pen.x = abs_fraction(&device->abs[code], value);
+            break;
+        case ABS_Y:
+            device->pen.y = abs_fraction(&device->abs[code], value);
+            break;
+        case ABS_PRESSURE:
+            device->pen.pressure = abs_fraction(&device->abs[code], value);
+            break;
+        case ABS_TILT_X:
+            device->pen.tilt_x = abs_degrees(&device->abs[code], value);
+            break;
+        case ABS_TILT_Y:
+            device->pen.tilt_y = abs_degrees(&device->abs[code], value);
+            break;
+        default:
+            return;
+    }
+    device->pen_changed = 1;
+}
+
+static void push_pen(evdev_device *device)
+{
+    SDL_Event event;
+    pen_state *pen;
+
+    if (!device->pen_changed || pen_event == 0) {
+        return;
+    }
+
+    pen = malloc(sizeof(*pen));
+    if (pen == NULL) {
+        return;
+    }
+    *pen = device->pen;
+
+    memset(&event, 0, sizeof(event));
+    event.type = pen_event;
+    event.user.data1 = pen;
+    SDL_PushEvent(&event);
+
+    device->pen_changed = 0;
+}
+
+static void handle_event(evdev_device *device, const struct input_event *ev)
+{
+    switch (ev->type) {
+        case EV_KEY:
+            if (device->tablet && ev->code >= BTN_DIGI && ev->code < BTN_WHEEL) {
+                pen_key(device, ev->code, ev->value);
+            } else if (ev->code >= BTN_MOUSE && ev->code < BTN_JOYSTICK) {
+                evdev_push_button(ev->code, ev->value);
+            } else {
+                evdev_push_key(ev->code, ev->value);
+            }
+            break;
+        case EV_REL:
+            switch (ev->code) {
+                case REL_X:
+                    device->x += ev->value;
+                    break;
+                case REL_Y:
+                    device->y += ev->value;
+                    break;
+                case REL_WHEEL:
+                    push_wheel(0, ev->value);
+                    break;
+                case REL_HWHEEL:
+                    push_wheel(ev->value, 0);
+                    break;
+            }
+            break;
+        case EV_ABS:
+            if (device->tablet) {
+                pen_axis(device, ev->code, ev->value);
+            }
+            break;
+        case EV_SYN:
+            if (ev->code == SYN_REPORT) {
+                push_motion(device);
+                push_pen(device);
+            }
+            break;
+    }
+}
+
+static int read_devices(void *data)
+{
+    struct pollfd fds[MAX_DEVICES];
+
+    (void) data;
+
+    for (int i = 0; i < device_count; i++) {
+        fds[i].fd = devices[i].fd;
+        fds[i].events = POLLIN;
+    }
+
+    for (;;) {
+        if (poll(fds, device_count, -1) < 0) {
+            continue;
+        }
+
+        for (int i = 0; i < device_count; i++) {
+            struct input_event ev[64];
+            ssize_t len;
+
+            // unplugged
+            if (fds[i].revents & (POLLERR | POLLHUP | POLLNVAL)) {
+                fds[i].fd = -1;
+                continue;
+            }
+
+            if (!(fds[i].revents & POLLIN)) {
+                continue;
+            }
+
+            while ((len = read(devices[i].fd, ev, sizeof(ev))) > 0) {
+                for (size_t j = 0; j < len / sizeof(ev[0]); j++) {
+                    handle_event(&devices[i], &ev[j]);
+                }
+            }
+        }
+    }
+
+    return 0;
+}
+
+int evdev_start(void)
+{
+    pen_event = SDL_RegisterEvents(1);
+    if (pen_event == (uint32_t) -1) {
+        pen_event = 0;
+    }
+
+    if (SDL_CreateThread(read_devices, "evdev", NULL) == NULL) {
+        printf("Could not start reading input devices: %s\n", SDL_GetError());
+        return -1;
+    }
+
+    return 0;
+}
+
+#else
+
+int evdev_open(void)
+{
+    printf("The evdev backend only works on linux\n");
+    return -1;
+}
+
+int evdev_start(void)
+{
+    return -1;
+}
+
+void evdev_grab(int grab)
+{
+    (void) grab;
+}
+
+#endif
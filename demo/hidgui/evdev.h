@@ -0,0 +1,31 @@
+#ifndef __EVDEV_H
+#define __EVDEV_H
+
+// input read straight from /dev/input, turned into sdl events, so hidgui can
+// run without a window system, e.g. on a headless box or a text console
+
+#include <stdint.h>
+
+// a drawing tablet's pen. sdl has no events for these, so they come as
+// user events of type pen_event, with a pen_state to free in data1
+typedef struct {
+    uint8_t flags; // 0x01 tip, 0x02 barrel button, 0x04 eraser, 0x08 in range
+    float x; // 0 - 1 across the tablet
+    float y;
+    float pressure; // 0 - 1
+    int tilt_x; // degrees
+    int tilt_y;
+} pen_state;
+
+extern uint32_t pen_event; // 0 until the backend has started
+
+int evdev_open(void);
+int evdev_start(void);
+void evdev_grab(int grab);
+
+#ifdef __linux__
+void evdev_push_key(int code, int value);
+void evdev_push_button(int code, int value);
+#endif
+
+#endif
@@ -0,0 +1,38 @@
+#ifndef __EVENTTAP_H
+#define __EVENTTAP_H
+
+// input captured system wide with a quartz event tap on macos, turned into
+// sdl events. unlike the sdl window this also sees cmd shortcuts and media
+// keys, but needs the accessibility permission
+
+#include <stdint.h>
+
+#ifdef __APPLE__
+
+int eventtap_start(void);
+void eventtap_grab(int grab);
+void eventtap_passthrough(const uint8_t *keys);
+
+#else
+
+#include <stdio.h>
+
+static inline int eventtap_start(void)
+{
+    printf("The eventtap backend only works on macos\n");
+    return -1;
+}
+
+static inline void eventtap_grab(int grab)
+{
+    (void) grab;
+}
+
+static inline void eventtap_passthrough(const uint8_t *keys)
+{
+    (void) keys;
+}
+
+#endif
+
+#endif
@@ -0,0 +1,341 @@
+#include <stdio.h>
+#include <string.h>
+#include <ApplicationServices/ApplicationServices.h>
+#import <AppKit/AppKit.h>
+#include <SDL.h>
+
+#include "eventtap.h"
+
+// macos virtual key code -> sdl scancode (hid usage), as in sdl's
+// darwin_scancode_table
+static const SDL_Scancode keycode_to_scancode[128] = {
+    SDL_SCANCODE_A, SDL_SCANCODE_S, SDL_SCANCODE_D, SDL_SCANCODE_F,
+    SDL_SCANCODE_H, SDL_SCANCODE_G, SDL_SCANCODE_Z, SDL_SCANCODE_X,
+    SDL_SCANCODE_C, SDL_SCANCODE_V, SDL_SCANCODE_NONUSBACKSLASH, SDL_SCANCODE_B,
+    SDL_SCANCODE_Q, SDL_SCANCODE_W, SDL_SCANCODE_E, SDL_SCANCODE_R,
+    SDL_SCANCODE_Y, SDL_SCANCODE_T, SDL_SCANCODE_1, SDL_SCANCODE_2,
+    SDL_SCANCODE_3, SDL_SCANCODE_4, SDL_SCANCODE_6, SDL_SCANCODE_5,
+    SDL_SCANCODE_EQUALS, SDL_SCANCODE_9, SDL_SCANCODE_7, SDL_SCANCODE_MINUS,
+    SDL_SCANCODE_8, SDL_SCANCODE_0, SDL_SCANCODE_RIGHTBRACKET, SDL_SCANCODE_O,
+    SDL_SCANCODE_U, SDL_SCANCODE_LEFTBRACKET, SDL_SCANCODE_I, SDL_SCANCODE_P,
+    SDL_SCANCODE_RETURN, SDL_SCANCODE_L, SDL_SCANCODE_J, SDL_SCANCODE_APOSTROPHE,
+    SDL_SCANCODE_K, SDL_SCANCODE_SEMICOLON, SDL_SCANCODE_BACKSLASH, SDL_SCANCODE_COMMA,
+    SDL_SCANCODE_SLASH, SDL_SCANCODE_N, SDL_SCANCODE_M, SDL_SCANCODE_PERIOD,
+    SDL_SCANCODE_TAB, SDL_SCANCODE_SPACE, SDL_SCANCODE_GRAVE, SDL_SCANCODE_BACKSPACE,
+    SDL_SCANCODE_KP_ENTER, SDL_SCANCODE_ESCAPE, SDL_SCANCODE_RGUI, SDL_SCANCODE_LGUI,
+    SDL_SCANCODE_LSHIFT, SDL_SCANCODE_CAPSLOCK, SDL_SCANCODE_LALT, SDL_SCANCODE_LCTRL,
+    SDL_SCANCODE_RSHIFT, SDL_SCANCODE_RALT, SDL_SCANCODE_RCTRL, SDL_SCANCODE_UNKNOWN, // fn
+    SDL_SCANCODE_F17, SDL_SCANCODE_KP_PERIOD, SDL_SCANCODE_UNKNOWN, SDL_SCANCODE_KP_MULTIPLY,
+    SDL_SCANCODE_UNKNOWN, SDL_SCANCODE_KP_PLUS, SDL_SCANCODE_UNKNOWN, SDL_SCANCODE_NUMLOCKCLEAR,
+    SDL_SCANCODE_VOLUMEUP, SDL_SCANCODE_VOLUMEDOWN, SDL_SCANCODE_MUTE, SDL_SCANCODE_KP_DIVIDE,
+    SDL_SCANCODE_KP_ENTER, SDL_SCANCODE_UNKNOWN, SDL_SCANCODE_KP_MINUS, SDL_SCANCODE_F18,
+    SDL_SCANCODE_F19, SDL_SCANCODE_KP_EQUALS, SDL_SCANCODE_KP_0, SDL_SCANCODE_KP_1,
+    SDL_SCANCODE_KP_2, SDL_SCANCODE_KP_3, SDL_SCANCODE_KP_4, SDL_SCANCODE_KP_5,
+    SDL_SCANCODE_KP_6, SDL_SCANCODE_KP_7, SDL_SCANCODE_F20, SDL_SCANCODE_KP_8,
+    SDL_SCANCODE_KP_9, SDL_SCANCODE_INTERNATIONAL3, SDL_SCANCODE_INTERNATIONAL1, SDL_SCANCODE_KP_COMMA,
+    SDL_SCANCODE_F5, SDL_SCANCODE_F6, SDL_SCANCODE_F7, SDL_SCANCODE_F3,
+    SDL_SCANCODE_F8, SDL_SCANCODE_F9, SDL_SCANCODE_LANG2, SDL_SCANCODE_F11,
+    SDL_SCANCODE_LANG1, SDL_SCANCODE_F13, SDL_SCANCODE_F16, SDL_SCANCODE_F14,
+    SDL_SCANCODE_UNKNOWN, SDL_SCANCODE_F10, SDL_SCANCODE_APPLICATION, SDL_SCANCODE_F12,
+    SDL_SCANCODE_UNKNOWN, SDL_SCANCODE_F15, SDL_SCANCODE_INSERT, SDL_SCANCODE_HOME,
+    SDL_SCANCODE_PAGEUP, SDL_SCANCODE_DELETE, SDL_SCANCODE_F4, SDL_SCANCODE_END,
+    SDL_SCANCODE_F2, SDL_SCANCODE_PAGEDOWN, SDL_SCANCODE_F1, SDL_SCANCODE_LEFT,
+    SDL_SCANCODE_RIGHT, SDL_SCANCODE_DOWN, SDL_SCANCODE_UP, SDL_SCANCODE_POWER,
+};
+
+// modifier keys only get flags changed events, the device dependent bits
+// tell which side is down
+static const struct {
+    CGKeyCode keycode;
+    CGEventFlags mask;
+    int keymod;
+} modifier_keys[] = {
+    { 59, 0x00000001, KMOD_LCTRL },
+    { 56, 0x00000002, KMOD_LSHIFT },
+    { 60, 0x00000004, KMOD_RSHIFT },
+    { 55, 0x00000008, KMOD_LGUI },
+    { 54, 0x00000010, KMOD_RGUI },
+    { 58, 0x00000020, KMOD_LALT },
+    { 61, 0x00000040, KMOD_RALT },
+    { 62, 0x00002000, KMOD_RCTRL },
+};
+
+// media keys arrive as system defined events, NX_KEYTYPE_* -> sdl scancode
+static const struct {
+    int key;
+    SDL_Scancode scancode;
+} media_keys[] = {
+    { 0, SDL_SCANCODE_VOLUMEUP },
+    { 1, SDL_SCANCODE_VOLUMEDOWN },
+    { 7, SDL_SCANCODE_MUTE },
+    { 16, SDL_SCANCODE_AUDIOPLAY },
+    { 17, SDL_SCANCODE_AUDIONEXT },
+    { 18, SDL_SCANCODE_AUDIOPREV },
+    { 19, SDL_SCANCODE_AUDIOFASTFORWARD },
+    { 20, SDL_SCANCODE_AUDIOREWIND },
+};
+
+#define NX_SYSDEFINED 14
+#define NX_SUBTYPE_AUX_CONTROL_BUTTONS 8
+
+static CFMachPortRef tap = NULL;
+static int keymod = 0;
+static volatile int grabbed = 0;
+static const uint8_t *passthrough = NULL;
+
+static void push_key(SDL_Scancode scancode, int pressed, int repeat)
+{
+    SDL_Event event;
+
+    if (scancode == SDL_SCANCODE_UNKNOWN) {
+        return;
+    }
+
+    memset(&event, 0, sizeof(event));
+    event.type = pressed ? SDL_KEYDOWN : SDL_KEYUP;
+    event.key.state = pressed ? SDL_PRESSED : SDL_RELEASED;
+    event.key.repeat = repeat;
+    event.key.keysym.scancode = scancode;
+    event.key.keysym.mod = keymod;
+    SDL_PushEvent(&event);
+}
+
+static void push_button(int button, int pressed)
+{
+    SDL_Event event;
+
+    memset(&event, 0, sizeof(event));
+    event.type = pressed ? SDL_MOUSEBUTTONDOWN : SDL_MOUSEBUTTONUP;
+    event.button.state = pressed ? SDL_PRESSED : SDL_RELEASED;
+    event.button.button = button;
+    SDL_PushEvent(&event);
+}
+
+// quartz numbers buttons from 0, with 2 for middle, then back and forward
+static int sdl_button(CGEventRef ev)
+{
+    switch (CGEventGetIntegerValueField(ev, kCGMouseEventButtonNumber)) {
+        case 0:
+            return SDL_BUTTON_LEFT;
+        case 1:
+            return SDL_BUTTON_RIGHT;
+        case 2:
+            return SDL_BUTTON_MIDDLE;
+        case 3:
+            return SDL_BUTTON_X1;
+        case 4:
+            return SDL_BUTTON_X2;
+    }
+    return 0;
+}
+
+static void flags_changed(CGEventRef ev)
+{
+    CGKeyCode keycode = CGEventGetIntegerValueField(ev, kCGKeyboardEventKeycode);
+    CGEventFlags flags = CGEventGetFlags(ev);
+
+    // caps lock reports the lock state, not the key, so every change is a
+    // press and a release
+    if (keycode == 57) {
+        push_key(SDL_SCANCODE_CAPSLOCK, 1, 0);
+        push_key(SDL_SCANCODE_CAPSLOCK, 0, 0);
+        return;
+    }
+
+    for (size_t i = 0; i < sizeof(modifier_keys) / sizeof(modifier_keys[0]); i++) {
+        if (modifier_keys[i].keycode == keycode) {
+            int pressed = (flags & modifier_keys[i].mask) != 0;
+            if (pressed) {
+                keymod |= modifier_keys[i].keymod;
+            } else {
+                keymod &= ~modifier_keys[i].keymod;
+            }
+            push_key(keycode_to_scancode[keycode], pressed, 0);
+            return;
+        }
+    }
+}
+
+// keys this machine should see too, even while grabbed
+static int passed(SDL_Scancode scancode)
+{
+    return passthrough != NULL && passthrough[scancode];
+}
+
+// returns 1 if this machine should see the key too
+static int system_defined(CGEventRef ev)
+{
+    NSEvent *event = [NSEvent eventWithCGEvent:ev];
+    int pass = 0;
+
+    if ([event subtype] != NX_SUBTYPE_AUX_CONTROL_BUTTONS) {
+        return 0;
+    }
+
+    int key = ([event data1] & 0xffff0000) >> 16;
+    int pressed = (([event data1] & 0xff00) >> 8) == 0x0a;
+    int repeat = [event data1] & 0x1;
+
+    for (size_t i = 0; i < sizeof(media_keys) / sizeof(media_keys[0]); i++) {
+        if (media_keys[i].key == key) {
+            push_key(media_keys[i].scancode, pressed, repeat);
+            pass = passed(media_keys[i].scancode);
+        }
+    }
+
+    return pass;
+}
+
+static CGEventRef tap_callback(CGEventTapProxy proxy, CGEventType type, CGEventRef ev, void *data)
+{
+    SDL_Event event;
+
+    (void) proxy;
+    (void) data;
+
+    switch ((int) type) {
+        // macos turns taps off that take too long, or on user input
+        // while secure input is on
+        case kCGEventTapDisabledByTimeout:
+        case kCGEventTapDisabledByUserInput:
+            CGEventTapEnable(tap, true);
+            return ev;
+
+        case kCGEventKeyDown:
+        case kCGEventKeyUp: {
+            CGKeyCode keycode = CGEventGetIntegerValueField(ev, kCGKeyboardEventKeycode);
+            if (keycode < 128) {
+                push_key(keycode_to_scancode[keycode], type == kCGEventKeyDown,
+                    CGEventGetIntegerValueField(ev, kCGKeyboardEventAutorepeat) != 0);
+                if (passed(keycode_to_scancode[keycode])) {
+                    return ev;
+                }
+            }
+            break;
+        }
+
+        case kCGEventFlagsChanged:
+            flags_changed(ev);
+            break;
+
+        case NX_SYSDEFINED:
+            if (system_defined(ev)) {
+                return ev;
+            }
+            break;
+
+        case kCGEventLeftMouseDown:
+        case kCGEventRightMouseDown:
+        case kCGEventOtherMouseDown:
+        case kCGEventLeftMouseUp:
+        case kCGEventRightMouseUp:
+        case kCGEventOtherMouseUp:
+            if (sdl_button(ev)) {
+                push_button(sdl_button(ev),
+                    type == kCGEventLeftMouseDown ||
+                    type == kCGEventRightMouseDown ||
+                    type == kCGEventOtherMouseDown);
+            }
+            break;
+
+        case kCGEventMouseMoved:
+        case kCGEventLeftMouseDragged:
+        case kCGEventRightMouseDragged:
+        case kCGEventOtherMouseDragged:
+            memset(&event, 0, sizeof(event));
+            event.type = SDL_MOUSEMOTION;
+            event.motion.xrel = CGEventGetIntegerValueField(ev, kCGMouseEventDeltaX);
+            event.motion.yrel = CGEventGetIntegerValueField(ev, kCGMouseEventDeltaY);
+            if (event.motion.xrel || event.motion.yrel) {
+                SDL_PushEvent(&event);
+            }
+            break;
+
+        case kCGEventScrollWheel:
+            memset(&event, 0, sizeof(event));
+            event.type = SDL_MOUSEWHEEL;
+            event.wheel.y = CGEventGetIntegerValueField(ev, kCGScrollWheelEventDeltaAxis1);
+            // quartz scrolls left with positive values
+            event.wheel.x = -CGEventGetIntegerValueField(ev, kCGScrollWheelEventDeltaAxis2);
+            event.wheel.direction = SDL_MOUSEWHEEL_NORMAL;
+            if (event.wheel.x || event.wheel.y) {
+                SDL_PushEvent(&event);
+            }
+            break;
+
+        default:
+            return ev;
+    }
+
+    // while grabbed, nothing else on this machine sees the input
+    return grabbed ? NULL : ev;
+}
+
+static int run_tap(void *data)
+{
+    CFRunLoopSourceRef source;
+
+    (void) data;
+
+    source = CFMachPortCreateRunLoopSource(kCFAllocatorDefault, tap, 0);
+    CFRunLoopAddSource(CFRunLoopGetCurrent(), source, kCFRunLoopCommonModes);
+    CGEventTapEnable(tap, true);
+    CFRunLoopRun();
+
+    return 0;
+}
+
+int eventtap_start(void)
+{
+    CGEventMask mask =
+        CGEventMaskBit(kCGEventKeyDown) |
+        CGEventMaskBit(kCGEventKeyUp) |
+        CGEventMaskBit(kCGEventFlagsChanged) |
+        CGEventMaskBit(NX_SYSDEFINED) |
+        CGEventMaskBit(kCGEventLeftMouseDown) |
+        CGEventMaskBit(kCGEventLeftMouseUp) |
+        CGEventMaskBit(kCGEventRightMouseDown) |
+        CGEventMaskBit(kCGEventRightMouseUp) |
+        CGEventMaskBit(kCGEventOtherMouseDown) |
+        CGEventMaskBit(kCGEventOtherMouseUp) |
+        CGEventMaskBit(kCGEventMouseMoved) |
+        CGEventMaskBit(kCGEventLeftMouseDragged) |
+        CGEventMaskBit(kCGEventRightMouseDragged) |
+        CGEventMaskBit(kCGEventOtherMouseDragged) |
+        CGEventMaskBit(kCGEventScrollWheel);
+
+    tap = CGEventTapCreate(
+        kCGSessionEventTap,
+        kCGHeadInsertEventTap,
+        kCGEventTapOptionDefault,
+        mask,
+        tap_callback,
+        NULL);
+
+    if (tap == NULL) {
+        printf("Could not create an event tap, allow the terminal (or hidgui) in\n");
+        printf("System Settings > Privacy & Security > Accessibility\n");
+        return -1;
+    }
+
+    if (SDL_CreateThread(run_tap, "eventtap", NULL) == NULL) {
+        printf("Could not start the event tap: %s\n", SDL_GetError());
+        return -1;
+    }
+
+    return 0;
+}
+
+// media keys to let through, by sdl scancode
+void eventtap_passthrough(const uint8_t *keys)
+{
+    passthrough = keys;
+}
+
+void eventtap_grab(int grab)
+{
+    grabbed = grab;
+
+    // keep the cursor still while the motion goes to the target
+    CGAssociateMouseAndMouseCursorPosition(grab ? false : true);
+}
@@ -1,157 +1,3709 @@
 #include <stdio.h>
 #include <stdint.h>
+#include <stdlib.h>
 #include <string.h>
+#include <ctype.h>
 #include <unistd.h>
+#include <getopt.h>
+#include <netdb.h>
+#include <time.h>
+#include <sys/time.h>
+#include <sys/select.h>
 #include <sys/socket.h>
 #include <netinet/in.h>
 #include <arpa/inet.h>
 #include <SDL.h>
 
-#define TARGET_IP "192.168.1.10"
-#define TARGET_PORT 4444
+#include "config.h"
+#include "typing.h"
+#include "evdev.h"
+#include "eventtap.h"
+#include "portal.h"
 
 #define INHIBIT_SHORTCUTS 1
 
 typedef struct {
     uint8_t type;
     uint8_t version;
-    uint8_t pressed;
     uint8_t modifiers;
-    uint8_t scancode;
-} keypress_packet;
+    uint8_t keys[6];
+} keyboard_report_packet;
+
+// keys currently held down, tracked client-side
+typedef struct {
+    uint8_t modifiers;
+    uint8_t keys[6];
+    uint8_t latched; // sticky modifiers for the next key
+    uint8_t locked; // sticky modifiers until tapped again
+    uint8_t tapping; // modifier pressed with no other keys since
+} keyboard_state;
 
 typedef struct {
     uint8_t type;
     uint8_t version;
-    uint8_t buttons;
+    uint8_t buttons; // 0x01 left, 0x02 right, 0x04 middle, 0x08 back, 0x10 forward
     int8_t x;
     int8_t y;
     int8_t vertical;
     int8_t horizontal;
 } mouse_packet;
 
-void send_keyboard(uint8_t pressed, uint8_t scancode)
-{
-    int sockfd;
-    struct sockaddr_in servaddr;
+// the pointer put at a position instead of moved, for tablets and such
+typedef struct {
+    uint8_t type;
+    uint8_t version;
+    uint8_t buttons;
+    uint8_t x[2]; // little endian, 0 - 32767 across the whole desktop
+    uint8_t y[2];
+    int8_t vertical;
+    int8_t horizontal;
+} absolute_mouse_packet;
+
+typedef struct {
+    uint8_t type;
+    uint8_t version;
+    uint8_t flags; // 0x01 tip, 0x02 barrel button, 0x04 eraser, 0x08 in range
+    uint8_t x[2]; // little endian, 0 - 32767 across the whole desktop
+    uint8_t y[2];
+    uint8_t pressure[2]; // little endian, 0 - 4095
+    int8_t tilt_x; // degrees, -90 - 90
+    int8_t tilt_y;
+} pen_packet;
+
+typedef struct {
+    uint8_t type;
+    uint8_t version;
+    uint8_t usage[2]; // little endian, 0 == released
+} consumer_packet;
+
+typedef struct {
+    uint8_t type;
+    uint8_t version;
+    uint8_t control; // 1 == power down, 2 == sleep, 3 == wake up, 0 == released
+} system_control_packet;
+
+// sent back to us by the device when the keyboard leds change
+typedef struct {
+    uint8_t type;
+    uint8_t version;
+    uint8_t leds; // 0x01 == num lock, 0x02 == caps lock, 0x04 == scroll lock
+} leds_packet;
+
+// keepalive, the device answers with a pong carrying the same id
+typedef struct {
+    uint8_t type;
+    uint8_t version;
+    uint8_t id[4];
+} ping_packet;
+
+#define KEEPALIVE_INTERVAL_MS 1000
+#define KEEPALIVE_TIMEOUT_MS 3000
+
+// how often to look up the address again while a device isn't answering,
+// in case it got a new one
+#define RESOLVE_INTERVAL_MS 10000
+
+// what we know about the device, from packets it has sent us
+typedef struct {
+    uint8_t leds;
+    int connected;
+    Uint32 last_ping; // when we last sent a keepalive
+    Uint32 last_pong; // when the device last answered one
+    Uint32 last_resolve; // when the address was last looked up
+    uint32_t ping_id;
+    struct timeval ping_sent; // for round trip times
+    double rtt_min;
+    double rtt_max;
+    double rtt_sum;
+    Uint32 rtt_count;
+    int leds_known; // the device has told us its leds
+    Uint32 locks_differ_since; // the lock keys stopped matching ours, 0 if they match
+    uint8_t locks_differ; // leds of the lock keys that don't match, once warned about
+    uint8_t locks_fixed; // lock keys already toggled to match, not tried again
+} device_state;
+
+// sdl scancodes that are sent as consumer control (media keys)
+// instead of keyboard keys
+static const struct {
+    SDL_Scancode scancode;
+    uint16_t usage;
+} consumer_keymap[] = {
+    { SDL_SCANCODE_MUTE, 0x00e2 },
+    { SDL_SCANCODE_VOLUMEUP, 0x00e9 },
+    { SDL_SCANCODE_VOLUMEDOWN, 0x00ea },
+    { SDL_SCANCODE_AUDIONEXT, 0x00b5 },
+    { SDL_SCANCODE_AUDIOPREV, 0x00b6 },
+    { SDL_SCANCODE_AUDIOSTOP, 0x00b7 },
+    { SDL_SCANCODE_AUDIOPLAY, 0x00cd },
+    { SDL_SCANCODE_AUDIOMUTE, 0x00e2 },
+    { SDL_SCANCODE_AUDIOREWIND, 0x00b4 },
+    { SDL_SCANCODE_AUDIOFASTFORWARD, 0x00b3 },
+    { SDL_SCANCODE_MEDIASELECT, 0x0183 },
+    { SDL_SCANCODE_WWW, 0x0196 },
+    { SDL_SCANCODE_MAIL, 0x018a },
+    { SDL_SCANCODE_CALCULATOR, 0x0192 },
+    { SDL_SCANCODE_COMPUTER, 0x0194 },
+    { SDL_SCANCODE_AC_SEARCH, 0x0221 },
+    { SDL_SCANCODE_AC_HOME, 0x0223 },
+    { SDL_SCANCODE_AC_BACK, 0x0224 },
+    { SDL_SCANCODE_AC_FORWARD, 0x0225 },
+    { SDL_SCANCODE_AC_STOP, 0x0226 },
+    { SDL_SCANCODE_AC_REFRESH, 0x0227 },
+    { SDL_SCANCODE_AC_BOOKMARKS, 0x022a },
+    { SDL_SCANCODE_BRIGHTNESSUP, 0x006f },
+    { SDL_SCANCODE_BRIGHTNESSDOWN, 0x0070 },
+    { SDL_SCANCODE_EJECT, 0x00b8 },
+};
+
+// and ones sent as system control
+static const struct {
+    SDL_Scancode scancode;
+    uint8_t control;
+} system_keymap[] = {
+    { SDL_SCANCODE_SLEEP, 2 },
+};
+
+typedef struct {
+    uint8_t type;
+    uint8_t version;
+    int8_t x;
+    int8_t y;
+    int8_t z;
+    int8_t rz;
+    int8_t rx;
+    int8_t ry;
+    uint8_t hat;
+    uint8_t buttons[4]; // little endian
+} gamepad_packet;
+
+// state of all opened game controllers, merged together
+typedef struct {
+    int8_t axes[SDL_CONTROLLER_AXIS_MAX];
+    uint32_t buttons;
+} gamepad_state;
+
+#define PACKET_TYPE_COUNT 14
+
+static const char *packet_type_names[PACKET_TYPE_COUNT] = {
+    "unknown",
+    "keyboard",
+    "mouse",
+    "gamepad",
+    "keyboard report",
+    "consumer",
+    "system control",
+    "leds",
+    "ping",
+    "pong",
+    "discover",
+    "discover reply",
+    "absolute mouse",
+    "pen",
+};
+
+// what has been sent since the stats were last reset
+typedef struct {
+    Uint32 started;
+    Uint32 packets;
+    Uint32 bytes;
+    Uint32 errors;
+    Uint32 merged; // packets combined with queued ones, while held back by --max-pps or a full queue
+    Uint32 per_type[PACKET_TYPE_COUNT];
+} send_stats;
+
+send_stats stats;
+
+// packets/s and errors during the last full second, for the status display
+Uint32 packet_rate = 0;
+Uint32 error_rate = 0;
+
+// show packet rate and errors in the window, toggled with RCTRL-S
+int show_status = 0;
+
+// a device we are sending to
+typedef struct {
+    char name[256];
+    struct sockaddr_storage addr; // ipv4 or ipv6
+    SDL_atomic_t send_errors; // consecutive failed sends, counted by the sender thread
+    device_state device;
+    remap_table remap;
+    const keyboard_layout *layout; // for typing text
+} target;
+
+target targets[MAX_TARGETS];
+int target_count = 0;
+
+// index of the target receiving input, or -1 to send to all of them
+int active_target = -1;
+
+// keyboard and mouse are captured and sent to the targets
+int grabbed = 1;
+
+// one socket is used for everything, so the devices can reply to it
+int sockfd = -1;
+
+// AF_INET6 when the socket is dual stack, ipv4 goes through it mapped
+int socket_family = AF_INET;
+
+// AF_INET or AF_INET6 from -4 or -6, hosts can be either otherwise
+int address_family = AF_UNSPEC;
+
+// local keys sent as something else, used when sending to all targets
+remap_table default_remap;
+
+// and the layout text is typed for
+const keyboard_layout *default_layout;
+
+int address_port(const struct sockaddr_storage *addr)
+{
+    if (addr->ss_family == AF_INET6) {
+        return ntohs(((const struct sockaddr_in6 *) addr)->sin6_port);
+    }
+    return ntohs(((const struct sockaddr_in *) addr)->sin_port);
+}
+
+// address and port as text, ipv6 ones in brackets
+void format_address(const struct sockaddr_storage *addr, char *buf, size_t len)
+{
+    char ip[INET6_ADDRSTRLEN];
+    socklen_t addr_len = addr->ss_family == AF_INET6 ?
+        sizeof(struct sockaddr_in6) : sizeof(struct sockaddr_in);
+
+    if (getnameinfo((const struct sockaddr *) addr, addr_len, ip, sizeof(ip),
+            NULL, 0, NI_NUMERICHOST) != 0) {
+        snprintf(ip, sizeof(ip), "?");
+    }
+
+    if (addr->ss_family == AF_INET6) {
+        snprintf(buf, len, "[%s]:%d", ip, address_port(addr));
+    } else {
+        snprintf(buf, len, "%s:%d", ip, address_port(addr));
+    }
+}
+
+int same_address(const struct sockaddr_storage *a, const struct sockaddr_storage *b)
+{
+    if (a->ss_family != b->ss_family || address_port(a) != address_port(b)) {
+        return 0;
+    }
+
+    if (a->ss_family == AF_INET6) {
+        return memcmp(
+            &((const struct sockaddr_in6 *) a)->sin6_addr,
+            &((const struct sockaddr_in6 *) b)->sin6_addr,
+            sizeof(struct in6_addr)) == 0;
+    }
+    return ((const struct sockaddr_in *) a)->sin_addr.s_addr ==
+        ((const struct sockaddr_in *) b)->sin_addr.s_addr;
+}
+
+// resolve host, which can be in host:port form, or [host]:port for ipv6
+// addresses
+int resolve_host(const char *host, int default_port, struct sockaddr_storage *addr)
+{
+    struct addrinfo hints;
+    struct addrinfo *result;
+    char *address;
+    char *port;
+    char name[256];
+
+    snprintf(name, sizeof(name), "%s", host);
+
+    address = name;
+    if (name[0] == '[') {
+        port = strchr(name, ']');
+        if (port == NULL || (port[1] != '\0' && port[1] != ':')) {
+            printf("Invalid address %s\n", host);
+            return -1;
+        }
+        *port++ = '\0';
+        address = name + 1;
+        if (*port == '\0') {
+            port = NULL;
+        }
+    } else {
+        port = strchr(name, ':');
+        // more than one colon is an ipv6 address without a port
+        if (port != NULL && strchr(port + 1, ':') != NULL) {
+            port = NULL;
+        }
+        if (port != NULL) {
+            *port = '\0';
+        }
+    }
+
+    bzero(&hints, sizeof(hints));
+    hints.ai_family = address_family;
+    hints.ai_socktype = SOCK_DGRAM;
+    // either kind, as long as this machine has that kind of address
+    if (address_family == AF_UNSPEC) {
+        hints.ai_flags = AI_ADDRCONFIG;
+    }
+
+    if (getaddrinfo(address, NULL, &hints, &result) != 0) {
+        printf("Could not resolve %s\n", address);
+        return -1;
+    }
+
+    bzero(addr, sizeof(*addr));
+    memcpy(addr, result->ai_addr, result->ai_addrlen);
+    if (addr->ss_family == AF_INET6) {
+        ((struct sockaddr_in6 *) addr)->sin6_port = htons(port != NULL ? atoi(port + 1) : default_port);
+    } else {
+        ((struct sockaddr_in *) addr)->sin_port = htons(port != NULL ? atoi(port + 1) : default_port);
+    }
+
+    freeaddrinfo(result);
+
+    if (address_port(addr) == 0) {
+        printf("Invalid port for %s\n", host);
+        return -1;
+    }
+
+    return 0;
+}
+
+// add host, which can be in host:port form, as a target
+int add_target(const char *host, int default_port)
+{
+    target *t = &targets[target_count];
+    char address[64];
+
+    bzero(t, sizeof(*t));
+    snprintf(t->name, sizeof(t->name), "%s", host);
+
+    if (resolve_host(host, default_port, &t->addr) < 0) {
+        return -1;
+    }
+
+    format_address(&t->addr, address, sizeof(address));
+    printf("Sending to %s\n", address);
+
+    target_count++;
+    return 0;
+}
+
+int open_socket(void)
+{
+    int on = 1;
+    int off = 0;
+
+    // one dual stack socket reaches targets of both kinds, systems without
+    // ipv6 get an ipv4 one
+    if (address_family != AF_INET) {
+        sockfd = socket(AF_INET6, SOCK_DGRAM, 0);
+    }
+    if (sockfd >= 0) {
+        socket_family = AF_INET6;
+        setsockopt(sockfd, IPPROTO_IPV6, IPV6_V6ONLY, &off, sizeof(off));
+    } else if (address_family != AF_INET6) {
+        sockfd = socket(AF_INET, SOCK_DGRAM, 0);
+    }
+    if (sockfd < 0) {
+        printf("Could not create socket\n");
+        return -1;
+    }
+
+    // we only read the socket every now and then, so let the kernel tell us
+    // when packets actually arrived
+    setsockopt(sockfd, SOL_SOCKET, SO_TIMESTAMP, &on, sizeof(on));
+
+    return 0;
+}
+
+// the address to give sendto(), ipv4 ones are mapped to ipv6 for the dual
+// stack socket. returns its length
+socklen_t socket_address(const struct sockaddr_storage *addr, struct sockaddr_storage *out)
+{
+    const struct sockaddr_in *v4 = (const struct sockaddr_in *) addr;
+    struct sockaddr_in6 *v6 = (struct sockaddr_in6 *) out;
+
+    if (addr->ss_family == AF_INET6) {
+        *out = *addr;
+        return sizeof(struct sockaddr_in6);
+    }
+
+    if (socket_family != AF_INET6) {
+        *out = *addr;
+        return sizeof(struct sockaddr_in);
+    }
+
+    bzero(out, sizeof(*out));
+    v6->sin6_family = AF_INET6;
+    v6->sin6_port = v4->sin_port;
+    v6->sin6_addr.s6_addr[10] = 0xff;
+    v6->sin6_addr.s6_addr[11] = 0xff;
+    memcpy(&v6->sin6_addr.s6_addr[12], &v4->sin_addr, 4);
+    return sizeof(*v6);
+}
+
+// and back, for what arrives on the socket
+void unmap_address(struct sockaddr_storage *addr)
+{
+    struct sockaddr_in6 *v6 = (struct sockaddr_in6 *) addr;
+    struct sockaddr_in v4;
+
+    if (addr->ss_family != AF_INET6 || !IN6_IS_ADDR_V4MAPPED(&v6->sin6_addr)) {
+        return;
+    }
+
+    bzero(&v4, sizeof(v4));
+    v4.sin_family = AF_INET;
+    v4.sin_port = v6->sin6_port;
+    memcpy(&v4.sin_addr, &v6->sin6_addr.s6_addr[12], 4);
+    bzero(addr, sizeof(*addr));
+    memcpy(addr, &v4, sizeof(v4));
+}
+
+#define SEND_QUEUE_SIZE 256
+
+// a packet waiting for the sender thread
+typedef struct {
+    target *target;
+    struct sockaddr_storage addr; // the target's address may be looked up again meanwhile
+    uint8_t len;
+    uint8_t data[32];
+} queued_packet;
+
+// packets are sent from a thread of their own, so a slow sendto() (arp
+// lookups, full socket buffers) never holds up input handling
+typedef struct {
+    queued_packet packets[SEND_QUEUE_SIZE];
+    int head; // next to send
+    int count;
+    SDL_mutex *lock;
+    SDL_cond *ready;
+} send_queue;
+
+send_queue queue;
+
+// failed sends, counted by the sender thread
+SDL_atomic_t send_failures;
+
+// for devices that drop packets when they get too many, 0 for no limit
+int max_pps = 0;
+
+// keep to max_pps by waiting until the next packet is due
+void pace_sending(void)
+{
+    static struct timeval next = { 0, 0 };
+    struct timeval now;
+    long wait;
+
+    if (max_pps <= 0) {
+        return;
+    }
+
+    gettimeofday(&now, NULL);
+    wait = (next.tv_sec - now.tv_sec) * 1000000 + (next.tv_usec - now.tv_usec);
+    if (wait > 0) {
+        usleep(wait);
+    } else {
+        // been idle, don't make up for it with a burst
+        next = now;
+    }
+
+    next.tv_usec += 1000000 / max_pps;
+    next.tv_sec += next.tv_usec / 1000000;
+    next.tv_usec %= 1000000;
+}
+
+// while packets wait for their turn, mouse movement can be added to the
+// last queued one instead of taking another slot.
+// returns 1 if the packet was merged
+int merge_motion(target *t, const void *packet, size_t len)
+{
+    const mouse_packet *mouse = packet;
+    queued_packet *last;
+    mouse_packet *queued;
+
+    // the first one may be going out right now
+    if (queue.count < 2 || len != sizeof(mouse_packet) || mouse->type != 2 ||
+            mouse->vertical || mouse->horizontal) {
+        return 0;
+    }
+
+    last = &queue.packets[(queue.head + queue.count - 1) % SEND_QUEUE_SIZE];
+    queued = (mouse_packet *) last->data;
+    if (last->target != t || last->len != len || queued->type != 2 ||
+            queued->buttons != mouse->buttons ||
+            queued->vertical || queued->horizontal ||
+            abs(queued->x + mouse->x) > 127 || abs(queued->y + mouse->y) > 127) {
+        return 0;
+    }
+
+    queued->x += mouse->x;
+    queued->y += mouse->y;
+
+    return 1;
+}
+
+// keyboard, buttons, consumer and system control, the gamepad, the
+// absolute pointer and the pen are sent as their whole state, only the
+// newest one matters
+int carries_state(uint8_t type)
+{
+    return type == 2 || type == 3 || type == 4 || type == 5 ||
+        type == 6 || type == 12 || type == 13;
+}
+
+int add_clamped(int a, int b)
+{
+    int sum = a + b;
+    return sum > 127 ? 127 : sum < -127 ? -127 : sum;
+}
+
+// a queued state packet takes the state of a newer one of the same type
+void merge_state(queued_packet *queued, const void *packet, size_t len)
+{
+    // keep what the older one moved, as far as it fits
+    if (queued->data[0] == 2) {
+        const mouse_packet *old = (const mouse_packet *) queued->data;
+        mouse_packet mouse = *(const mouse_packet *) packet;
+
+        mouse.x = add_clamped(old->x, mouse.x);
+        mouse.y = add_clamped(old->y, mouse.y);
+        mouse.vertical = add_clamped(old->vertical, mouse.vertical);
+        mouse.horizontal = add_clamped(old->horizontal, mouse.horizontal);
+        memcpy(queued->data, &mouse, len);
+    } else {
+        memcpy(queued->data, packet, len);
+    }
+}
+
+// the state packets of a type queued for the target are folded into the
+// oldest of them, which ends up with the newest state, and the others'
+// slots are freed. returns that oldest one, or NULL if none was queued
+queued_packet *fold_queued(target *t, uint8_t type, size_t len)
+{
+    queued_packet *oldest = NULL;
+    int kept = 1;
+
+    if (queue.count < 2) {
+        return NULL;
+    }
+
+    // the first one may be going out right now
+    for (int i = 1; i < queue.count; i++) {
+        queued_packet *queued = &queue.packets[(queue.head + i) % SEND_QUEUE_SIZE];
+        queued_packet *slot = &queue.packets[(queue.head + kept) % SEND_QUEUE_SIZE];
+        int same = queued->target == t && queued->len == len && queued->data[0] == type;
+
+        if (same && oldest != NULL) {
+            merge_state(oldest, queued->data, len);
+            stats.merged++;
+            continue;
+        }
+
+        if (slot != queued) {
+            *slot = *queued;
+        }
+        if (same) {
+            oldest = slot;
+        }
+        kept++;
+    }
+
+    queue.count = kept;
+    return oldest;
+}
+
+int send_packets(void *data)
+{
+    (void) data;
+
+    for (;;) {
+        queued_packet packet;
+
+        SDL_LockMutex(queue.lock);
+        while (queue.count == 0) {
+            SDL_CondWait(queue.ready, queue.lock);
+        }
+        packet = queue.packets[queue.head];
+        SDL_UnlockMutex(queue.lock);
+
+        pace_sending();
+
+        target *t = packet.target;
+        struct sockaddr_storage addr;
+        socklen_t addr_len = socket_address(&packet.addr, &addr);
+
+        if (sendto(
+                sockfd,
+                packet.data,
+                packet.len,
+                0,
+                (struct sockaddr *) &addr,
+                addr_len) < 0) {
+            SDL_AtomicAdd(&send_failures, 1);
+
+            // only complain when a target starts failing, not for every packet
+            if (SDL_AtomicAdd(&t->send_errors, 1) == 0) {
+                printf("Error sending packet to %s\n", t->name);
+            }
+        } else if (SDL_AtomicGet(&t->send_errors)) {
+            printf("Sending to %s works again after %d errors\n", t->name,
+                SDL_AtomicSet(&t->send_errors, 0));
+        }
+
+        // only now, so flush_packets() knows everything went out
+        SDL_LockMutex(queue.lock);
+        queue.head = (queue.head + 1) % SEND_QUEUE_SIZE;
+        queue.count--;
+        SDL_UnlockMutex(queue.lock);
+    }
+
+    return 0;
+}
+
+int start_sender(void)
+{
+    queue.lock = SDL_CreateMutex();
+    queue.ready = SDL_CreateCond();
+
+    if (queue.lock == NULL || queue.ready == NULL ||
+            SDL_CreateThread(send_packets, "sender", NULL) == NULL) {
+        printf("Could not start sending: %s\n", SDL_GetError());
+        return -1;
+    }
+
+    return 0;
+}
+
+// wait a moment for the queued packets to go out, e.g. the releases sent
+// when quitting
+void flush_packets(void)
+{
+    for (int i = 0; i < 100; i++) {
+        SDL_LockMutex(queue.lock);
+        int count = queue.count;
+        SDL_UnlockMutex(queue.lock);
+
+        if (count == 0) {
+            break;
+        }
+        SDL_Delay(10);
+    }
+}
+
+// returns 1 if the packet was queued, or merged into one that was
+int send_packet_to(target *t, const void *packet, size_t len)
+{
+    uint8_t type = ((const uint8_t *) packet)[0];
+
+    SDL_LockMutex(queue.lock);
+
+    if (max_pps > 0 && merge_motion(t, packet, len)) {
+        stats.merged++;
+        SDL_UnlockMutex(queue.lock);
+        return 1;
+    }
+
+    // the sender is behind, but input handling doesn't wait for it. a
+    // release may be the last packet for a while, so state isn't dropped:
+    // it goes into the oldest queued state of its type, or room is made by
+    // folding together the type waiting the longest
+    if (queue.count == SEND_QUEUE_SIZE && carries_state(type)) {
+        queued_packet *oldest = fold_queued(t, type, len);
+        queued_packet *next = &queue.packets[(queue.head + 1) % SEND_QUEUE_SIZE];
+
+        if (oldest != NULL) {
+            merge_state(oldest, packet, len);
+            stats.merged++;
+            SDL_UnlockMutex(queue.lock);
+            return 1;
+        }
+        if (carries_state(next->data[0])) {
+            fold_queued(next->target, next->data[0], next->len);
+        }
+    }
+
+    // anything else, pings and such, can go
+    if (queue.count == SEND_QUEUE_SIZE || len > sizeof(queue.packets[0].data)) {
+        SDL_UnlockMutex(queue.lock);
+        SDL_AtomicAdd(&send_failures, 1);
+        return 0;
+    }
+
+    queued_packet *queued = &queue.packets[(queue.head + queue.count) % SEND_QUEUE_SIZE];
+    queued->target = t;
+    queued->addr = t->addr;
+    queued->len = len;
+    memcpy(queued->data, packet, len);
+    queue.count++;
+
+    stats.packets++;
+    stats.bytes += len;
+    stats.per_type[type < PACKET_TYPE_COUNT ? type : 0]++;
+
+    SDL_CondSignal(queue.ready);
+    SDL_UnlockMutex(queue.lock);
+    return 1;
+}
+
+#define MAX_MACRO_EVENTS 4096
+
+// a packet sent while recording a macro
+typedef struct {
+    Uint32 time; // ms from the start of the recording
+    uint8_t len;
+    uint8_t data[16];
+} macro_event;
+
+typedef struct {
+    macro_event events[MAX_MACRO_EVENTS];
+    int count;
+    int recording;
+    int playing; // index of the next event to play, -1 if not playing
+    Uint32 started; // when recording or playback started
+} macro_state;
+
+macro_state macro = { .playing = -1 };
+
+// every packet sent is written here with --record, in the macro format
+FILE *session = NULL;
+Uint32 session_started;
+
+// with --audit, a line about every event sent is added here. keys are
+// only counted, so typed passwords don't end up in it, unless audit_keys
+// is set
+FILE *audit = NULL;
+int audit_keys = 0;
+
+void audit_packet(const char *to, const void *packet, size_t len)
+{
+    const uint8_t *data = packet;
+    struct timeval now;
+    char when[64];
+    char what[128];
+
+    gettimeofday(&now, NULL);
+    strftime(when, sizeof(when), "%Y-%m-%d %H:%M:%S", localtime(&now.tv_sec));
+
+    switch (data[0]) {
+        case 2: {
+            const mouse_packet *mouse = packet;
+            snprintf(what, sizeof(what), "mouse buttons %02x move %d %d wheel %d %d",
+                mouse->buttons, mouse->x, mouse->y, mouse->vertical, mouse->horizontal);
+            break;
+        }
+        case 12: {
+            const absolute_mouse_packet *mouse = packet;
+            snprintf(what, sizeof(what), "mouse buttons %02x to %d %d wheel %d %d",
+                mouse->buttons,
+                mouse->x[0] | (mouse->x[1] << 8), mouse->y[0] | (mouse->y[1] << 8),
+                mouse->vertical, mouse->horizontal);
+            break;
+        }
+        case 13: {
+            const pen_packet *pen = packet;
+            snprintf(what, sizeof(what), "pen %02x at %d %d pressure %d tilt %d %d",
+                pen->flags,
+                pen->x[0] | (pen->x[1] << 8), pen->y[0] | (pen->y[1] << 8),
+                pen->pressure[0] | (pen->pressure[1] << 8),
+                pen->tilt_x, pen->tilt_y);
+            break;
+        }
+        case 3:
+            snprintf(what, sizeof(what), "gamepad");
+            break;
+        case 4: {
+            const keyboard_report_packet *keyboard = packet;
+            int held = 0;
+            for (int i = 0; i < 6; i++) {
+                held += keyboard->keys[i] != 0;
+            }
+            if (audit_keys) {
+                snprintf(what, sizeof(what), "keyboard modifiers %02x keys %02x %02x %02x %02x %02x %02x",
+                    keyboard->modifiers,
+                    keyboard->keys[0], keyboard->keys[1], keyboard->keys[2],
+                    keyboard->keys[3], keyboard->keys[4], keyboard->keys[5]);
+            } else {
+                snprintf(what, sizeof(what), "keyboard modifiers %02x, %d keys held",
+                    keyboard->modifiers, held);
+            }
+            break;
+        }
+        case 5: {
+            const consumer_packet *consumer = packet;
+            snprintf(what, sizeof(what), "consumer control %04x",
+                consumer->usage[0] | (consumer->usage[1] << 8));
+            break;
+        }
+        case 6: {
+            const system_control_packet *control = packet;
+            snprintf(what, sizeof(what), "system control %d", control->control);
+            break;
+        }
+        default:
+            snprintf(what, sizeof(what), "packet type %d, %zu bytes", data[0], len);
+            break;
+    }
+
+    fprintf(audit, "%s.%03ld %s %s\n", when, (long) now.tv_usec / 1000, to, what);
+}
+
+void record_packet(const void *packet, size_t len)
+{
+    fprintf(session, "%u", SDL_GetTicks() - session_started);
+    for (size_t i = 0; i < len; i++) {
+        fprintf(session, " %02x", ((const uint8_t *) packet)[i]);
+    }
+    fprintf(session, "\n");
+}
+
+// events go out to the active target, or all of them
+void send_packet(const void *packet, size_t len)
+{
+    int queued = 0;
+
+    if (macro.recording && macro.count < MAX_MACRO_EVENTS && len <= sizeof(macro.events[0].data)) {
+        macro_event *event = &macro.events[macro.count++];
+        event->time = SDL_GetTicks() - macro.started;
+        event->len = len;
+        memcpy(event->data, packet, len);
+    }
+
+    for (int i = 0; i < target_count; i++) {
+        if (active_target == -1 || active_target == i) {
+            queued |= send_packet_to(&targets[i], packet, len);
+        }
+    }
+
+    // what was dropped never went anywhere
+    if (!queued) {
+        return;
+    }
+
+    if (audit != NULL) {
+        audit_packet(active_target == -1 ? "all" : targets[active_target].name, packet, len);
+    }
+
+    if (session != NULL) {
+        record_packet(packet, len);
+    }
+}
+
+// events hidgui sends one target on its own, audited and recorded like
+// the others but not part of a macro
+void send_event_to(target *t, const void *packet, size_t len)
+{
+    if (!send_packet_to(t, packet, len)) {
+        return;
+    }
+
+    if (audit != NULL) {
+        audit_packet(t->name, packet, len);
+    }
+
+    if (session != NULL) {
+        record_packet(packet, len);
+    }
+}
+
+// keys are remapped for the target receiving input, the default remap is
+// used when sending to several
+remap_table *active_remap(void)
+{
+    if (active_target != -1) {
+        return &targets[active_target].remap;
+    }
+
+    return target_count == 1 ? &targets[0].remap : &default_remap;
+}
+
+// text is typed for the layout of the target receiving input
+const keyboard_layout *active_layout(void)
+{
+    if (active_target != -1) {
+        return targets[active_target].layout;
+    }
+
+    return target_count == 1 ? targets[0].layout : default_layout;
+}
+
+target *find_target(struct sockaddr_storage *addr)
+{
+    for (int i = 0; i < target_count; i++) {
+        if (same_address(&targets[i].addr, addr)) {
+            return &targets[i];
+        }
+    }
+
+    return NULL;
+}
+
+// read any pending packets from the devices, without blocking.
+// returns 1 if the state of a device was updated
+int receive_packets(void)
+{
+    int updated = 0;
+    uint8_t buf[64];
+    ssize_t len;
+    struct sockaddr_storage addr;
+    target *t;
+    device_state *device;
+    struct iovec iov;
+    struct msghdr msg;
+    struct cmsghdr *cmsg;
+    char control[CMSG_SPACE(sizeof(struct timeval))];
+    struct timeval received;
+
+    while (1) {
+        iov.iov_base = buf;
+        iov.iov_len = sizeof(buf);
+        bzero(&msg, sizeof(msg));
+        msg.msg_name = &addr;
+        msg.msg_namelen = sizeof(addr);
+        msg.msg_iov = &iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control;
+        msg.msg_controllen = sizeof(control);
+
+        len = recvmsg(sockfd, &msg, MSG_DONTWAIT);
+        if (len < 0) {
+            break;
+        }
+        unmap_address(&addr);
+
+        gettimeofday(&received, NULL);
+        for (cmsg = CMSG_FIRSTHDR(&msg); cmsg != NULL; cmsg = CMSG_NXTHDR(&msg, cmsg)) {
+            if (cmsg->cmsg_level == SOL_SOCKET && cmsg->cmsg_type == SCM_TIMESTAMP) {
+                memcpy(&received, CMSG_DATA(cmsg), sizeof(received));
+            }
+        }
+
+        if (len < 2 || buf[1] != 1) {
+            continue;
+        }
+
+        t = find_target(&addr);
+        if (t == NULL) {
+            continue;
+        }
+        device = &t->device;
+
+        if (buf[0] == 7 && len == sizeof(leds_packet)) {
+            device->leds = ((leds_packet *) buf)->leds;
+            device->leds_known = 1;
+            updated = 1;
+        } else if (buf[0] == 9 && len == sizeof(ping_packet)) {
+            ping_packet *pong = (ping_packet *) buf;
+            uint32_t id = pong->id[0] |
+                (pong->id[1] << 8) |
+                (pong->id[2] << 16) |
+                ((uint32_t) pong->id[3] << 24);
+
+            // ignore stale answers to older pings
+            if (id == device->ping_id) {
+                double rtt = (received.tv_sec - device->ping_sent.tv_sec) * 1000.0 +
+                    (received.tv_usec - device->ping_sent.tv_usec) / 1000.0;
+
+                if (device->rtt_count == 0 || rtt < device->rtt_min) {
+                    device->rtt_min = rtt;
+                }
+                if (device->rtt_count == 0 || rtt > device->rtt_max) {
+                    device->rtt_max = rtt;
+                }
+                device->rtt_sum += rtt;
+                device->rtt_count++;
+
+                device->last_pong = SDL_GetTicks();
+                updated = 1;
+            }
+        }
+    }
+
+    return updated;
+}
+
+// send keepalives and track whether the device is answering them.
+// returns 1 if the link state changed
+int keepalive(target *t)
+{
+    device_state *device = &t->device;
+    Uint32 now = SDL_GetTicks();
+    int connected;
+
+    if (now - device->last_ping >= KEEPALIVE_INTERVAL_MS) {
+        ping_packet packet;
+        packet.type = 8; // 8 == ping
+        packet.version = 1;
+
+        device->ping_id++;
+        packet.id[0] = device->ping_id & 0xff;
+        packet.id[1] = (device->ping_id >> 8) & 0xff;
+        packet.id[2] = (device->ping_id >> 16) & 0xff;
+        packet.id[3] = (device->ping_id >> 24) & 0xff;
+
+        gettimeofday(&device->ping_sent, NULL);
+        send_packet_to(t, &packet, sizeof(packet));
+        device->last_ping = now;
+    }
+
+    connected = device->last_pong != 0 &&
+        now - device->last_pong < KEEPALIVE_TIMEOUT_MS;
+
+    if (connected != device->connected) {
+        device->connected = connected;
+        printf("%s %s\n", t->name, connected ? "connected" : "disconnected");
+        return 1;
+    }
+
+    // the device may have come back with a different address
+    if (!connected && device->last_pong != 0 &&
+            now - device->last_resolve >= RESOLVE_INTERVAL_MS) {
+        struct sockaddr_storage addr;
+        char address[64];
+
+        device->last_resolve = now;
+        if (resolve_host(t->name, address_port(&t->addr), &addr) == 0 &&
+                !same_address(&addr, &t->addr)) {
+            t->addr = addr;
+            format_address(&addr, address, sizeof(address));
+            printf("%s is now at %s\n", t->name, address);
+        }
+    }
+
+    return 0;
+}
+
+void send_keyboard(const keyboard_state *state)
+{
+    keyboard_report_packet packet;
+    packet.type = 4; // 4 == full keyboard report
+    packet.version = 1;
+    packet.modifiers = state->modifiers | state->latched | state->locked;
+    memcpy(packet.keys, state->keys, sizeof(packet.keys));
+
+    send_packet(&packet, sizeof(packet));
+}
+
+// how long the lock keys of a target may differ from ours before anything
+// is done, the leds of a lock key just pressed take a moment to come back
+#define LOCK_SYNC_DELAY_MS 500
+
+// the lock keys by led, with their hid usages
+static const struct {
+    uint8_t led;
+    uint8_t key;
+    const char *name;
+} lock_keys[] = {
+    { 0x01, 0x53, "num lock" },
+    { 0x02, 0x39, "caps lock" },
+    { 0x04, 0x47, "scroll lock" },
+};
+
+// press and release a lock key on one target, with nothing else held
+void tap_lock_key(target *t, uint8_t key)
+{
+    keyboard_report_packet packet = { 4, 1, 0, { key } };
+
+    send_event_to(t, &packet, sizeof(packet));
+    packet.keys[0] = 0;
+    send_event_to(t, &packet, sizeof(packet));
+}
+
+// our lock keys, as keyboard leds
+uint8_t local_locks(void)
+{
+    SDL_Keymod mod = SDL_GetModState();
+    uint8_t leds = 0;
+
+    if (mod & KMOD_NUM) {
+        leds |= 0x01;
+    }
+    if (mod & KMOD_CAPS) {
+        leds |= 0x02;
+    }
+#if SDL_VERSION_ATLEAST(2, 0, 18)
+    if (mod & KMOD_SCROLL) {
+        leds |= 0x04;
+    }
+#endif
+
+    return leds;
+}
+
+// compare the lock keys of the targets receiving input with ours, with
+// --lock-sync fix press the ones that differ once, and warn about what
+// still does. returns 1 if the title needs updating
+int check_locks(const options *opts, const keyboard_state *keyboard)
+{
+    int fix = strcmp(opts->lock_sync, "fix") == 0;
+    uint8_t local = local_locks();
+    Uint32 now = SDL_GetTicks();
+    int updated = 0;
+
+    // other backends take the keys before sdl sees our lock keys change
+    if (strcmp(opts->lock_sync, "off") == 0 || strcmp(opts->backend, "sdl") != 0) {
+        return 0;
+    }
+
+    for (int i = 0; i < target_count; i++) {
+        device_state *device = &targets[i].device;
+        uint8_t differ = (device->leds ^ local) & 0x07;
+        uint8_t unfixed;
+
+        if (!device->connected || !device->leds_known ||
+                (active_target != -1 && active_target != i)) {
+            continue;
+        }
+
+        if (!differ) {
+            updated |= device->locks_differ != 0;
+            device->locks_differ_since = 0;
+            device->locks_differ = 0;
+            device->locks_fixed = 0;
+            continue;
+        }
+
+        if (device->locks_differ_since == 0) {
+            device->locks_differ_since = now;
+        }
+        if (now - device->locks_differ_since < LOCK_SYNC_DELAY_MS) {
+            continue;
+        }
+
+        // not in the middle of a shortcut
+        unfixed = differ & ~device->locks_fixed;
+        if (fix && unfixed && !keyboard->keys[0] &&
+                !(keyboard->modifiers | keyboard->latched | keyboard->locked)) {
+            for (size_t j = 0; j < sizeof(lock_keys) / sizeof(lock_keys[0]); j++) {
+                if (!(unfixed & lock_keys[j].led)) {
+                    continue;
+                }
+                printf("Pressing %s on %s to turn it %s\n", lock_keys[j].name, targets[i].name,
+                    (local & lock_keys[j].led) ? "on" : "off");
+                tap_lock_key(&targets[i], lock_keys[j].key);
+            }
+            device->locks_fixed |= unfixed;
+            device->locks_differ_since = now;
+            continue;
+        }
+
+        if (device->locks_differ != differ) {
+            for (size_t j = 0; j < sizeof(lock_keys) / sizeof(lock_keys[0]); j++) {
+                if (differ & ~device->locks_differ & lock_keys[j].led) {
+                    printf("%s is %s on %s but %s here\n", lock_keys[j].name,
+                        (device->leds & lock_keys[j].led) ? "on" : "off", targets[i].name,
+                        (local & lock_keys[j].led) ? "on" : "off");
+                }
+            }
+            device->locks_differ = differ;
+            updated = 1;
+        }
+    }
+
+    return updated;
+}
+
+// toggle a lock key on the targets receiving input, for when it has drifted
+// from ours and the device can't tell, e.g. after the target rebooted. what
+// we think its leds are is flipped along with it, and lock sync doesn't
+// press it back
+void toggle_lock(uint8_t led, const keyboard_state *keyboard)
+{
+    for (size_t j = 0; j < sizeof(lock_keys) / sizeof(lock_keys[0]); j++) {
+        keyboard_report_packet packet = { 4, 1, 0, { lock_keys[j].key } };
+
+        if (lock_keys[j].led != led) {
+            continue;
+        }
+
+        // sent like any other key, so a recorded session toggles it once
+        // however many targets there are
+        printf("Toggling %s on %s\n", lock_keys[j].name,
+            active_target != -1 ? targets[active_target].name :
+                target_count == 1 ? targets[0].name : "all targets");
+        send_packet(&packet, sizeof(packet));
+        packet.keys[0] = 0;
+        send_packet(&packet, sizeof(packet));
+
+        for (int i = 0; i < target_count; i++) {
+            device_state *device = &targets[i].device;
+
+            if (active_target != -1 && active_target != i) {
+                continue;
+            }
+
+            device->leds ^= led;
+            device->locks_differ_since = 0;
+            device->locks_differ &= ~led;
+            device->locks_fixed |= led;
+        }
+    }
+
+    // whatever was held before is held again
+    send_keyboard(keyboard);
+}
+
+// returns the consumer usage for a scancode, or 0 if it's a normal key
+uint16_t consumer_usage(SDL_Scancode scancode)
+{
+    for (size_t i = 0; i < sizeof(consumer_keymap) / sizeof(consumer_keymap[0]); i++) {
+        if (consumer_keymap[i].scancode == scancode) {
+            return consumer_keymap[i].usage;
+        }
+    }
+
+    return 0;
+}
+
+// returns the system control for a scancode, or 0 if it's a normal key
+uint8_t system_control(SDL_Scancode scancode)
+{
+    for (size_t i = 0; i < sizeof(system_keymap) / sizeof(system_keymap[0]); i++) {
+        if (system_keymap[i].scancode == scancode) {
+            return system_keymap[i].control;
+        }
+    }
+
+    return 0;
+}
+
+// where a media key goes
+enum {
+    MEDIA_FORWARD = 0, // to the target only
+    MEDIA_LOCAL, // left to this machine
+    MEDIA_SHARED, // both
+};
+
+uint8_t media_policy[SDL_NUM_SCANCODES];
+int media_all_local = 0; // toggled with a hotkey, all media keys stay here
+
+// what the event tap lets through to this machine, kept in sync with the
+// above
+uint8_t media_passthrough[SDL_NUM_SCANCODES];
+
+// media keys from the config file, e.g. "VolumeUp, VolumeDown".
+// returns -1 if a key is unknown or not a media key
+int set_media_keys(const char *names, uint8_t policy)
+{
+    char list[256];
+    char *name;
+
+    snprintf(list, sizeof(list), "%s", names);
+
+    for (name = strtok(list, ","); name != NULL; name = strtok(NULL, ",")) {
+        SDL_Scancode scancode;
+
+        while (*name == ' ') {
+            name++;
+        }
+        scancode = SDL_GetScancodeFromName(name);
+        if (scancode == SDL_SCANCODE_UNKNOWN || !consumer_usage(scancode)) {
+            printf("Unknown media key %s\n", name);
+            return -1;
+        }
+        media_policy[scancode] = policy;
+    }
+
+    return 0;
+}
+
+uint8_t media_key_policy(SDL_Scancode scancode)
+{
+    return media_all_local ? MEDIA_LOCAL : media_policy[scancode];
+}
+
+void update_media_passthrough(void)
+{
+    for (int i = 0; i < SDL_NUM_SCANCODES; i++) {
+        media_passthrough[i] = consumer_usage(i) && media_key_policy(i) != MEDIA_FORWARD;
+    }
+}
+
+void send_consumer(uint16_t usage)
+{
+    consumer_packet packet;
+    packet.type = 5; // 5 == consumer control
+    packet.version = 1;
+    packet.usage[0] = usage & 0xff;
+    packet.usage[1] = (usage >> 8) & 0xff;
+
+    send_packet(&packet, sizeof(packet));
+}
+
+void send_system_control(uint8_t control)
+{
+    system_control_packet packet;
+    packet.type = 6; // 6 == system control
+    packet.version = 1;
+    packet.control = control;
+
+    send_packet(&packet, sizeof(packet));
+}
+
+// returns 1 if the keyboard state changed
+int press_key(keyboard_state *state, uint8_t scancode)
+{
+    // modifiers (LCTRL..RGUI) go to the modifier byte
+    if (scancode >= 224 && scancode <= 231) {
+        state->modifiers |= 1 << (scancode - 224);
+        return 1;
+    }
+
+    for (int i = 0; i < 6; i++) {
+        if (state->keys[i] == scancode) {
+            return 0;
+        }
+    }
+
+    for (int i = 0; i < 6; i++) {
+        if (state->keys[i] == 0) {
+            state->keys[i] = scancode;
+            return 1;
+        }
+    }
+
+    // rollover, no free slots
+    return 0;
+}
+
+// returns 1 if the keyboard state changed
+int release_key(keyboard_state *state, uint8_t scancode)
+{
+    if (scancode >= 224 && scancode <= 231) {
+        state->modifiers &= ~(1 << (scancode - 224));
+        return 1;
+    }
+
+    for (int i = 0; i < 6; i++) {
+        if (state->keys[i] == scancode) {
+            state->keys[i] = 0;
+            return 1;
+        }
+    }
+
+    return 0;
+}
+
+// sticky keys: a modifier tapped on its own applies to the next key, tapped
+// again it stays on until tapped a third time
+void sticky_press(keyboard_state *state, uint8_t scancode)
+{
+    if (scancode >= 224 && scancode <= 231) {
+        state->tapping = 1 << (scancode - 224);
+    } else {
+        state->tapping = 0;
+    }
+}
+
+// returns 1 if the keyboard state changed
+int sticky_release(keyboard_state *state, uint8_t scancode)
+{
+    uint8_t bit;
+
+    if (scancode < 224 || scancode > 231) {
+        // the key used the latched modifiers
+        if (state->latched) {
+            state->latched = 0;
+            return 1;
+        }
+        return 0;
+    }
+
+    bit = 1 << (scancode - 224);
+    if (state->tapping != bit) {
+        return 0;
+    }
+    state->tapping = 0;
+
+    if (state->locked & bit) {
+        state->locked &= ~bit;
+    } else if (state->latched & bit) {
+        state->latched &= ~bit;
+        state->locked |= bit;
+    } else {
+        state->latched |= bit;
+    }
+
+    return 1;
+}
+
+// convert sdl modifier state to hid modifier byte
+uint8_t keymod_to_modifiers(int keymod)
+{
+    uint8_t modifiers = 0;
+
+    if (keymod & KMOD_LCTRL) modifiers |= 0x01;
+    if (keymod & KMOD_LSHIFT) modifiers |= 0x02;
+    if (keymod & KMOD_LALT) modifiers |= 0x04;
+    if (keymod & KMOD_LGUI) modifiers |= 0x08;
+    if (keymod & KMOD_RCTRL) modifiers |= 0x10;
+    if (keymod & KMOD_RSHIFT) modifiers |= 0x20;
+    if (keymod & KMOD_RALT) modifiers |= 0x40;
+    if (keymod & KMOD_RGUI) modifiers |= 0x80;
+
+    return modifiers;
+}
+
+// pick up modifiers sdl knows about but we haven't seen a key event for,
+// returns 1 if the keyboard state changed
+int sync_modifiers(keyboard_state *state, int keymod)
+{
+    uint8_t remapped = active_remap()->modifiers;
+    uint8_t modifiers = (keymod_to_modifiers(keymod) & ~remapped) |
+        (state->modifiers & remapped);
+
+    if (modifiers == state->modifiers) {
+        return 0;
+    }
+
+    state->modifiers = modifiers;
+    return 1;
+}
+
+void send_mouse(
+    uint8_t buttons,
+    int8_t x,
+    int8_t y,
+    int8_t vertical,
+    int8_t horizontal)
+{
+    mouse_packet packet;
+    packet.type = 2; // 2 == mouse
+    packet.version = 1;
+    packet.buttons = buttons;
+    packet.x = x;
+    packet.y = y;
+    packet.vertical = vertical;
+    packet.horizontal = horizontal;
+
+    send_packet(&packet, sizeof(packet));
+}
+
+void send_mouse_to(
+    uint8_t buttons,
+    uint16_t x,
+    uint16_t y,
+    int8_t vertical,
+    int8_t horizontal)
+{
+    absolute_mouse_packet packet;
+    packet.type = 12; // 12 == absolute mouse
+    packet.version = 1;
+    packet.buttons = buttons;
+    packet.x[0] = x & 0xff;
+    packet.x[1] = x >> 8;
+    packet.y[0] = y & 0xff;
+    packet.y[1] = y >> 8;
+    packet.vertical = vertical;
+    packet.horizontal = horizontal;
+
+    send_packet(&packet, sizeof(packet));
+}
+
+// with --absolute, and for touches, the window is mapped onto this part of
+// the target's desktop. without --target-screen it's all of it, in the
+// units of the absolute mouse packet
+int absolute = 0;
+SDL_Rect absolute_screen = { 0, 0, 32768, 32768 };
+int absolute_desktop[2] = { 32768, 32768 };
+
+// parse WxH, or WxH+X+Y if offset is set
+int parse_geometry(const char *text, SDL_Rect *rect, int offset)
+{
+    int end = 0;
+
+    rect->x = 0;
+    rect->y = 0;
+    if (offset) {
+        sscanf(text, "%dx%d+%d+%d%n", &rect->w, &rect->h, &rect->x, &rect->y, &end);
+    } else {
+        sscanf(text, "%dx%d%n", &rect->w, &rect->h, &end);
+    }
+
+    if (end == 0 || text[end] != '\0' || rect->w <= 0 || rect->h <= 0 ||
+            rect->x < 0 || rect->y < 0) {
+        return -1;
+    }
+
+    return 0;
+}
+
+// returns -1 if the settings don't make sense
+int setup_absolute(const options *opts)
+{
+    SDL_Rect desktop;
+    int sdl = strcmp(opts->backend, "sdl") == 0;
+
+    // the other backends have no window to map, tablets still use the screen
+    if (opts->absolute && !sdl) {
+        printf("Absolute mode only works with the sdl backend\n");
+    }
+
+    if (opts->target_screen[0] != '\0' && opts->target_desktop[0] == '\0') {
+        printf("The target screen needs the target desktop size too\n");
+        return -1;
+    }
+
+    if (opts->target_desktop[0] != '\0') {
+        if (parse_geometry(opts->target_desktop, &desktop, 0) < 0) {
+            printf("Invalid target desktop %s, expected WIDTHxHEIGHT\n", opts->target_desktop);
+            return -1;
+        }
+        absolute_desktop[0] = desktop.w;
+        absolute_desktop[1] = desktop.h;
+        absolute_screen = desktop;
+    }
+
+    if (opts->target_screen[0] != '\0') {
+        if (parse_geometry(opts->target_screen, &absolute_screen, 1) < 0) {
+            printf("Invalid target screen %s, expected WIDTHxHEIGHT+X+Y\n", opts->target_screen);
+            return -1;
+        }
+        if (absolute_screen.x + absolute_screen.w > absolute_desktop[0] ||
+                absolute_screen.y + absolute_screen.h > absolute_desktop[1]) {
+            printf("The target screen %s is outside the desktop\n", opts->target_screen);
+            return -1;
+        }
+    }
+
+    absolute = opts->absolute && sdl;
+    return 0;
+}
+
+// where a point is on the target, from 0 to 1 across the target screen
+void area_to_target(double x, double y, uint16_t position[2])
+{
+    double point[2] = { x, y };
+    int origin[2] = { absolute_screen.x, absolute_screen.y };
+    int screen[2] = { absolute_screen.w, absolute_screen.h };
+
+    for (int i = 0; i < 2; i++) {
+        double clamped = point[i] < 0 ? 0 : point[i] > 1 ? 1 : point[i];
+        double pixel = origin[i] + clamped * (screen[i] - 1);
+        position[i] = absolute_desktop[i] > 1 ?
+            (uint16_t) (pixel * 32767 / (absolute_desktop[i] - 1) + 0.5) : 0;
+    }
+}
+
+// where a point in the window is on the target, the window's corners
+// being the corners of the target screen
+void window_to_target(SDL_Window *window, int x, int y, uint16_t position[2])
+{
+    int width, height;
+
+    SDL_GetWindowSize(window, &width, &height);
+    area_to_target(
+        width > 1 ? (double) x / (width - 1) : 0,
+        height > 1 ? (double) y / (height - 1) : 0,
+        position);
+}
+
+// the last pen packet sent, to lift the pen where it was
+pen_packet pen;
+
+// the tablet's area is mapped onto the target screen
+void send_pen(const pen_state *state)
+{
+    uint16_t at[2];
+    int pressure = (int) (state->pressure * 4095 + 0.5);
+    int tilt_x = state->tilt_x < -90 ? -90 : state->tilt_x > 90 ? 90 : state->tilt_x;
+    int tilt_y = state->tilt_y < -90 ? -90 : state->tilt_y > 90 ? 90 : state->tilt_y;
+
+    area_to_target(state->x, state->y, at);
+
+    pen.type = 13; // 13 == pen
+    pen.version = 1;
+    pen.flags = state->flags;
+    pen.x[0] = at[0] & 0xff;
+    pen.x[1] = at[0] >> 8;
+    pen.y[0] = at[1] & 0xff;
+    pen.y[1] = at[1] >> 8;
+    pen.pressure[0] = pressure & 0xff;
+    pen.pressure[1] = pressure >> 8;
+    pen.tilt_x = tilt_x;
+    pen.tilt_y = tilt_y;
+
+    send_packet(&pen, sizeof(pen));
+}
+
+void release_pen(void)
+{
+    if (pen.flags) {
+        pen.flags = 0;
+        pen.pressure[0] = 0;
+        pen.pressure[1] = 0;
+        send_packet(&pen, sizeof(pen));
+    }
+}
+
+// mouse movement collected but not sent yet, see --motion-interval. in
+// absolute mode, the position to go to instead
+int pending_motion[2] = { 0, 0 };
+uint16_t position[2] = { 0, 0 };
+int pending_position = 0;
+Uint32 last_motion_flush = 0;
+int motion_split = 0; // what's pending is the rest of a split, see --split-delay
+
+// buttons and the wheel, at the last position in absolute mode. the
+// absolute pointer is a device of its own on the target, its buttons
+// have to be released there too
+void send_buttons(uint8_t buttons, int8_t vertical, int8_t horizontal)
+{
+    if (absolute) {
+        send_mouse_to(buttons, position[0], position[1], vertical, horizontal);
+    } else {
+        send_mouse(buttons, 0, 0, vertical, horizontal);
+    }
+}
+
+int clamp_delta(int delta)
+{
+    return delta > 127 ? 127 : delta < -127 ? -127 : delta;
+}
+
+// send the collected movement, in several packets if it doesn't fit in one.
+// with split_delay only the first one goes now, so a fast flick doesn't
+// overflow the device's receive queue
+void flush_motion(uint8_t buttons, int split_delay)
+{
+    if (pending_position) {
+        send_mouse_to(buttons, position[0], position[1], 0, 0);
+        pending_position = 0;
+    }
+
+    while (pending_motion[0] || pending_motion[1]) {
+        int x = clamp_delta(pending_motion[0]);
+        int y = clamp_delta(pending_motion[1]);
+
+        send_mouse(buttons, x, y, 0, 0);
+        pending_motion[0] -= x;
+        pending_motion[1] -= y;
+
+        if (split_delay) {
+            break;
+        }
+    }
+
+    motion_split = pending_motion[0] || pending_motion[1];
+    last_motion_flush = SDL_GetTicks();
+}
+
+// ms until the pending movement is due
+int motion_wait(const options *opts)
+{
+    int interval = motion_split ? opts->split_delay : opts->motion_interval;
+    Uint32 since = SDL_GetTicks() - last_motion_flush;
+
+    return since >= (Uint32) interval ? 0 : interval - (int) since;
+}
+
+// larger files would take ages to type anyway
+#define MAX_TYPE_FILE_SIZE (64 * 1024)
+
+typing_state typing;
+
+// start typing text on the target, replacing anything still being typed
+void start_typing(const char *text)
+{
+    free(typing.text);
+    typing.text = strdup(text);
+    typing.len = strlen(text);
+    typing.pos = 0;
+    typing.next = SDL_GetTicks();
+}
+
+// stop typing, and send the keys held down locally again
+void stop_typing(const keyboard_state *keyboard)
+{
+    free(typing.text);
+    typing.text = NULL;
+    send_keyboard(keyboard);
+}
+
+// percentage of the text typed so far
+int typing_progress(void)
+{
+    return typing.len ? typing.pos * 100 / typing.len : 100;
+}
+
+// press and release each key in turn, with nothing else held down. a dead
+// key goes right before the key it changes
+void send_keystrokes(const keystroke *keys, int count)
+{
+    keyboard_state state;
+
+    for (int i = 0; i < count; i++) {
+        memset(&state, 0, sizeof(state));
+        state.keys[0] = keys[i].keycode;
+        state.modifiers = keys[i].modifiers;
+        send_keyboard(&state);
+        memset(&state, 0, sizeof(state));
+        send_keyboard(&state);
+    }
+}
+
+// type the next character if it's time for it.
+// returns 1 if the progress percentage changed
+int type_next(const keyboard_state *keyboard, int delay)
+{
+    keystroke keys[2];
+    int progress = typing_progress();
+    int count = -1;
+
+    if (typing.text == NULL || !SDL_TICKS_PASSED(SDL_GetTicks(), typing.next)) {
+        return 0;
+    }
+
+    // skip what can't be typed on the target layout, e.g. \r of \r\n
+    while (typing.pos < typing.len && count < 0) {
+        count = char_to_keys(active_layout(), next_char(typing.text, typing.len, &typing.pos), keys);
+    }
+
+    if (count < 0) {
+        stop_typing(keyboard);
+        return 1;
+    }
+
+    send_keystrokes(keys, count);
+
+    typing.next = SDL_GetTicks() + delay;
+
+    return typing_progress() != progress;
+}
+
+// type a text file dropped on the window.
+// returns -1 if it can't be read or doesn't look like text
+int type_file(const char *path)
+{
+    char *text;
+    long len;
+    FILE *f;
+
+    f = fopen(path, "rb");
+    if (f == NULL) {
+        printf("Could not open %s\n", path);
+        return -1;
+    }
+
+    fseek(f, 0, SEEK_END);
+    len = ftell(f);
+    fseek(f, 0, SEEK_SET);
+
+    if (len < 0 || len > MAX_TYPE_FILE_SIZE) {
+        printf("%s is too large to type\n", path);
+        fclose(f);
+        return -1;
+    }
+
+    text = malloc(len + 1);
+    if (text == NULL || (long) fread(text, 1, len, f) != len) {
+        printf("Could not read %s\n", path);
+        free(text);
+        fclose(f);
+        return -1;
+    }
+    text[len] = '\0';
+    fclose(f);
+
+    if ((long) strlen(text) != len) {
+        printf("%s is not a text file\n", path);
+        free(text);
+        return -1;
+    }
+
+    printf("Typing %s, RCTRL-C to cancel\n", path);
+    start_typing(text);
+    free(text);
+
+    return 0;
+}
+
+// keys that autofire while held, by hid usage id
+uint8_t turbo[256];
+
+// the next key pressed toggles turbo for it
+int turbo_pick = 0;
+
+// turbo keys from the config file, e.g. "Z, Space".
+// returns -1 if a key is unknown
+int set_turbo_keys(const char *names)
+{
+    char list[256];
+    char *name;
+
+    snprintf(list, sizeof(list), "%s", names);
+
+    for (name = strtok(list, ","); name != NULL; name = strtok(NULL, ",")) {
+        SDL_Scancode scancode;
+
+        while (*name == ' ') {
+            name++;
+        }
+        scancode = SDL_GetScancodeFromName(name);
+        if (scancode == SDL_SCANCODE_UNKNOWN || scancode > 0xff) {
+            printf("Unknown turbo key %s\n", name);
+            return -1;
+        }
+        turbo[scancode] = 1;
+    }
+
+    return 0;
+}
+
+// held turbo keys are released and pressed again, rate times a second.
+// returns the ms until the next change, or -1 if no turbo keys are held
+int turbo_next(const keyboard_state *keyboard, int rate)
+{
+    static Uint32 next = 0;
+    static int released = 0;
+    keyboard_state state = *keyboard;
+    Uint32 now = SDL_GetTicks();
+    int held = 0;
+
+    for (int i = 0; i < 6; i++) {
+        if (state.keys[i] && turbo[state.keys[i]]) {
+            held = 1;
+            if (!released) {
+                state.keys[i] = 0;
+            }
+        }
+    }
+
+    if (!held) {
+        released = 0;
+        next = now + 500 / rate;
+        return -1;
+    }
+
+    if (SDL_TICKS_PASSED(now, next)) {
+        send_keyboard(&state);
+        released = !released;
+        next = now + 500 / rate;
+    }
+
+    return next - now;
+}
+
+// the last key pressed, repeated by us while it's held
+uint8_t repeat_key = 0;
+Uint32 repeat_next_time;
+
+// client side key repeat: the key is released and pressed again.
+// returns the ms until the next repeat, or -1 if nothing is repeating
+int repeat_next(const keyboard_state *keyboard, int rate)
+{
+    keyboard_state state = *keyboard;
+    Uint32 now = SDL_GetTicks();
+    int held = 0;
+
+    for (int i = 0; i < 6; i++) {
+        if (repeat_key && state.keys[i] == repeat_key) {
+            state.keys[i] = 0;
+            held = 1;
+        }
+    }
+
+    if (!held) {
+        repeat_key = 0;
+        return -1;
+    }
+
+    if (SDL_TICKS_PASSED(now, repeat_next_time)) {
+        send_keyboard(&state);
+        send_keyboard(keyboard);
+        repeat_next_time = now + 1000 / rate;
+    }
+
+    return repeat_next_time - now;
+}
+
+// keys pressed after the leader key, while waiting for a macro to match
+char leader_sequence[16];
+int leader_active = 0;
+
+// add a key pressed after the leader key to the sequence, and type the
+// macro if it matches one.
+// returns 1 while more keys are needed
+int leader_next(const options *opts, SDL_Scancode scancode)
+{
+    const char *name = SDL_GetScancodeName(scancode);
+    size_t len = strlen(leader_sequence);
+    int partial = 0;
+
+    // sequences are made of letters and digits
+    if (strlen(name) != 1 || len + 1 >= sizeof(leader_sequence)) {
+        return 0;
+    }
+    leader_sequence[len] = tolower((unsigned char) name[0]);
+    leader_sequence[len + 1] = '\0';
+
+    for (int i = 0; i < opts->leader_macro_count; i++) {
+        const leader_macro *macro = &opts->leader_macros[i];
+
+        if (strcmp(macro->sequence, leader_sequence) == 0) {
+            start_typing(macro->text);
+            return 0;
+        }
+        if (strncmp(macro->sequence, leader_sequence, len + 1) == 0) {
+            partial = 1;
+        }
+    }
+
+    if (!partial) {
+        printf("No leader macro for %s\n", leader_sequence);
+    }
+
+    return partial;
+}
+
+// characters typed after the compose key, from the text input so they
+// come out as on the local layout
+char compose_sequence[16];
+int compose_active = 0;
+
+// add text typed after the compose key to the sequence, and type the
+// character it makes on the target once there are two.
+// returns 1 while more are needed
+int compose_next(const keyboard_state *keyboard, const char *text)
+{
+    size_t used = strlen(compose_sequence);
+    size_t len;
+    size_t pos = 0;
+    uint32_t first, second, c;
+    keystroke keys[2];
+    int count;
+
+    snprintf(compose_sequence + used, sizeof(compose_sequence) - used, "%s", text);
+    len = strlen(compose_sequence);
+
+    first = next_char(compose_sequence, len, &pos);
+    if (pos >= len) {
+        return 1;
+    }
+    second = next_char(compose_sequence, len, &pos);
+
+    c = compose(first, second);
+    if (c == 0) {
+        printf("No compose sequence for %s\n", compose_sequence);
+        return 0;
+    }
+
+    count = char_to_keys(active_layout(), c, keys);
+    if (count < 0) {
+        printf("Compose %s can't be typed on the %s layout\n", compose_sequence, active_layout()->name);
+        return 0;
+    }
+
+    // the keys held down locally go down again afterwards
+    send_keystrokes(keys, count);
+    send_keyboard(keyboard);
+
+    return 0;
+}
+
+void update_title(SDL_Window *window)
+{
+    char title[1024];
+    size_t len;
+
+    len = snprintf(title, sizeof(title), "Keyboard/Mouse event sender%s",
+        grabbed ? "" : " (input released)");
+
+    if (typing.text != NULL) {
+        len += snprintf(title + len, sizeof(title) - len, " (typing %d%%)", typing_progress());
+    }
+
+    if (leader_active) {
+        len += snprintf(title + len, sizeof(title) - len, " (leader %s)", leader_sequence);
+    }
+
+    if (compose_active) {
+        len += snprintf(title + len, sizeof(title) - len, " (compose %s)", compose_sequence);
+    }
+
+    if (media_all_local) {
+        len += snprintf(title + len, sizeof(title) - len, " (media keys local)");
+    }
+
+    if (macro.recording) {
+        len += snprintf(title + len, sizeof(title) - len, " (recording macro)");
+    } else if (macro.playing != -1) {
+        len += snprintf(title + len, sizeof(title) - len, " (playing macro)");
+    }
+
+    if (show_status) {
+        len += snprintf(title + len, sizeof(title) - len, " %u packets/s %u errors/s",
+            packet_rate, error_rate);
+    }
+
+    for (int i = 0; i < target_count && len < sizeof(title); i++) {
+        device_state *device = &targets[i].device;
+        char rtt[64] = "";
+
+        // min/avg/max round trip time
+        if (device->connected && device->rtt_count) {
+            snprintf(rtt, sizeof(rtt), " %.1f/%.1f/%.1f ms",
+                device->rtt_min,
+                device->rtt_sum / device->rtt_count,
+                device->rtt_max);
+        }
+
+        // failing sends
+        int errors = SDL_AtomicGet(&targets[i].send_errors);
+        if (show_status && errors) {
+            snprintf(rtt + strlen(rtt), sizeof(rtt) - strlen(rtt), " %d errors", errors);
+        }
+
+        len += snprintf(title + len, sizeof(title) - len, " [%s%s%s%s%s%s%s%s]",
+            active_target == i ? "*" : "",
+            target_count > 1 ? targets[i].name : "",
+            target_count > 1 ? ": " : "",
+            device->connected ? "connected" :
+                device->last_pong != 0 ? "reconnecting..." : "disconnected",
+            rtt,
+            (device->leds & 0x02) ? " CAPS" : "",
+            (device->leds & 0x01) ? " NUM" : "",
+            device->locks_differ ? " LOCKS DIFFER" : "");
+    }
+
+    SDL_SetWindowTitle(window, title);
+}
+
+// macros are saved per profile
+void macro_path(const options *opts, char *path, size_t len)
+{
+    char name[512];
+
+    if (opts->profile[0] != '\0') {
+        snprintf(name, sizeof(name), "macro-%s.txt", opts->profile);
+    } else {
+        snprintf(name, sizeof(name), "macro.txt");
+    }
+
+    config_file_path(name, path, len);
+}
+
+// one packet per line: milliseconds from the start, then the packet in hex
+int save_macro(const char *path)
+{
+    FILE *f = create_parent_dir(path) == 0 ? fopen(path, "w") : NULL;
+
+    if (f == NULL) {
+        printf("Could not write %s\n", path);
+        return -1;
+    }
+
+    fprintf(f, "# nethid macro: milliseconds from start, packet bytes in hex\n");
+    for (int i = 0; i < macro.count; i++) {
+        fprintf(f, "%u", macro.events[i].time);
+        for (int j = 0; j < macro.events[i].len; j++) {
+            fprintf(f, " %02x", macro.events[i].data[j]);
+        }
+        fprintf(f, "\n");
+    }
+
+    fclose(f);
+
+    return 0;
+}
+
+int load_macro(const char *path)
+{
+    char line[256];
+    FILE *f = fopen(path, "r");
+
+    if (f == NULL) {
+        printf("Could not open %s\n", path);
+        return -1;
+    }
+
+    macro.count = 0;
+    while (fgets(line, sizeof(line), f) != NULL && macro.count < MAX_MACRO_EVENTS) {
+        macro_event *event = &macro.events[macro.count];
+        char *s = line;
+        char *end;
+
+        if (*s == '#') {
+            continue;
+        }
+
+        event->time = strtoul(s, &end, 10);
+        if (end == s) {
+            continue;
+        }
+
+        event->len = 0;
+        for (s = end; event->len < sizeof(event->data); s = end) {
+            unsigned long byte = strtoul(s, &end, 16);
+            if (end == s) {
+                break;
+            }
+            event->data[event->len++] = byte;
+        }
+
+        if (event->len > 0) {
+            macro.count++;
+        }
+    }
+
+    fclose(f);
+
+    return 0;
+}
+
+// start or stop recording, the macro is saved when recording stops
+void toggle_recording(const options *opts)
+{
+    char path[1024];
+
+    if (macro.playing != -1) {
+        return;
+    }
+
+    if (!macro.recording) {
+        macro.count = 0;
+        macro.started = SDL_GetTicks();
+        macro.recording = 1;
+        printf("Recording macro\n");
+        return;
+    }
+
+    macro.recording = 0;
+    macro_path(opts, path, sizeof(path));
+    if (save_macro(path) == 0) {
+        printf("Saved %d events to %s\n", macro.count, path);
+    }
+}
+
+void start_playback(const options *opts)
+{
+    char path[1024];
+
+    if (macro.recording || macro.playing != -1) {
+        return;
+    }
+
+    macro_path(opts, path, sizeof(path));
+    if (load_macro(path) < 0) {
+        return;
+    }
+
+    macro.playing = 0;
+    macro.started = SDL_GetTicks();
+}
+
+// stop playback, and send the keys held down locally again
+void stop_playback(const keyboard_state *keyboard)
+{
+    macro.playing = -1;
+    send_keyboard(keyboard);
+}
+
+// send the macro events that are due.
+// returns 1 when playback finishes
+int play_next(const keyboard_state *keyboard)
+{
+    Uint32 now = SDL_GetTicks();
+
+    if (macro.playing == -1) {
+        return 0;
+    }
+
+    while (macro.playing < macro.count &&
+            now - macro.started >= macro.events[macro.playing].time) {
+        send_packet(macro.events[macro.playing].data, macro.events[macro.playing].len);
+        macro.playing++;
+    }
+
+    if (macro.playing >= macro.count) {
+        stop_playback(keyboard);
+        return 1;
+    }
+
+    return 0;
+}
+
+// scale a mouse delta, carrying the fraction over to the next one so that
+// slow movements aren't lost with a low sensitivity
+int scale_motion(int delta, double sensitivity, double *remainder)
+{
+    double scaled = delta * sensitivity + *remainder;
+    int whole = (int) scaled;
+
+    *remainder = scaled - whole;
+    return whole;
+}
+
+// how far two fingers move for one wheel step, of the window's size
+#define TOUCH_SCROLL_STEP 0.04
+
+// fingers on a touchscreen. one finger is the pointer with the left button
+// held, two scroll. once two have been down nothing else happens until all
+// are lifted, so lifting one of them doesn't start a drag
+typedef struct {
+    SDL_FingerID fingers[2];
+    float x[2];
+    float y[2];
+    int count;
+    int scrolling;
+    double remainder[2];
+} touch_state;
+
+touch_state touch;
+
+int find_finger(SDL_FingerID id)
+{
+    for (int i = 0; i < touch.count; i++) {
+        if (touch.fingers[i] == id) {
+            return i;
+        }
+    }
+
+    return -1;
+}
+
+// finger positions are from 0 to 1 across the window
+void touch_position(SDL_Window *window, float x, float y)
+{
+    int width, height;
+
+    SDL_GetWindowSize(window, &width, &height);
+    window_to_target(window, (int) (x * width), (int) (y * height), position);
+}
+
+void touch_down(SDL_Window *window, const SDL_TouchFingerEvent *finger, uint8_t buttons)
+{
+    // a third finger does nothing
+    if (touch.count == 2 || find_finger(finger->fingerId) != -1) {
+        return;
+    }
+
+    touch.fingers[touch.count] = finger->fingerId;
+    touch.x[touch.count] = finger->x;
+    touch.y[touch.count] = finger->y;
+    touch.count++;
+
+    if (touch.scrolling) {
+        return;
+    }
+
+    if (touch.count == 1) {
+        touch_position(window, finger->x, finger->y);
+        send_mouse_to(buttons | 0x01, position[0], position[1], 0, 0);
+    } else {
+        // let go of what the first finger was holding
+        send_mouse_to(buttons, position[0], position[1], 0, 0);
+        touch.scrolling = 1;
+    }
+}
+
+void touch_motion(
+    SDL_Window *window,
+    const SDL_TouchFingerEvent *finger,
+    uint8_t buttons,
+    const options *opts)
+{
+    int i = find_finger(finger->fingerId);
+    double speed = opts->scroll_speed / TOUCH_SCROLL_STEP;
+    double x, y;
+    int vertical, horizontal;
+
+    if (i == -1) {
+        return;
+    }
+
+    // the middle of the fingers moves half as much as one of them
+    x = (finger->x - touch.x[i]) / 2;
+    y = (finger->y - touch.y[i]) / 2;
+    touch.x[i] = finger->x;
+    touch.y[i] = finger->y;
+
+    if (!touch.scrolling) {
+        touch_position(window, finger->x, finger->y);
+        send_mouse_to(buttons | 0x01, position[0], position[1], 0, 0);
+        return;
+    }
+
+    if (touch.count < 2) {
+        return;
+    }
+
+    // the page follows the fingers, i.e. moving them down scrolls up
+    if (opts->swap_scroll) {
+        double swapped = y;
+        y = x;
+        x = swapped;
+    }
+    if (!opts->hscroll) {
+        x = 0;
+    }
+
+    touch.remainder[0] += y * (opts->invert_scroll ? -speed : speed);
+    touch.remainder[1] -= x * (opts->invert_hscroll ? -speed : speed);
+    vertical = (int) touch.remainder[0];
+    horizontal = (int) touch.remainder[1];
+    touch.remainder[0] -= vertical;
+    touch.remainder[1] -= horizontal;
+
+    if (vertical || horizontal) {
+        send_buttons(buttons, clamp_delta(vertical), clamp_delta(horizontal));
+    }
+}
+
+void touch_up(const SDL_TouchFingerEvent *finger, uint8_t buttons)
+{
+    int i = find_finger(finger->fingerId);
+
+    if (i == -1) {
+        return;
+    }
+
+    if (!touch.scrolling) {
+        send_mouse_to(buttons, position[0], position[1], 0, 0);
+    }
+
+    touch.count--;
+    touch.fingers[i] = touch.fingers[touch.count];
+    touch.x[i] = touch.x[touch.count];
+    touch.y[i] = touch.y[touch.count];
+
+    if (touch.count == 0) {
+        memset(&touch, 0, sizeof(touch));
+    }
+}
+
+// let go of the button held by a finger, the rest of the touch is ignored
+void release_touch(void)
+{
+    if (touch.count && !touch.scrolling) {
+        send_mouse_to(0, position[0], position[1], 0, 0);
+        touch.scrolling = 1;
+    }
+}
+
+// returns 1 if we think any keys or mouse buttons are held down on the target
+int anything_held(const keyboard_state *keyboard, uint8_t mouse_buttons)
+{
+    for (int i = 0; i < 6; i++) {
+        if (keyboard->keys[i]) {
+            return 1;
+        }
+    }
+
+    return keyboard->modifiers || mouse_buttons;
+}
+
+// release everything we have pressed on the target(s)
+void release_all(keyboard_state *keyboard, uint8_t *mouse_buttons)
+{
+    pending_motion[0] = 0;
+    pending_motion[1] = 0;
+    pending_position = 0;
+
+    memset(keyboard, 0, sizeof(*keyboard));
+    send_keyboard(keyboard);
+
+    *mouse_buttons = 0;
+    send_buttons(0, 0, 0);
+    release_touch();
+    release_pen();
+
+    send_consumer(0);
+}
+
+void send_gamepad(gamepad_state *state)
+{
+    uint32_t buttons = state->buttons;
+    int up = buttons & (1 << SDL_CONTROLLER_BUTTON_DPAD_UP);
+    int down = buttons & (1 << SDL_CONTROLLER_BUTTON_DPAD_DOWN);
+    int left = buttons & (1 << SDL_CONTROLLER_BUTTON_DPAD_LEFT);
+    int right = buttons & (1 << SDL_CONTROLLER_BUTTON_DPAD_RIGHT);
+
+    gamepad_packet packet;
+    packet.type = 3; // 3 == gamepad
+    packet.version = 1;
+    packet.x = state->axes[SDL_CONTROLLER_AXIS_LEFTX];
+    packet.y = state->axes[SDL_CONTROLLER_AXIS_LEFTY];
+    packet.z = state->axes[SDL_CONTROLLER_AXIS_RIGHTX];
+    packet.rz = state->axes[SDL_CONTROLLER_AXIS_RIGHTY];
+    packet.rx = state->axes[SDL_CONTROLLER_AXIS_TRIGGERLEFT];
+    packet.ry = state->axes[SDL_CONTROLLER_AXIS_TRIGGERRIGHT];
+
+    // d-pad goes to the hat switch, 1 == up, clockwise, 0 == centered
+    if (up && right) {
+        packet.hat = 2;
+    } else if (right && down) {
+        packet.hat = 4;
+    } else if (down && left) {
+        packet.hat = 6;
+    } else if (left && up) {
+        packet.hat = 8;
+    } else if (up) {
+        packet.hat = 1;
+    } else if (right) {
+        packet.hat = 3;
+    } else if (down) {
+        packet.hat = 5;
+    } else if (left) {
+        packet.hat = 7;
+    } else {
+        packet.hat = 0;
+    }
+
+    buttons &= ~((1 << SDL_CONTROLLER_BUTTON_DPAD_UP) |
+                 (1 << SDL_CONTROLLER_BUTTON_DPAD_DOWN) |
+                 (1 << SDL_CONTROLLER_BUTTON_DPAD_LEFT) |
+                 (1 << SDL_CONTROLLER_BUTTON_DPAD_RIGHT));
+
+    packet.buttons[0] = buttons & 0xff;
+    packet.buttons[1] = (buttons >> 8) & 0xff;
+    packet.buttons[2] = (buttons >> 16) & 0xff;
+    packet.buttons[3] = (buttons >> 24) & 0xff;
+
+    send_packet(&packet, sizeof(packet));
+}
+
+// print the stats once a second, and start counting again
+// returns 1 once a second, when packet_rate and error_rate are updated
+int report_stats(int print)
+{
+    Uint32 now = SDL_GetTicks();
+    Uint32 elapsed = now - stats.started;
+
+    if (elapsed < 1000) {
+        return 0;
+    }
+
+    stats.errors += SDL_AtomicSet(&send_failures, 0);
+
+    packet_rate = stats.packets * 1000 / elapsed;
+    error_rate = stats.errors * 1000 / elapsed;
+
+    if (print) {
+        printf("%u packets/s, %u bytes/s, %u errors",
+            packet_rate,
+            stats.bytes * 1000 / elapsed,
+            stats.errors);
+        if (stats.merged) {
+            printf(", %u merged", stats.merged);
+        }
+        for (int i = 0; i < PACKET_TYPE_COUNT; i++) {
+            if (stats.per_type[i]) {
+                printf(", %s %u", packet_type_names[i], stats.per_type[i]);
+            }
+        }
+        printf("\n");
+    }
+
+    memset(&stats, 0, sizeof(stats));
+    stats.started = now;
+
+    return 1;
+}
+
+// draw a row of boxes, lit for each bit set in mask
+void draw_indicators(SDL_Surface *surface, int x, int y, int count, uint32_t mask)
+{
+    for (int i = 0; i < count; i++) {
+        SDL_Rect box = { x + i * 20, y, 16, 16 };
+        Uint8 level = (mask & (1 << i)) ? 0xff : 0x60;
+
+        SDL_FillRect(surface, &box, SDL_MapRGB(surface->format, level, level, level));
+    }
+}
+
+// the window is darker when input isn't captured, and the status strip at
+// the top is green when all targets are connected, yellow when some and red
+// when none are.
+// below it are what we think is held down on the targets: modifiers, key
+// slots and mouse buttons
+void draw_window(SDL_Window *window, const keyboard_state *keyboard, uint8_t mouse_buttons)
+{
+    uint32_t keys = 0;
+
+    SDL_Surface *surface = SDL_GetWindowSurface(window);
+    Uint8 gray = grabbed ? 0x80 : 0x40;
+    int connected = 0;
+
+    SDL_FillRect(surface, NULL, SDL_MapRGB(surface->format, gray, gray, gray));
+
+    if (show_status) {
+        SDL_Rect strip = { 0, 0, surface->w, 8 };
+
+        for (int i = 0; i < target_count; i++) {
+            connected += targets[i].device.connected;
+        }
+
+        SDL_FillRect(surface, &strip, connected == target_count ?
+            SDL_MapRGB(surface->format, 0x00, 0xc0, 0x00) : connected ?
+            SDL_MapRGB(surface->format, 0xc0, 0xc0, 0x00) :
+            SDL_MapRGB(surface->format, 0xc0, 0x00, 0x00));
+    }
+
+    for (int i = 0; i < 6; i++) {
+        if (keyboard->keys[i]) {
+            keys |= 1 << i;
+        }
+    }
+
+    draw_indicators(surface, 16, 24, 8,
+        keyboard->modifiers | keyboard->latched | keyboard->locked);
+    draw_indicators(surface, 16 + 9 * 20, 24, 6, keys);
+    draw_indicators(surface, 16 + 16 * 20, 24, 5, mouse_buttons);
+
+    SDL_UpdateWindowSurface(window);
+}
+
+// parse a mac address, aa:bb:cc:dd:ee:ff or with dashes.
+// returns -1 if it isn't one
+int parse_mac(const char *text, uint8_t mac[6])
+{
+    unsigned int bytes[6];
+    char sep[5];
+    char end;
+
+    if (sscanf(text, "%2x%c%2x%c%2x%c%2x%c%2x%c%2x%c",
+            &bytes[0], &sep[0], &bytes[1], &sep[1], &bytes[2], &sep[2],
+            &bytes[3], &sep[3], &bytes[4], &sep[4], &bytes[5], &end) != 11) {
+        return -1;
+    }
+
+    for (int i = 0; i < 5; i++) {
+        if (sep[i] != sep[0] || (sep[i] != ':' && sep[i] != '-')) {
+            return -1;
+        }
+    }
+
+    for (int i = 0; i < 6; i++) {
+        mac[i] = bytes[i];
+    }
+
+    return 0;
+}
+
+// broadcast a wake on lan magic packet: six 0xff bytes, then the mac
+// address sixteen times.
+// returns -1 if it can't be sent
+int send_wake(const char *text)
+{
+    struct sockaddr_in addr;
+    uint8_t packet[6 + 16 * 6];
+    uint8_t mac[6];
+    int on = 1;
+    int fd;
+
+    if (parse_mac(text, mac) < 0) {
+        return -1;
+    }
+
+    memset(packet, 0xff, 6);
+    for (int i = 0; i < 16; i++) {
+        memcpy(packet + 6 + i * 6, mac, sizeof(mac));
+    }
+
+    fd = socket(AF_INET, SOCK_DGRAM, 0);
+    if (fd < 0) {
+        return -1;
+    }
+    setsockopt(fd, SOL_SOCKET, SO_BROADCAST, &on, sizeof(on));
+
+    bzero(&addr, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_BROADCAST);
+    addr.sin_port = htons(9); // discard, where wake on lan is usually sent
+
+    if (sendto(fd, packet, sizeof(packet), 0, (struct sockaddr *) &addr, sizeof(addr)) < 0) {
+        close(fd);
+        return -1;
+    }
+
+    close(fd);
+    printf("Sent a wake on lan packet to %s\n", text);
+
+    return 0;
+}
+
+void usage(const char *name)
+{
+    printf("Usage: %s [options] [host[:port]...]\n", name);
+    printf("\n");
+    printf("  -c, --config PATH   config file (default ~/.config/nethid/config.toml)\n");
+    printf("  -P, --profile NAME  use settings from [profile.NAME] in the config file\n");
+    printf("  -p, --port PORT     target port (default %d)\n", TARGET_PORT);
+    printf("  -4, --ipv4          only use ipv4 addresses\n");
+    printf("  -6, --ipv6          only use ipv6 addresses\n");
+    printf("  -r, --remap PATH    send keys as other keys, see remap.toml\n");
+    printf("  -s, --stats         print packet statistics every second\n");
+    printf("  -S, --sensitivity N mouse movement multiplier (default 1.0)\n");
+    printf("  --motion-interval MS\n");
+    printf("                      send mouse movement every MS ms at most, 0 to send\n");
+    printf("                      every event (default %d)\n", MOTION_INTERVAL_MS);
+    printf("  --split-delay MS    wait MS ms between the packets of movement too big\n");
+    printf("                      for one, instead of sending them all at once\n");
+    printf("  --scroll-speed N    mouse wheel multiplier (default 1.0)\n");
+    printf("  --max-pps N         send at most N packets per second, for devices that\n");
+    printf("                      drop packets under load, enough to keep up with\n");
+    printf("                      typing: 2000 / the typing delay at least\n");
+    printf("  --invert-scroll     reverse vertical scrolling (natural scrolling)\n");
+    printf("  --invert-hscroll    reverse horizontal scrolling\n");
+    printf("  --no-hscroll        don't send horizontal scrolling\n");
+    printf("  --swap-scroll       swap vertical and horizontal scrolling\n");
+    printf("  --absolute          send where the pointer is in the window, its\n");
+    printf("                      corners being the corners of the target screen\n");
+    printf("  --target-desktop WxH\n");
+    printf("                      size of the target's desktop, all screens together\n");
+    printf("  --target-screen WxH+X+Y\n");
+    printf("                      the screen in the desktop the window maps onto\n");
+    printf("  --repeat DELAY      repeat held keys after DELAY ms, for targets that\n");
+    printf("                      don't repeat them themselves\n");
+    printf("  --sticky-keys       tap a modifier to use it with the next key, tap\n");
+    printf("                      twice to keep it on\n");
+    printf("  --keep-awake S      move the mouse a bit after S seconds without input\n");
+    printf("  --release-timeout S release held keys after S seconds without input,\n");
+    printf("                      0 to never (default %d)\n", RELEASE_TIMEOUT);
+    printf("  --record PATH       save everything sent to PATH, for replaying with\n");
+    printf("                      keycli.py\n");
+    printf("  --audit PATH        add a line about every event sent to PATH, with\n");
+    printf("                      the number of keys held but not which ones\n");
+    printf("  --audit-keys        also log which keys, i.e. what was typed\n");
+    printf("  --backend NAME      read input from the sdl window, or with evdev\n");
+    printf("                      straight from /dev/input without a window system,\n");
+    printf("                      or with eventtap system wide on macos, or with\n");
+    printf("                      portal through the wayland InputCapture portal\n");
+    printf("  --local-media-keys LIST\n");
+    printf("                      media keys left to this machine, e.g. \"VolumeUp,\n");
+    printf("                      VolumeDown\", RCTRL-L does this for all of them\n");
+    printf("  --shared-media-keys LIST\n");
+    printf("                      media keys for both this machine and the target\n");
+    printf("  --full-capture      send alt-tab, the windows key, media keys and such\n");
+    printf("                      to the target too, with the backend that can\n");
+    printf("                      capture them here\n");
+    printf("  --wake              wake the target up with wake on lan first, and wait\n");
+    printf("                      for the devices to answer before capturing\n");
+    printf("  --wake-mac MAC      mac address of the target, for --wake and RCTRL-W\n");
+    printf("  --wake-wait S       seconds to wait after waking, 0 to not wait\n");
+    printf("                      (default %d)\n", WAKE_WAIT);
+    printf("  --lock-sync MODE    when caps, num or scroll lock differs on the target:\n");
+    printf("                      off, warn, or fix by pressing it there (default warn)\n");
+    printf("  -d, --type-delay MS delay between characters when typing (default %d)\n", TYPE_DELAY_MS);
+    printf("  --target-layout NAME keyboard layout of the target, to type text with\n");
+    printf("                      the right keys (default us)\n");
+    printf("  --calibrate-layout  type a test on the target to find out its layout\n");
+    printf("  -f, --fullscreen    cover the whole screen\n");
+    printf("  -b, --borderless    borderless window filling the desktop\n");
+    printf("  -h, --help          show this help\n");
+    printf("\n");
+    printf("Without hosts, devices on the local network are looked for and one of\n");
+    printf("them can be picked, %s is used if none are found. With several hosts,\n", TARGET_IP);
+    printf("input is sent to all of them (up to %d). IPv6 addresses with a port\n", MAX_TARGETS);
+    printf("are written as [address]:port.\n");
+}
+
+// long options without a short one
+enum {
+    OPTION_SCROLL_SPEED = 256,
+    OPTION_INVERT_SCROLL,
+    OPTION_INVERT_HSCROLL,
+    OPTION_NO_HSCROLL,
+    OPTION_SWAP_SCROLL,
+    OPTION_REPEAT,
+    OPTION_STICKY_KEYS,
+    OPTION_KEEP_AWAKE,
+    OPTION_RELEASE_TIMEOUT,
+    OPTION_RECORD,
+    OPTION_BACKEND,
+    OPTION_MOTION_INTERVAL,
+    OPTION_MAX_PPS,
+    OPTION_SPLIT_DELAY,
+    OPTION_AUDIT,
+    OPTION_AUDIT_KEYS,
+    OPTION_TARGET_LAYOUT,
+    OPTION_CALIBRATE_LAYOUT,
+    OPTION_ABSOLUTE,
+    OPTION_TARGET_DESKTOP,
+    OPTION_TARGET_SCREEN,
+    OPTION_FULL_CAPTURE,
+    OPTION_LOCAL_MEDIA_KEYS,
+    OPTION_SHARED_MEDIA_KEYS,
+    OPTION_LOCK_SYNC,
+    OPTION_WAKE,
+    OPTION_WAKE_MAC,
+    OPTION_WAKE_WAIT,
+};
+
+// returns -1 if the program should exit
+int parse_options(int argc, char *argv[], options *opts)
+{
+    static const struct option long_options[] = {
+        { "config", required_argument, NULL, 'c' },
+        { "profile", required_argument, NULL, 'P' },
+        { "port", required_argument, NULL, 'p' },
+        { "ipv4", no_argument, NULL, '4' },
+        { "ipv6", no_argument, NULL, '6' },
+        { "remap", required_argument, NULL, 'r' },
+        { "stats", no_argument, NULL, 's' },
+        { "sensitivity", required_argument, NULL, 'S' },
+        { "scroll-speed", required_argument, NULL, OPTION_SCROLL_SPEED },
+        { "invert-scroll", no_argument, NULL, OPTION_INVERT_SCROLL },
+        { "invert-hscroll", no_argument, NULL, OPTION_INVERT_HSCROLL },
+        { "no-hscroll", no_argument, NULL, OPTION_NO_HSCROLL },
+        { "swap-scroll", no_argument, NULL, OPTION_SWAP_SCROLL },
+        { "absolute", no_argument, NULL, OPTION_ABSOLUTE },
+        { "target-desktop", required_argument, NULL, OPTION_TARGET_DESKTOP },
+        { "target-screen", required_argument, NULL, OPTION_TARGET_SCREEN },
+        { "repeat", required_argument, NULL, OPTION_REPEAT },
+        { "sticky-keys", no_argument, NULL, OPTION_STICKY_KEYS },
+        { "keep-awake", required_argument, NULL, OPTION_KEEP_AWAKE },
+        { "release-timeout", required_argument, NULL, OPTION_RELEASE_TIMEOUT },
+        { "record", required_argument, NULL, OPTION_RECORD },
+        { "backend", required_argument, NULL, OPTION_BACKEND },
+        { "full-capture", no_argument, NULL, OPTION_FULL_CAPTURE },
+        { "local-media-keys", required_argument, NULL, OPTION_LOCAL_MEDIA_KEYS },
+        { "shared-media-keys", required_argument, NULL, OPTION_SHARED_MEDIA_KEYS },
+        { "lock-sync", required_argument, NULL, OPTION_LOCK_SYNC },
+        { "wake", no_argument, NULL, OPTION_WAKE },
+        { "wake-mac", required_argument, NULL, OPTION_WAKE_MAC },
+        { "wake-wait", required_argument, NULL, OPTION_WAKE_WAIT },
+        { "motion-interval", required_argument, NULL, OPTION_MOTION_INTERVAL },
+        { "max-pps", required_argument, NULL, OPTION_MAX_PPS },
+        { "split-delay", required_argument, NULL, OPTION_SPLIT_DELAY },
+        { "audit", required_argument, NULL, OPTION_AUDIT },
+        { "audit-keys", no_argument, NULL, OPTION_AUDIT_KEYS },
+        { "target-layout", required_argument, NULL, OPTION_TARGET_LAYOUT },
+        { "calibrate-layout", no_argument, NULL, OPTION_CALIBRATE_LAYOUT },
+        { "type-delay", required_argument, NULL, 'd' },
+        { "fullscreen", no_argument, NULL, 'f' },
+        { "borderless", no_argument, NULL, 'b' },
+        { "help", no_argument, NULL, 'h' },
+        { NULL, 0, NULL, 0 }
+    };
+    static const char *short_options = "c:P:p:46r:sS:d:fbh";
+    char config[1024];
+    char state[1024];
+    const char *profile = NULL;
+    int config_given = 0;
+    int c;
+
+    default_options(opts);
+    config_file_path("config.toml", config, sizeof(config));
+
+    // the config file is applied first, so that other options can override it
+    opterr = 0;
+    while ((c = getopt_long(argc, argv, short_options, long_options, NULL)) != -1) {
+        if (c == 'c') {
+            snprintf(config, sizeof(config), "%s", optarg);
+            config_given = 1;
+        } else if (c == 'P') {
+            profile = optarg;
+        }
+    }
+    opterr = 1;
+    optind = 1;
+
+    if (profile != NULL) {
+        snprintf(opts->profile, sizeof(opts->profile), "%s", profile);
+    }
+
+    // window size from the last run, the config file can override it
+    config_file_path("window.toml", state, sizeof(state));
+    if (load_config(state, NULL, 0, opts) < 0) {
+        return -1;
+    }
+
+    if (load_config(config, profile, config_given, opts) < 0) {
+        return -1;
+    }
+
+    while ((c = getopt_long(argc, argv, short_options, long_options, NULL)) != -1) {
+        switch (c) {
+            case 'c':
+            case 'P':
+                break;
+            case 'p':
+                opts->port = atoi(optarg);
+                break;
+            case '4':
+                opts->ip_version = 4;
+                break;
+            case '6':
+                opts->ip_version = 6;
+                break;
+            case 'r':
+                snprintf(opts->remap, sizeof(opts->remap), "%s", optarg);
+                break;
+            case 's':
+                opts->stats = 1;
+                break;
+            case 'S':
+                opts->sensitivity = atof(optarg);
+                break;
+            case OPTION_SCROLL_SPEED:
+                opts->scroll_speed = atof(optarg);
+                break;
+            case OPTION_INVERT_SCROLL:
+                opts->invert_scroll = 1;
+                break;
+            case OPTION_INVERT_HSCROLL:
+                opts->invert_hscroll = 1;
+                break;
+            case OPTION_NO_HSCROLL:
+                opts->hscroll = 0;
+                break;
+            case OPTION_SWAP_SCROLL:
+                opts->swap_scroll = 1;
+                break;
+            case OPTION_ABSOLUTE:
+                opts->absolute = 1;
+                break;
+            case OPTION_TARGET_DESKTOP:
+                snprintf(opts->target_desktop, sizeof(opts->target_desktop), "%s", optarg);
+                break;
+            case OPTION_TARGET_SCREEN:
+                snprintf(opts->target_screen, sizeof(opts->target_screen), "%s", optarg);
+                break;
+            case OPTION_REPEAT:
+                opts->repeat_delay = atoi(optarg);
+                break;
+            case OPTION_STICKY_KEYS:
+                opts->sticky_keys = 1;
+                break;
+            case OPTION_KEEP_AWAKE:
+                opts->keep_awake = atoi(optarg);
+                break;
+            case OPTION_RELEASE_TIMEOUT:
+                opts->release_timeout = atoi(optarg);
+                break;
+            case OPTION_RECORD:
+                snprintf(opts->record, sizeof(opts->record), "%s", optarg);
+                break;
+            case OPTION_MOTION_INTERVAL:
+                opts->motion_interval = atoi(optarg);
+                break;
+            case OPTION_MAX_PPS:
+                opts->max_pps = atoi(optarg);
+                break;
+            case OPTION_SPLIT_DELAY:
+                opts->split_delay = atoi(optarg);
+                break;
+            case OPTION_AUDIT:
+                snprintf(opts->audit, sizeof(opts->audit), "%s", optarg);
+                break;
+            case OPTION_AUDIT_KEYS:
+                opts->audit_keys = 1;
+                break;
+            case OPTION_BACKEND:
+                snprintf(opts->backend, sizeof(opts->backend), "%s", optarg);
+                break;
+            case OPTION_FULL_CAPTURE:
+                opts->full_capture = 1;
+                break;
+            case OPTION_LOCAL_MEDIA_KEYS:
+                snprintf(opts->local_media_keys, sizeof(opts->local_media_keys), "%s", optarg);
+                break;
+            case OPTION_SHARED_MEDIA_KEYS:
+                snprintf(opts->shared_media_keys, sizeof(opts->shared_media_keys), "%s", optarg);
+                break;
+            case OPTION_LOCK_SYNC:
+                snprintf(opts->lock_sync, sizeof(opts->lock_sync), "%s", optarg);
+                break;
+            case OPTION_WAKE:
+                opts->wake = 1;
+                break;
+            case OPTION_WAKE_MAC:
+                snprintf(opts->wake_mac, sizeof(opts->wake_mac), "%s", optarg);
+                break;
+            case OPTION_WAKE_WAIT:
+                opts->wake_wait = atoi(optarg);
+                break;
+            case 'd':
+                opts->type_delay = atoi(optarg);
+                break;
+            case OPTION_TARGET_LAYOUT:
+                snprintf(opts->target_layout, sizeof(opts->target_layout), "%s", optarg);
+                break;
+            case OPTION_CALIBRATE_LAYOUT:
+                opts->calibrate_layout = 1;
+                break;
+            case 'f':
+                opts->fullscreen = 1;
+                break;
+            case 'b':
+                opts->borderless = 1;
+                break;
+            case 'h':
+            default:
+                usage(argv[0]);
+                return -1;
+        }
+    }
+
+    if (argc - optind > MAX_TARGETS) {
+        printf("Too many hosts, at most %d supported\n", MAX_TARGETS);
+        return -1;
+    }
+
+    if (optind < argc) {
+        opts->default_host = 0;
+        opts->host_count = 0;
+        while (optind < argc) {
+            snprintf(
+                opts->hosts[opts->host_count],
+                sizeof(opts->hosts[0]),
+                "%s",
+                argv[optind++]);
+            opts->host_count++;
+        }
+    }
+
+    if (opts->port <= 0 || opts->port > 65535) {
+        printf("Invalid port\n");
+        return -1;
+    }
+
+    if (opts->sensitivity <= 0) {
+        printf("Invalid sensitivity\n");
+        return -1;
+    }
+
+    if (opts->scroll_speed <= 0) {
+        printf("Invalid scroll speed\n");
+        return -1;
+    }
+
+    if (opts->turbo_rate <= 0 || opts->turbo_rate > 50) {
+        printf("Invalid turbo rate, 1 to 50 presses per second\n");
+        return -1;
+    }
+
+    if (opts->repeat_delay < 0 || opts->repeat_rate <= 0 || opts->repeat_rate > 50) {
+        printf("Invalid key repeat delay or rate\n");
+        return -1;
+    }
+
+    if (opts->keep_awake < 0) {
+        printf("Invalid keep awake interval\n");
+        return -1;
+    }
+
+    if (strcmp(opts->backend, "sdl") != 0 &&
+            strcmp(opts->backend, "evdev") != 0 &&
+            strcmp(opts->backend, "eventtap") != 0 &&
+            strcmp(opts->backend, "portal") != 0) {
+        printf("Unknown backend %s\n", opts->backend);
+        return -1;
+    }
+
+    if (strcmp(opts->lock_sync, "off") != 0 &&
+            strcmp(opts->lock_sync, "warn") != 0 &&
+            strcmp(opts->lock_sync, "fix") != 0) {
+        printf("Unknown lock sync mode %s, expected off, warn or fix\n", opts->lock_sync);
+        return -1;
+    }
+
+    if (opts->wake_mac[0] != '\0') {
+        uint8_t mac[6];
+        if (parse_mac(opts->wake_mac, mac) < 0) {
+            printf("Invalid mac address %s\n", opts->wake_mac);
+            return -1;
+        }
+    } else if (opts->wake) {
+        printf("Waking needs the mac address of the target, set wake_mac in the profile\n");
+        return -1;
+    }
+
+    if (opts->wake_wait < 0) {
+        printf("Invalid wake wait\n");
+        return -1;
+    }
+
+    if (opts->max_pps < 0) {
+        printf("Invalid packet rate\n");
+        return -1;
+    }
+
+    if (opts->split_delay < 0) {
+        printf("Invalid split delay\n");
+        return -1;
+    }
+
+    if (opts->motion_interval < 0) {
+        printf("Invalid motion interval\n");
+        return -1;
+    }
+
+    if (opts->release_timeout < 0) {
+        printf("Invalid release timeout\n");
+        return -1;
+    }
+
+    if (opts->type_delay < 0) {
+        printf("Invalid typing delay\n");
+        return -1;
+    }
+
+    // typing presses and releases a key every type_delay ms, slower sending
+    // backs the queue up until typed keys are lost
+    if (opts->max_pps > 0 && opts->max_pps * opts->type_delay < 2000) {
+        printf("--type-delay %d types faster than --max-pps %d sends, use --type-delay %d or more\n",
+            opts->type_delay, opts->max_pps, (2000 + opts->max_pps - 1) / opts->max_pps);
+        return -1;
+    }
+
+    if (find_layout(opts->target_layout) == NULL) {
+        printf("Unknown target layout %s, these are known:\n", opts->target_layout);
+        list_layouts();
+        return -1;
+    }
+
+    if (opts->width <= 0 || opts->height <= 0) {
+        printf("Invalid window size\n");
+        return -1;
+    }
+
+    return 0;
+}
+
+// with --full-capture, switch to the backend that keeps system shortcuts
+// and media keys from the local desktop. on x11, and on windows where sdl
+// uses a keyboard hook for it, the sdl window's keyboard grab already does
+void choose_full_capture(options *opts)
+{
+    const char *backend = NULL;
+
+    if (!opts->full_capture || strcmp(opts->backend, "sdl") != 0) {
+        return;
+    }
+
+#ifdef __APPLE__
+    backend = "eventtap";
+#else
+    // xwayland can't keep shortcuts from the compositor
+    if (getenv("WAYLAND_DISPLAY") != NULL) {
+#ifdef HAVE_PORTAL
+        backend = "portal";
+#else
+        printf("The compositor may keep some shortcuts, hidgui needs to be built\n");
+        printf("with libportal and libei to capture them on wayland\n");
+#endif
+    }
+#endif
+
+    if (backend != NULL) {
+        printf("Capturing everything with the %s backend\n", backend);
+        snprintf(opts->backend, sizeof(opts->backend), "%s", backend);
+    }
+}
+
+// type a test on the target and ask what came out, to find out the layout
+// set up there.
+// returns -1 if it's none of the known ones
+int calibrate_layout(const options *opts)
+{
+    const keystroke *keys;
+    size_t count = layout_probe(&keys);
+    const keyboard_layout *layout;
+    char seen[256];
+
+    if (target_count != 1) {
+        printf("Calibrate one host at a time\n");
+        return -1;
+    }
+
+    printf("Put the cursor in an empty text field on %s and press enter here\n", targets[0].name);
+    if (fgets(seen, sizeof(seen), stdin) == NULL) {
+        return -1;
+    }
+
+    send_keystrokes(keys, count);
+    flush_packets();
+
+    printf("Type what appeared there: ");
+    fflush(stdout);
+    if (fgets(seen, sizeof(seen), stdin) == NULL) {
+        return -1;
+    }
+
+    layout = match_layout(seen);
+    if (layout == NULL) {
+        printf("That's none of the known layouts, these are known:\n");
+        list_layouts();
+        return -1;
+    }
+
+    printf("%s has the %s layout (%s), add this to the config file:\n\n",
+        targets[0].name, layout->name, layout->description);
+    if (opts->profile[0] != '\0') {
+        printf("[profile.%s]\n", opts->profile);
+    } else {
+        printf("[host.\"%s\"]\n", targets[0].name);
+    }
+    printf("target_layout = \"%s\"\n", layout->name);
+
+    return 0;
+}
+
+#define MDNS_SERVICE "_nethid._udp.local"
+
+// reads the possibly compressed name at offset into name, dotted.
+// returns the offset after it, or -1 if it doesn't fit the packet
+static int dns_name(const uint8_t *packet, int len, int offset, char *name, size_t size)
+{
+    size_t used = 0;
+    int end = -1;
+
+    name[0] = '\0';
+    // a broken packet could point back at itself, so give up at some point
+    for (int i = 0; i < 128; i++) {
+        int length;
+
+        if (offset >= len) {
+            return -1;
+        }
+        length = packet[offset];
+        if ((length & 0xc0) == 0xc0) {
+            if (offset + 1 >= len) {
+                return -1;
+            }
+            if (end < 0) {
+                end = offset + 2;
+            }
+            offset = ((length & 0x3f) << 8) | packet[offset + 1];
+            continue;
+        }
+        offset++;
+        if (length == 0) {
+            return end < 0 ? offset : end;
+        }
+        if (offset + length > len || used + length + 2 > size) {
+            return -1;
+        }
+        if (used > 0) {
+            name[used++] = '.';
+        }
+        memcpy(name + used, packet + offset, length);
+        used += length;
+        name[used] = '\0';
+        offset += length;
+    }
+    return -1;
+}
+
+// finds the srv record of a nethid service in an mdns answer. returns 1
+// with the instance name and port filled in, 0 if there's none
+static int mdns_service(const uint8_t *packet, int len, char *instance, size_t size, int *port)
+{
+    size_t service_len = strlen(MDNS_SERVICE);
+    char name[256];
+    int offset = 12;
+    int questions;
+    int records;
+
+    if (len < 12) {
+        return 0;
+    }
+    questions = (packet[4] << 8) | packet[5];
+    records = ((packet[6] << 8) | packet[7]) + ((packet[8] << 8) | packet[9])
+        + ((packet[10] << 8) | packet[11]);
+
+    for (int i = 0; i < questions; i++) {
+        offset = dns_name(packet, len, offset, name, sizeof(name));
+        if (offset < 0) {
+            return 0;
+        }
+        offset += 4; // type, class
+    }
+
+    for (int i = 0; i < records; i++) {
+        size_t name_len;
+        int type;
+        int length;
+
+        offset = dns_name(packet, len, offset, name, sizeof(name));
+        if (offset < 0 || offset + 10 > len) {
+            return 0;
+        }
+        type = (packet[offset] << 8) | packet[offset + 1];
+        length = (packet[offset + 8] << 8) | packet[offset + 9];
+        offset += 10; // type, class, ttl, length
+        if (offset + length > len) {
+            return 0;
+        }
+
+        // srv: priority, weight, port, target
+        name_len = strlen(name);
+        if (type == 33 && length >= 6 && name_len > service_len + 1
+            && name[name_len - service_len - 1] == '.'
+            && strcasecmp(name + name_len - service_len, MDNS_SERVICE) == 0) {
+            snprintf(instance, size, "%.*s", (int) (name_len - service_len - 1), name);
+            *port = (packet[offset + 4] << 8) | packet[offset + 5];
+            return 1;
+        }
+        offset += length;
+    }
+    return 0;
+}
+
+// find devices with a broadcast and over mdns, and let the user pick one
+// of them. returns -1 if none were found or picked
+int pick_device(int port, char *host, size_t len)
+{
+    struct sockaddr_in addr;
+    char names[MAX_TARGETS][64];
+    char ips[MAX_TARGETS][INET_ADDRSTRLEN];
+    int ports[MAX_TARGETS];
+    uint8_t packet[2] = { 10, 1 }; // 10 == discover
+    // a ptr query for the service, from a port other than 5353 so it's
+    // answered straight back to us
+    static const uint8_t query[] = {
+        0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+        7, '_', 'n', 'e', 't', 'h', 'i', 'd', 4, '_', 'u', 'd', 'p',
+        5, 'l', 'o', 'c', 'a', 'l', 0,
+        0, 12, 0, 1, // ptr, in
+    };
+    Uint32 started = SDL_GetTicks();
+    int count = 0;
+    int on = 1;
+    int choice;
+    int fd;
+
+    fd = socket(AF_INET, SOCK_DGRAM, 0);
+    if (fd < 0) {
+        return -1;
+    }
+    setsockopt(fd, SOL_SOCKET, SO_BROADCAST, &on, sizeof(on));
+
+    bzero(&addr, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_BROADCAST);
+    addr.sin_port = htons(port);
 
-    sockfd = socket(AF_INET, SOCK_DGRAM, 0);
+    printf("Looking for devices...\n");
+    if (sendto(fd, packet, sizeof(packet), 0, (struct sockaddr *) &addr, sizeof(addr)) < 0) {
+        close(fd);
+        return -1;
+    }
 
-    bzero(&servaddr, sizeof(servaddr));
+    // without mdns on the network the broadcast still finds them
+    addr.sin_addr.s_addr = inet_addr("224.0.0.251");
+    addr.sin_port = htons(5353);
+    sendto(fd, query, sizeof(query), 0, (struct sockaddr *) &addr, sizeof(addr));
 
-    servaddr.sin_family = AF_INET;
-    servaddr.sin_addr.s_addr = inet_addr(TARGET_IP);
-    servaddr.sin_port = htons(TARGET_PORT);
+    // collect replies until the timeout, one entry per address
+    while (count < MAX_TARGETS && SDL_GetTicks() - started < DISCOVER_TIMEOUT_MS) {
+        Uint32 left = DISCOVER_TIMEOUT_MS - (SDL_GetTicks() - started);
+        struct timeval timeout = { left / 1000, (left % 1000) * 1000 };
+        socklen_t addr_len = sizeof(addr);
+        char name[64];
+        char ip[INET_ADDRSTRLEN];
+        uint8_t reply[1500];
+        int reply_port;
+        int mdns;
+        int i;
+        fd_set fds;
+        ssize_t n;
 
-    keypress_packet packet;
-    packet.type = 1; // 1 == keyboard
-    packet.version = 1;
-    packet.pressed = pressed;
-    packet.modifiers = 0;
-    packet.scancode = scancode;
+        FD_ZERO(&fds);
+        FD_SET(fd, &fds);
+        if (select(fd + 1, &fds, NULL, NULL, &timeout) <= 0) {
+            break;
+        }
+
+        n = recvfrom(fd, reply, sizeof(reply) - 1, 0, (struct sockaddr *) &addr, &addr_len);
+        if (n < 0) {
+            continue;
+        }
+        reply[n] = '\0';
+
+        mdns = ntohs(addr.sin_port) == 5353;
+        if (mdns) {
+            if (!mdns_service(reply, n, name, sizeof(name), &reply_port)) {
+                continue;
+            }
+        } else if (n >= 2 && reply[0] == 11) { // 11 == discover reply
+            snprintf(name, sizeof(name), "%s", (char *) reply + 2);
+            reply_port = ntohs(addr.sin_port);
+        } else {
+            continue;
+        }
+        inet_ntop(AF_INET, &addr.sin_addr, ip, sizeof(ip));
 
-    if (sendto(
-            sockfd,
-            &packet,
-            sizeof(packet),
-            0,
-            (struct sockaddr *) &servaddr,
-            sizeof(servaddr)) < 0) {
-        printf("Error sending packet\n");
+        for (i = 0; i < count; i++) {
+            if (strcmp(ips[i], ip) == 0) {
+                break;
+            }
+        }
+        // what mdns says wins over the broadcast reply
+        if (i < count && !mdns) {
+            continue;
+        }
+        if (i == count) {
+            count++;
+        }
+        snprintf(names[i], sizeof(names[0]), "%s", name);
+        snprintf(ips[i], sizeof(ips[0]), "%s", ip);
+        ports[i] = reply_port;
     }
 
-    close(sockfd);
-}
+    close(fd);
 
-void send_mouse(
-    uint8_t buttons,
-    int8_t x,
-    int8_t y,
-    int8_t vertical,
-    int8_t horizontal)
-{
-    int sockfd;
-    struct sockaddr_in servaddr;
+    if (count == 0) {
+        printf("No devices found\n");
+        return -1;
+    }
 
-    sockfd = socket(AF_INET, SOCK_DGRAM, 0);
+    for (int i = 0; i < count; i++) {
+        printf("%d) %s (%s)\n", i + 1, names[i], ips[i]);
+    }
 
-    bzero(&servaddr, sizeof(servaddr));
+    choice = 1;
+    if (count > 1) {
+        printf("Pick a device [1-%d]: ", count);
+        fflush(stdout);
+        if (scanf("%d", &choice) != 1 || choice < 1 || choice > count) {
+            return -1;
+        }
+    }
 
-    servaddr.sin_family = AF_INET;
-    servaddr.sin_addr.s_addr = inet_addr(TARGET_IP);
-    servaddr.sin_port = htons(TARGET_PORT);
+    // the port only needs spelling out when it isn't the one we'd use anyway
+    if (ports[choice - 1] == port) {
+        snprintf(host, len, "%s", ips[choice - 1]);
+    } else {
+        snprintf(host, len, "%s:%d", ips[choice - 1], ports[choice - 1]);
+    }
+    return 0;
+}
 
-    mouse_packet packet;
-    packet.type = 2; // 2 == mouse
-    packet.version = 1;
-    packet.buttons = buttons;
-    packet.x = x;
-    packet.y = y;
-    packet.vertical = vertical;
-    packet.horizontal = horizontal;
+// after waking the target, wait until every device answers. they're
+// powered from the target's usb port, so that's the target being up too.
+// returns -1 if some didn't answer in time
+int wait_for_devices(int seconds)
+{
+    Uint32 started = SDL_GetTicks();
 
-    if (sendto(
-            sockfd,
-            &packet,
-            sizeof(packet),
-            0,
-            (struct sockaddr *) &servaddr,
-            sizeof(servaddr)) < 0) {
-        printf("Error sending packet\n");
+    printf("Waiting for the devices to come up...\n");
+    while (SDL_GetTicks() - started < (Uint32) seconds * 1000) {
+        int up = 0;
+
+        receive_packets();
+        for (int i = 0; i < target_count; i++) {
+            keepalive(&targets[i]);
+            up += targets[i].device.connected;
+        }
+        if (up == target_count) {
+            return 0;
+        }
+        SDL_Delay(100);
     }
 
-    close(sockfd);
+    printf("The devices didn't answer in %d seconds, starting anyway\n", seconds);
+    return -1;
 }
 
-int main()
+int main(int argc, char *argv[])
 {
     SDL_Window *window;
-    SDL_Surface *surface;
+    options opts;
+
+    if (parse_options(argc, argv, &opts) < 0) {
+        return 1;
+    }
+
+    choose_full_capture(&opts);
+
+    if (opts.ip_version != 0) {
+        address_family = opts.ip_version == 6 ? AF_INET6 : AF_INET;
+    }
+
+    // without any hosts given, see what's out there. discovery is ipv4 only
+    if (opts.default_host && opts.ip_version != 6) {
+        if (pick_device(opts.port, opts.hosts[0], sizeof(opts.hosts[0])) < 0) {
+            printf("Using the default host %s\n", opts.hosts[0]);
+        }
+    }
+
+    for (int i = 0; i < opts.host_count; i++) {
+        if (add_target(opts.hosts[i], opts.port) < 0) {
+            return 1;
+        }
+
+        const char *path = host_remap(&opts, opts.hosts[i]);
+        if (path[0] != '\0' && load_remap(path, &targets[i].remap) < 0) {
+            return 1;
+        }
+
+        const char *layout = host_layout(&opts, opts.hosts[i]);
+        targets[i].layout = find_layout(layout);
+        if (targets[i].layout == NULL) {
+            printf("Unknown target layout %s for %s\n", layout, opts.hosts[i]);
+            return 1;
+        }
+    }
+    default_layout = find_layout(opts.target_layout);
+
+    if (opts.remap[0] != '\0' && load_remap(opts.remap, &default_remap) < 0) {
+        return 1;
+    }
+
+    SDL_Scancode grab_key = SDL_GetScancodeFromName(opts.grab_key);
+    if (grab_key == SDL_SCANCODE_UNKNOWN) {
+        printf("Unknown grab key %s\n", opts.grab_key);
+        return 1;
+    }
 
-    SDL_Init(SDL_INIT_VIDEO);
+    if (set_media_keys(opts.local_media_keys, MEDIA_LOCAL) < 0 ||
+            set_media_keys(opts.shared_media_keys, MEDIA_SHARED) < 0) {
+        return 1;
+    }
+    update_media_passthrough();
+
+    if (set_turbo_keys(opts.turbo_keys) < 0) {
+        return 1;
+    }
+
+    SDL_Scancode leader_key = SDL_SCANCODE_UNKNOWN;
+    if (opts.leader_key[0] != '\0') {
+        leader_key = SDL_GetScancodeFromName(opts.leader_key);
+        if (leader_key == SDL_SCANCODE_UNKNOWN) {
+            printf("Unknown leader key %s\n", opts.leader_key);
+            return 1;
+        }
+    }
+
+    // the other backends don't have text input to compose from
+    SDL_Scancode compose_key = SDL_SCANCODE_UNKNOWN;
+    if (opts.compose_key[0] != '\0') {
+        compose_key = SDL_GetScancodeFromName(opts.compose_key);
+        if (compose_key == SDL_SCANCODE_UNKNOWN) {
+            printf("Unknown compose key %s\n", opts.compose_key);
+            return 1;
+        }
+        if (strcmp(opts.backend, "sdl") != 0) {
+            printf("The compose key only works with the sdl backend\n");
+            compose_key = SDL_SCANCODE_UNKNOWN;
+        }
+    }
+
+    if (setup_absolute(&opts) < 0) {
+        return 1;
+    }
+
+    max_pps = opts.max_pps;
+    if (open_socket() < 0 || start_sender() < 0) {
+        return 1;
+    }
+
+    if (opts.wake) {
+        if (send_wake(opts.wake_mac) < 0) {
+            printf("Could not send a wake on lan packet\n");
+        } else if (opts.wake_wait > 0) {
+            wait_for_devices(opts.wake_wait);
+        }
+    }
+
+    if (opts.calibrate_layout) {
+        return calibrate_layout(&opts) < 0 ? 1 : 0;
+    }
+
+    // no window needed, input comes from the devices, the event tap or the
+    // portal
+    int evdev = strcmp(opts.backend, "evdev") == 0;
+    int eventtap = strcmp(opts.backend, "eventtap") == 0;
+    int portal = strcmp(opts.backend, "portal") == 0;
+    if (evdev && evdev_open() < 0) {
+        return 1;
+    }
+    if (evdev || eventtap || portal) {
+        SDL_SetHint(SDL_HINT_VIDEODRIVER, "dummy");
+    }
+
+    SDL_Init(SDL_INIT_VIDEO | SDL_INIT_GAMECONTROLLER);
+
+    if (opts.record[0] != '\0') {
+        session = fopen(opts.record, "w");
+        if (session == NULL) {
+            printf("Could not write %s\n", opts.record);
+            return 1;
+        }
+        fprintf(session, "# nethid session: milliseconds from start, packet bytes in hex\n");
+        session_started = SDL_GetTicks();
+    }
+
+    // appended to, and written out line by line so nothing is lost
+    if (opts.audit[0] != '\0') {
+        audit = create_parent_dir(opts.audit) == 0 ? fopen(opts.audit, "a") : NULL;
+        if (audit == NULL) {
+            printf("Could not write %s\n", opts.audit);
+            return 1;
+        }
+        setvbuf(audit, NULL, _IOLBF, 0);
+        audit_keys = opts.audit_keys;
+    }
+
+    SDL_Rect bounds = { SDL_WINDOWPOS_UNDEFINED, SDL_WINDOWPOS_UNDEFINED, opts.width, opts.height };
+    Uint32 flags = SDL_WINDOW_SHOWN | SDL_WINDOW_RESIZABLE | SDL_WINDOW_ALLOW_HIGHDPI;
+
+    if (opts.fullscreen) {
+        flags |= SDL_WINDOW_FULLSCREEN_DESKTOP;
+    } else if (opts.borderless) {
+        // leave panels and docks visible
+        flags |= SDL_WINDOW_BORDERLESS;
+        SDL_GetDisplayUsableBounds(0, &bounds);
+    }
 
     window = SDL_CreateWindow(
         "Keyboard/Mouse event sender",
-        SDL_WINDOWPOS_UNDEFINED,
-        SDL_WINDOWPOS_UNDEFINED,
-        640,
-        480,
-        SDL_WINDOW_SHOWN);
+        bounds.x,
+        bounds.y,
+        bounds.w,
+        bounds.h,
+        flags);
 
 #if INHIBIT_SHORTCUTS
     SDL_SetHint(SDL_HINT_GRAB_KEYBOARD, "1");
 #endif
 
     SDL_SetWindowGrab(window, SDL_TRUE);
-    SDL_SetRelativeMouseMode(SDL_TRUE);
+    // in absolute mode the local pointer shows where the remote one is
+    SDL_SetRelativeMouseMode(absolute ? SDL_FALSE : SDL_TRUE);
 
     if (window == NULL) {
         printf("Could not create window: %s\n", SDL_GetError());
         return 1;
     }
 
+    if (evdev) {
+        evdev_grab(1);
+        if (evdev_start() < 0) {
+            return 1;
+        }
+    }
+
+    if (eventtap) {
+        eventtap_passthrough(media_passthrough);
+        if (eventtap_start() < 0) {
+            return 1;
+        }
+        eventtap_grab(1);
+    }
+
+    if (portal && portal_start() < 0) {
+        return 1;
+    }
+
     SDL_Event event;
     int quit = 0;
 
+    update_title(window);
+
     int rctrl_held = 0;
     int q_held = 0;
 
+    keyboard_state keyboard;
+    memset(&keyboard, 0, sizeof(keyboard));
+
     uint8_t mouse_buttons = 0x00;
+    double motion_remainder[2] = { 0, 0 };
+    double wheel_remainder[2] = { 0, 0 };
+    Uint32 last_input = SDL_GetTicks();
+    Uint32 last_nudge = SDL_GetTicks();
+
+    gamepad_state gamepad;
+    memset(&gamepad, 0, sizeof(gamepad));
 
     printf("RCTRL-q to quit!\r\n");
+    printf("RCTRL-%s to release or capture keyboard and mouse\r\n", SDL_GetScancodeName(grab_key));
+    printf("RCTRL-S to show the status\r\n");
+    printf("RCTRL-V to type the clipboard contents on the target, or drop a\r\n");
+    printf("text file on the window. RCTRL-C cancels typing\r\n");
+    printf("RCTRL-T and a key to make the key autofire while held\r\n");
+    printf("RCTRL-M to start and stop recording a macro, RCTRL-P to play it back\r\n");
+    printf("RCTRL-R to release everything held down on the target\r\n");
+    printf("RCTRL-L to keep media keys on this machine, or send them again\r\n");
+    printf("RCTRL-K and RCTRL-N to toggle caps lock and num lock on the target only\r\n");
+    printf("RCTRL-F10 to sleep, RCTRL-F11 to wake up, RCTRL-F12 to power down the target\r\n");
+    if (opts.wake_mac[0] != '\0') {
+        printf("RCTRL-W to wake the target up with wake on lan\r\n");
+    }
+    if (target_count > 1) {
+        printf("RCTRL-1..%d to send to one target only, RCTRL-0 to send to all\r\n", target_count);
+    }
 
     while (!quit) {
-        surface = SDL_GetWindowSurface(window);
-        SDL_FillRect(surface, NULL, SDL_MapRGB(surface->format, 0x80, 0x80, 0x80));
-        SDL_UpdateWindowSurface(window);
+        draw_window(window, &keyboard, mouse_buttons);
+
+        int updated = receive_packets();
+        for (int i = 0; i < target_count; i++) {
+            updated |= keepalive(&targets[i]);
+        }
+
+        if (report_stats(opts.stats) && show_status) {
+            updated = 1;
+        }
+        updated |= type_next(&keyboard, opts.type_delay);
+        updated |= play_next(&keyboard);
+        if (grabbed) {
+            updated |= check_locks(&opts, &keyboard);
+        }
+        if (updated) {
+            update_title(window);
+        }
+
+        // keys held without any input are likely stuck, e.g. released while
+        // we didn't have focus
+        if (opts.release_timeout &&
+                SDL_GetTicks() - last_input >= (Uint32) opts.release_timeout * 1000 &&
+                anything_held(&keyboard, mouse_buttons)) {
+            printf("Nothing happened for %d seconds, releasing held keys\n", opts.release_timeout);
+            release_all(&keyboard, &mouse_buttons);
+        }
+
+        // keep the target from locking or sleeping, by moving the mouse back
+        // and forth by one step
+        if (opts.keep_awake &&
+                SDL_GetTicks() - last_input >= (Uint32) opts.keep_awake * 1000 &&
+                SDL_GetTicks() - last_nudge >= (Uint32) opts.keep_awake * 1000) {
+            send_mouse(mouse_buttons, 1, 0, 0, 0);
+            send_mouse(mouse_buttons, -1, 0, 0, 0);
+            last_nudge = SDL_GetTicks();
+        }
+
+        // wake up periodically to check for packets from the device
+        int timeout = 100;
+        int turbo_wait = turbo_next(&keyboard, opts.turbo_rate);
+        int repeat_wait = repeat_next(&keyboard, opts.repeat_rate);
+        if (typing.text != NULL) {
+            timeout = opts.type_delay;
+        } else if (macro.playing != -1) {
+            timeout = 1;
+        } else if (turbo_wait != -1) {
+            timeout = turbo_wait;
+        } else if (repeat_wait != -1) {
+            timeout = repeat_wait;
+        }
 
-        SDL_WaitEvent(&event);
+        // movement is sent at most every motion_interval ms
+        if (pending_motion[0] || pending_motion[1] || pending_position) {
+            int motion_due = motion_wait(&opts);
+            if (motion_due == 0) {
+                flush_motion(mouse_buttons, opts.split_delay);
+            } else if (motion_due < timeout) {
+                timeout = motion_due;
+            }
+        }
+        if (!SDL_WaitEventTimeout(&event, timeout)) {
+            continue;
+        }
+        last_input = SDL_GetTicks();
 
         switch (event.type) {
             case SDL_QUIT:
                 quit = 1;
                 break;
 
+            case SDL_WINDOWEVENT:
+                // modifiers may have been pressed while we didn't have focus
+                if (event.window.event == SDL_WINDOWEVENT_FOCUS_GAINED && grabbed) {
+                    if (sync_modifiers(&keyboard, SDL_GetModState())) {
+                        send_keyboard(&keyboard);
+                    }
+                }
+                break;
+
             case SDL_KEYDOWN:
                 if (event.key.repeat) {
                     break;
@@ -162,12 +3714,234 @@ int main()
                 if (event.key.keysym.scancode == 20) {
                     q_held = 1;
                 }
+                // the q completing the chord isn't sent, it would stay
+                // held on the target
                 if (rctrl_held && q_held) {
                     quit = 1;
+                    break;
+                }
+
+                // system control hotkeys, not forwarded as keypresses
+                if (rctrl_held) {
+                    // checked first, a grab key that is also one of the
+                    // hotkeys below must still release the grab
+                    if (event.key.keysym.scancode == grab_key) {
+                        // nothing should stay pressed while we're not looking
+                        release_all(&keyboard, &mouse_buttons);
+                        // the portal captures again when the pointer
+                        // reaches the screen edge
+                        if (portal) {
+                            portal_release();
+                            break;
+                        }
+                        grabbed = !grabbed;
+                        SDL_SetWindowGrab(window, grabbed ? SDL_TRUE : SDL_FALSE);
+                        SDL_SetRelativeMouseMode(grabbed && !absolute ? SDL_TRUE : SDL_FALSE);
+                        if (evdev) {
+                            evdev_grab(grabbed);
+                        } else if (eventtap) {
+                            eventtap_grab(grabbed);
+                        }
+                        update_title(window);
+                        break;
+                    }
+
+                    uint8_t control = 0;
+                    switch (event.key.keysym.scancode) {
+                        case SDL_SCANCODE_F10:
+                            control = 2; // sleep
+                            break;
+                        case SDL_SCANCODE_F11:
+                            control = 3; // wake up
+                            break;
+                        case SDL_SCANCODE_F12:
+                            control = 1; // power down
+                            break;
+                        default:
+                            break;
+                    }
+                    if (control) {
+                        send_system_control(control);
+                        send_system_control(0);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_V) {
+                        char *text = SDL_GetClipboardText();
+                        if (text != NULL) {
+                            start_typing(text);
+                            SDL_free(text);
+                        }
+                        update_title(window);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_C &&
+                            (typing.text != NULL || macro.playing != -1)) {
+                        if (typing.text != NULL) {
+                            stop_typing(&keyboard);
+                        } else {
+                            stop_playback(&keyboard);
+                        }
+                        update_title(window);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_T) {
+                        turbo_pick = 1;
+                        printf("Press a key to toggle turbo for it\n");
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_M) {
+                        toggle_recording(&opts);
+                        update_title(window);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_P) {
+                        start_playback(&opts);
+                        update_title(window);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_R) {
+                        release_all(&keyboard, &mouse_buttons);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_S) {
+                        show_status = !show_status;
+                        update_title(window);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_K ||
+                            event.key.keysym.scancode == SDL_SCANCODE_N) {
+                        toggle_lock(event.key.keysym.scancode == SDL_SCANCODE_K ?
+                            0x02 : 0x01, &keyboard);
+                        update_title(window);
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_W) {
+                        if (opts.wake_mac[0] == '\0') {
+                            printf("Set wake_mac in the profile to wake the target up\n");
+                        } else if (send_wake(opts.wake_mac) < 0) {
+                            printf("Could not send a wake on lan packet\n");
+                        }
+                        break;
+                    }
+
+                    if (event.key.keysym.scancode == SDL_SCANCODE_L) {
+                        media_all_local = !media_all_local;
+                        update_media_passthrough();
+                        // one may be held
+                        send_consumer(0);
+                        update_title(window);
+                        break;
+                    }
+
+                    // target switching
+                    SDL_Scancode scancode = event.key.keysym.scancode;
+                    if (target_count > 1 &&
+                            scancode >= SDL_SCANCODE_1 &&
+                            scancode <= SDL_SCANCODE_0) {
+                        int new_target = scancode == SDL_SCANCODE_0 ?
+                            -1 : scancode - SDL_SCANCODE_1;
+                        if (new_target < target_count) {
+                            // don't leave anything stuck on the old target
+                            release_all(&keyboard, &mouse_buttons);
+                            active_target = new_target;
+                            printf("Sending to %s\n", new_target == -1 ?
+                                "all targets" : targets[new_target].name);
+                            update_title(window);
+                        }
+                        break;
+                    }
+                }
+
+                if (!grabbed) {
+                    break;
+                }
+
+                if (turbo_pick && event.key.keysym.scancode <= 0xff) {
+                    turbo_pick = 0;
+                    turbo[event.key.keysym.scancode] = !turbo[event.key.keysym.scancode];
+                    printf("Turbo %s for %s\n",
+                        turbo[event.key.keysym.scancode] ? "on" : "off",
+                        SDL_GetScancodeName(event.key.keysym.scancode));
+                    break;
+                }
+
+                // leader key macros, the keys aren't forwarded
+                if (leader_active) {
+                    leader_active = leader_next(&opts, event.key.keysym.scancode);
+                    update_title(window);
+                    break;
+                }
+                if (leader_key != SDL_SCANCODE_UNKNOWN &&
+                        event.key.keysym.scancode == leader_key) {
+                    leader_active = 1;
+                    leader_sequence[0] = '\0';
+                    update_title(window);
+                    break;
+                }
+
+                // what the keys type comes as text input, escape gives up
+                if (compose_active) {
+                    if (event.key.keysym.scancode == SDL_SCANCODE_ESCAPE) {
+                        compose_active = 0;
+                        update_title(window);
+                    }
+                    break;
+                }
+                if (compose_key != SDL_SCANCODE_UNKNOWN &&
+                        event.key.keysym.scancode == compose_key) {
+                    compose_active = 1;
+                    compose_sequence[0] = '\0';
+                    update_title(window);
+                    break;
                 }
 
                 // printf("Key press detected: %d\n", event.key.keysym.scancode);
-                send_keyboard(1, event.key.keysym.scancode);
+                {
+                    remap_entry *entry = &active_remap()->keys[event.key.keysym.scancode];
+                    SDL_Scancode scancode = event.key.keysym.scancode;
+
+                    if (entry->type == REMAP_DISABLED) {
+                        break;
+                    } else if (entry->type == REMAP_CONSUMER) {
+                        send_consumer(entry->code);
+                        break;
+                    } else if (entry->type == REMAP_KEY) {
+                        scancode = entry->code;
+                    } else if (consumer_usage(scancode)) {
+                        if (media_key_policy(scancode) != MEDIA_LOCAL) {
+                            send_consumer(consumer_usage(scancode));
+                        }
+                        break;
+                    } else if (system_control(scancode)) {
+                        send_system_control(system_control(scancode));
+                        break;
+                    }
+                    // sdl's own scancodes past the keyboard page don't fit
+                    // a report, they'd come out as some other key
+                    if (scancode > 0xff) {
+                        break;
+                    }
+                    if (opts.sticky_keys) {
+                        sticky_press(&keyboard, scancode);
+                    }
+                    if (press_key(&keyboard, scancode) |
+                            sync_modifiers(&keyboard, event.key.keysym.mod)) {
+                        send_keyboard(&keyboard);
+                    }
+                    if (opts.repeat_delay && scancode < 224) {
+                        repeat_key = scancode;
+                        repeat_next_time = SDL_GetTicks() + opts.repeat_delay;
+                    }
+                }
                 break;
 
             case SDL_KEYUP:
@@ -180,11 +3954,74 @@ int main()
                 if (event.key.keysym.scancode == 20) {
                     q_held = 0;
                 }
+                if (!grabbed) {
+                    break;
+                }
+
                 // printf("Key release detected: %d\n", event.key.keysym.scancode);
-                send_keyboard(0, event.key.keysym.scancode);
+                {
+                    remap_entry *entry = &active_remap()->keys[event.key.keysym.scancode];
+                    SDL_Scancode scancode = event.key.keysym.scancode;
+
+                    if (entry->type == REMAP_DISABLED) {
+                        break;
+                    } else if (entry->type == REMAP_CONSUMER) {
+                        send_consumer(0);
+                        break;
+                    } else if (entry->type == REMAP_KEY) {
+                        scancode = entry->code;
+                    } else if (consumer_usage(scancode)) {
+                        if (media_key_policy(scancode) != MEDIA_LOCAL) {
+                            send_consumer(0);
+                        }
+                        break;
+                    } else if (system_control(scancode)) {
+                        send_system_control(0);
+                        break;
+                    }
+                    if (scancode > 0xff) {
+                        break;
+                    }
+                    // keys typed while composing weren't sent, but ones held
+                    // from before are still let go of. the modifiers sdl has
+                    // may be for the composed characters
+                    if (compose_active) {
+                        if (release_key(&keyboard, scancode)) {
+                            send_keyboard(&keyboard);
+                        }
+                        break;
+                    }
+                    if (release_key(&keyboard, scancode) |
+                            sync_modifiers(&keyboard, event.key.keysym.mod)) {
+                        send_keyboard(&keyboard);
+                    }
+                    if (opts.sticky_keys && sticky_release(&keyboard, scancode)) {
+                        send_keyboard(&keyboard);
+                    }
+                }
+                break;
+
+            case SDL_TEXTINPUT:
+                if (compose_active) {
+                    compose_active = compose_next(&keyboard, event.text.text);
+                    update_title(window);
+                }
+                break;
+
+            case SDL_DROPFILE:
+                type_file(event.drop.file);
+                SDL_free(event.drop.file);
+                update_title(window);
                 break;
 
             case SDL_MOUSEBUTTONDOWN:
+                // touches are handled as they are, not as clicks
+                if (!grabbed || event.button.which == SDL_TOUCH_MOUSEID) {
+                    break;
+                }
+                // movement first, so it happens before the click
+                flush_motion(mouse_buttons, 0);
+
                 // printf("Mouse button pressed: %d\n", event.button.button);
                 switch (event.button.button) {
                     case SDL_BUTTON_LEFT:
@@ -196,13 +4033,25 @@ int main()
                     case SDL_BUTTON_MIDDLE:
                         mouse_buttons |= 0x04;
                         break;
+                    case SDL_BUTTON_X1:
+                        mouse_buttons |= 0x08;
+                        break;
+                    case SDL_BUTTON_X2:
+                        mouse_buttons |= 0x10;
+                        break;
                 }
 
-                send_mouse(mouse_buttons, 0, 0, 0, 0);
+                keyboard.tapping = 0;
+                send_buttons(mouse_buttons, 0, 0);
 
                 break;
 
             case SDL_MOUSEBUTTONUP:
+                if (!grabbed || event.button.which == SDL_TOUCH_MOUSEID) {
+                    break;
+                }
+                flush_motion(mouse_buttons, 0);
+
                 // printf("Mouse button released: %d\n", event.button.button);
                 switch (event.button.button) {
                     case SDL_BUTTON_LEFT:
@@ -214,21 +4063,173 @@ int main()
                     case SDL_BUTTON_MIDDLE:
                         mouse_buttons &= ~0x04;
                         break;
+                    case SDL_BUTTON_X1:
+                        mouse_buttons &= ~0x08;
+                        break;
+                    case SDL_BUTTON_X2:
+                        mouse_buttons &= ~0x10;
+                        break;
                 }
 
-                send_mouse(mouse_buttons, 0, 0, 0, 0);
+                send_buttons(mouse_buttons, 0, 0);
+
+                // ctrl-click and such with latched modifiers
+                if (keyboard.latched) {
+                    keyboard.latched = 0;
+                    send_keyboard(&keyboard);
+                }
 
                 break;
 
             case SDL_MOUSEMOTION:
+                if (!grabbed || event.motion.which == SDL_TOUCH_MOUSEID) {
+                    break;
+                }
                 // printf("Mouse moved: %d, %d\n", event.motion.xrel, event.motion.yrel);
-                send_mouse(mouse_buttons, event.motion.xrel, event.motion.yrel, 0, 0);
+                if (absolute) {
+                    window_to_target(window, event.motion.x, event.motion.y, position);
+                    pending_position = 1;
+
+                    if (motion_wait(&opts) == 0) {
+                        flush_motion(mouse_buttons, 0);
+                    }
+                } else {
+                    pending_motion[0] += scale_motion(event.motion.xrel, opts.sensitivity,
+                        &motion_remainder[0]);
+                    pending_motion[1] += scale_motion(event.motion.yrel, opts.sensitivity,
+                        &motion_remainder[1]);
+
+                    if (motion_wait(&opts) == 0) {
+                        flush_motion(mouse_buttons, opts.split_delay);
+                    }
+                }
+                break;
+
+            case SDL_MOUSEWHEEL:
+                if (!grabbed) {
+                    break;
+                }
+                {
+                    double speed = opts.scroll_speed;
+                    int y = event.wheel.y;
+                    int x = event.wheel.x;
+                    int vertical, horizontal;
+
+                    if (event.wheel.direction == SDL_MOUSEWHEEL_FLIPPED) {
+                        speed = -speed;
+                    }
+                    if (opts.swap_scroll) {
+                        y = event.wheel.x;
+                        x = event.wheel.y;
+                    }
+                    if (!opts.hscroll) {
+                        x = 0;
+                    }
+
+                    vertical = scale_motion(y, opts.invert_scroll ? -speed : speed,
+                        &wheel_remainder[0]);
+                    horizontal = scale_motion(x, opts.invert_hscroll ? -speed : speed,
+                        &wheel_remainder[1]);
+
+                    if (vertical || horizontal) {
+                        flush_motion(mouse_buttons, 0);
+                        send_buttons(mouse_buttons, vertical, horizontal);
+                    }
+                }
                 break;
+
+            // only touchscreens, trackpads move the mouse
+            case SDL_FINGERDOWN:
+                if (grabbed &&
+                        SDL_GetTouchDeviceType(event.tfinger.touchId) == SDL_TOUCH_DEVICE_DIRECT) {
+                    flush_motion(mouse_buttons, 0);
+                    keyboard.tapping = 0;
+                    touch_down(window, &event.tfinger, mouse_buttons);
+                }
+                break;
+
+            case SDL_FINGERMOTION:
+                if (grabbed) {
+                    touch_motion(window, &event.tfinger, mouse_buttons, &opts);
+                }
+                break;
+
+            // even when not grabbed, so the finger isn't left down
+            case SDL_FINGERUP:
+                touch_up(&event.tfinger, mouse_buttons);
+                break;
+
+            case SDL_CONTROLLERDEVICEADDED:
+                if (SDL_GameControllerOpen(event.cdevice.which) == NULL) {
+                    printf("Could not open game controller: %s\n", SDL_GetError());
+                }
+                break;
+
+            case SDL_CONTROLLERDEVICEREMOVED:
+                SDL_GameControllerClose(
+                    SDL_GameControllerFromInstanceID(event.cdevice.which));
+                memset(&gamepad, 0, sizeof(gamepad));
+                send_gamepad(&gamepad);
+                break;
+
+            case SDL_CONTROLLERAXISMOTION:
+                // sdl axes are 16 bit, triggers only go from 0 to 32767
+                gamepad.axes[event.caxis.axis] = event.caxis.value >> 8;
+                if (gamepad.axes[event.caxis.axis] == -128) {
+                    gamepad.axes[event.caxis.axis] = -127;
+                }
+                send_gamepad(&gamepad);
+                break;
+
+            case SDL_CONTROLLERBUTTONDOWN:
+                gamepad.buttons |= 1 << event.cbutton.button;
+                send_gamepad(&gamepad);
+                break;
+
+            case SDL_CONTROLLERBUTTONUP:
+                gamepad.buttons &= ~(1 << event.cbutton.button);
+                send_gamepad(&gamepad);
+                break;
+
             default:
+                // drawing tablets, from the evdev backend
+                if (pen_event != 0 && event.type == pen_event) {
+                    if (grabbed) {
+                        keyboard.tapping = 0;
+                        send_pen(event.user.data1);
+                    }
+                    free(event.user.data1);
+                }
                 break;
         }
     }
 
+    // leave nothing held down on the target, then wait for it to go out.
+    // the sender thread still needs the socket for what is queued
+    release_all(&keyboard, &mouse_buttons);
+    flush_packets();
+    close(sockfd);
+
+    // only a window the user sized is worth remembering
+    if (!opts.fullscreen && !opts.borderless && !evdev && !eventtap && !portal) {
+        char state[1024];
+        int width, height;
+
+        SDL_GetWindowSize(window, &width, &height);
+        config_file_path("window.toml", state, sizeof(state));
+        if (save_window_size(state, width, height) < 0) {
+            printf("Could not save window size to %s\n", state);
+        }
+    }
+
+    if (session != NULL) {
+        fclose(session);
+    }
+
+    if (audit != NULL) {
+        fclose(audit);
+    }
+
     SDL_DestroyWindow(window);
     SDL_Quit();
 
@@ -0,0 +1,336 @@
+#include <stdio.h>
+#include <string.h>
+#include <glib-unix.h>
+#include <libportal/portal.h>
+#include <libei.h>
+#include <SDL.h>
+
+#include "evdev.h"
+#include "portal.h"
+
+// libei scrolls in 120ths of a wheel click, or in pixels for touchpads
+#define SCROLL_DISCRETE_STEP 120
+#define SCROLL_PIXEL_STEP 15.0
+
+static GMainLoop *loop = NULL;
+static XdpPortal *portal = NULL;
+static XdpInputCaptureSession *session = NULL;
+static struct ei *ei = NULL;
+static guint activation_id = 0;
+static int active = 0;
+
+// motion and smooth scrolling until the end of a frame
+static double motion[2];
+static double scroll[2];
+
+static void push_motion(void)
+{
+    SDL_Event event;
+    int x = (int) motion[0];
+    int y = (int) motion[1];
+
+    if (x == 0 && y == 0) {
+        return;
+    }
+
+    memset(&event, 0, sizeof(event));
+    event.type = SDL_MOUSEMOTION;
+    event.motion.xrel = x;
+    event.motion.yrel = y;
+    SDL_PushEvent(&event);
+
+    // keep the fractions for the next frame
+    motion[0] -= x;
+    motion[1] -= y;
+}
+
+// wayland scrolls down with positive values, sdl up
+static void push_wheel(int x, int y)
+{
+    SDL_Event event;
+
+    if (x == 0 && y == 0) {
+        return;
+    }
+
+    memset(&event, 0, sizeof(event));
+    event.type = SDL_MOUSEWHEEL;
+    event.wheel.x = x;
+    event.wheel.y = -y;
+    event.wheel.direction = SDL_MOUSEWHEEL_NORMAL;
+    SDL_PushEvent(&event);
+}
+
+static void push_scroll(void)
+{
+    int x = (int) (scroll[0] / SCROLL_PIXEL_STEP);
+    int y = (int) (scroll[1] / SCROLL_PIXEL_STEP);
+
+    push_wheel(x, y);
+    scroll[0] -= x * SCROLL_PIXEL_STEP;
+    scroll[1] -= y * SCROLL_PIXEL_STEP;
+}
+
+static void handle_ei_event(struct ei_event *event)
+{
+    switch (ei_event_get_type(event)) {
+        case EI_EVENT_SEAT_ADDED:
+            ei_seat_bind_capabilities(ei_event_get_seat(event),
+                EI_DEVICE_CAP_KEYBOARD,
+                EI_DEVICE_CAP_POINTER,
+                EI_DEVICE_CAP_BUTTON,
+                EI_DEVICE_CAP_SCROLL,
+                NULL);
+            break;
+        case EI_EVENT_DISCONNECT:
+            printf("Input capture was disconnected\n");
+            g_main_loop_quit(loop);
+            break;
+        case EI_EVENT_KEYBOARD_KEY:
+            evdev_push_key(ei_event_keyboard_get_key(event),
+                ei_event_keyboard_get_key_is_press(event));
+            break;
+        case EI_EVENT_BUTTON_BUTTON:
+            evdev_push_button(ei_event_button_get_button(event),
+                ei_event_button_get_is_press(event));
+            break;
+        case EI_EVENT_POINTER_MOTION:
+            motion[0] += ei_event_pointer_get_dx(event);
+            motion[1] += ei_event_pointer_get_dy(event);
+            break;
+        case EI_EVENT_SCROLL_DISCRETE:
+            push_wheel(ei_event_scroll_get_discrete_dx(event) / SCROLL_DISCRETE_STEP,
+                ei_event_scroll_get_discrete_dy(event) / SCROLL_DISCRETE_STEP);
+            break;
+        case EI_EVENT_SCROLL_DELTA:
+            scroll[0] += ei_event_scroll_get_dx(event);
+            scroll[1] += ei_event_scroll_get_dy(event);
+            break;
+        case EI_EVENT_FRAME:
+            push_motion();
+            push_scroll();
+            break;
+        default:
+            break;
+    }
+}
+
+static gboolean dispatch_ei(gint fd, GIOCondition condition, gpointer data)
+{
+    struct ei_event *event;
+
+    (void) fd;
+    (void) condition;
+    (void) data;
+
+    ei_dispatch(ei);
+    while ((event = ei_get_event(ei)) != NULL) {
+        handle_ei_event(event);
+        ei_event_unref(event);
+    }
+
+    return G_SOURCE_CONTINUE;
+}
+
+static void activated(XdpInputCaptureSession *s, guint id, GVariant *options, gpointer data)
+{
+    (void) s;
+    (void) options;
+    (void) data;
+
+    activation_id = id;
+    active = 1;
+}
+
+static void deactivated(XdpInputCaptureSession *s, guint id, GVariant *options, gpointer data)
+{
+    (void) s;
+    (void) id;
+    (void) options;
+    (void) data;
+
+    active = 0;
+}
+
+static void disabled(XdpInputCaptureSession *s, GVariant *options, gpointer data)
+{
+    (void) s;
+    (void) options;
+    (void) data;
+
+    printf("Input capture was disabled\n");
+    g_main_loop_quit(loop);
+}
+
+static void barriers_set(GObject *source, GAsyncResult *result, gpointer data)
+{
+    GError *error = NULL;
+    GList *failed;
+
+    (void) source;
+    (void) data;
+
+    failed = xdp_input_capture_session_set_pointer_barriers_finish(session, result, &error);
+    if (error != NULL) {
+        printf("Could not set up input capture: %s\n", error->message);
+        g_error_free(error);
+        g_main_loop_quit(loop);
+        return;
+    }
+
+    if (failed != NULL) {
+        printf("The compositor didn't accept the screen edge for input capture\n");
+        g_list_free_full(failed, g_object_unref);
+        g_main_loop_quit(loop);
+        return;
+    }
+
+    xdp_input_capture_session_enable(session);
+    printf("Push the pointer against the right edge of the screen to capture input\n");
+}
+
+// capture starts at the right edge of the rightmost screen
+static void set_barriers(void)
+{
+    GList *barriers = NULL;
+    int edge = 0;
+    int top = 0;
+    int bottom = 0;
+
+    for (GList *l = xdp_input_capture_session_get_zones(session); l != NULL; l = l->next) {
+        guint width, height;
+        gint x, y;
+
+        g_object_get(l->data, "width", &width, "height", &height, "x", &x, "y", &y, NULL);
+        if (x + (int) width > edge) {
+            edge = x + width;
+            top = y;
+            bottom = y + height - 1;
+        }
+    }
+
+    barriers = g_list_append(barriers, g_object_new(XDP_TYPE_INPUT_CAPTURE_POINTER_BARRIER,
+        "id", 1,
+        "x1", edge,
+        "y1", top,
+        "x2", edge,
+        "y2", bottom,
+        NULL));
+
+    xdp_input_capture_session_set_pointer_barriers(session, barriers, NULL, barriers_set, NULL);
+}
+
+// e.g. a screen was plugged in
+static void zones_changed(XdpInputCaptureSession *s, GVariant *options, gpointer data)
+{
+    (void) s;
+    (void) options;
+    (void) data;
+
+    set_barriers();
+}
+
+static void session_created(GObject *source, GAsyncResult *result, gpointer data)
+{
+    GError *error = NULL;
+    int fd;
+
+    (void) source;
+    (void) data;
+
+    session = xdp_portal_create_input_capture_session_finish(portal, result, &error);
+    if (session == NULL) {
+        printf("Could not start input capture: %s\n", error->message);
+        g_error_free(error);
+        g_main_loop_quit(loop);
+        return;
+    }
+
+    fd = xdp_input_capture_session_connect_to_eis(session, &error);
+    if (fd < 0) {
+        printf("Could not connect to the compositor for input: %s\n", error->message);
+        g_error_free(error);
+        g_main_loop_quit(loop);
+        return;
+    }
+
+    ei = ei_new_receiver(NULL);
+    ei_configure_name(ei, "hidgui");
+    if (ei_setup_backend_fd(ei, fd) != 0) {
+        printf("Could not set up libei\n");
+        g_main_loop_quit(loop);
+        return;
+    }
+    g_unix_fd_add(ei_get_fd(ei), G_IO_IN, dispatch_ei, NULL);
+
+    g_signal_connect(session, "activated", G_CALLBACK(activated), NULL);
+    g_signal_connect(session, "deactivated", G_CALLBACK(deactivated), NULL);
+    g_signal_connect(session, "disabled", G_CALLBACK(disabled), NULL);
+    g_signal_connect(session, "zones-changed", G_CALLBACK(zones_changed), NULL);
+
+    set_barriers();
+}
+
+// the portal talks over dbus, so it gets a glib main loop of its own
+static int run_portal(void *data)
+{
+    SDL_Event event;
+
+    (void) data;
+
+    g_main_loop_run(loop);
+
+    // nothing to capture input with anymore
+    memset(&event, 0, sizeof(event));
+    event.type = SDL_QUIT;
+    SDL_PushEvent(&event);
+
+    return 0;
+}
+
+int portal_start(void)
+{
+    GError *error = NULL;
+
+    portal = xdp_portal_initable_new(&error);
+    if (portal == NULL) {
+        printf("Could not connect to the desktop portal: %s\n", error->message);
+        g_error_free(error);
+        return -1;
+    }
+
+    loop = g_main_loop_new(NULL, FALSE);
+
+    xdp_portal_create_input_capture_session(
+        portal,
+        NULL,
+        XDP_INPUT_CAPABILITY_KEYBOARD | XDP_INPUT_CAPABILITY_POINTER,
+        NULL,
+        session_created,
+        NULL);
+
+    if (SDL_CreateThread(run_portal, "portal", NULL) == NULL) {
+        printf("Could not start input capture: %s\n", SDL_GetError());
+        return -1;
+    }
+
+    return 0;
+}
+
+static gboolean release(gpointer data)
+{
+    (void) data;
+
+    if (active) {
+        xdp_input_capture_session_release(session, activation_id);
+        active = 0;
+    }
+
+    return G_SOURCE_REMOVE;
+}
+
+// give the pointer back to the desktop, it's captured again at the edge
+void portal_release(void)
+{
+    g_idle_add(release, NULL);
+}
@@ -0,0 +1,30 @@
+#ifndef __PORTAL_H
+#define __PORTAL_H
+
+// input captured through the InputCapture portal and libei, for wayland
+// sessions where the sdl grab doesn't keep shortcuts from the compositor.
+// capture starts when the pointer is pushed against the right edge of the
+// screen
+
+#ifdef HAVE_PORTAL
+
+int portal_start(void);
+void portal_release(void);
+
+#else
+
+#include <stdio.h>
+
+static inline int portal_start(void)
+{
+    printf("hidgui was built without the portal backend (needs libportal and libei)\n");
+    return -1;
+}
+
+static inline void portal_release(void)
+{
+}
+
+#endif
+
+#endif
@@ -0,0 +1,76 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#include "../config.h"
+
+static int failures = 0;
+
+static void expect(const char *what, const char *got, const char *wanted)
+{
+    if (strcmp(got, wanted) != 0) {
+        printf("%s: got \"%s\", wanted \"%s\"\n", what, got, wanted);
+        failures++;
+    }
+}
+
+// write a config file and load it, returns what load_config() does
+static int load(const char *text, options *opts)
+{
+    char path[] = "/tmp/nethid-config-test-XXXXXX";
+    int fd = mkstemp(path);
+    int ret;
+
+    if (fd < 0 || write(fd, text, strlen(text)) != (ssize_t) strlen(text)) {
+        printf("Could not write %s\n", path);
+        exit(1);
+    }
+    close(fd);
+
+    default_options(opts);
+    ret = load_config(path, NULL, 1, opts);
+    unlink(path);
+
+    return ret;
+}
+
+// # inside a quoted string isn't a comment, escaped quotes don't end it
+static void test_comments_in_strings(void)
+{
+    options opts;
+
+    if (load("grab_key = \"G\" # comment\n"
+            "leader_key = \"#\"\n"
+            "\n"
+            "[leader]\n"
+            "x = \"echo #1\\n\" # run it\n"
+            "q = \"say \\\"#hi\\\" \\\\\" # quoted\n", &opts) < 0) {
+        printf("Config with # in strings didn't load\n");
+        failures++;
+        return;
+    }
+
+    expect("grab_key", opts.grab_key, "G");
+    expect("leader_key", opts.leader_key, "#");
+    if (opts.leader_macro_count != 2) {
+        printf("leader macros: got %d, wanted 2\n", opts.leader_macro_count);
+        failures++;
+        return;
+    }
+    expect("macro x", opts.leader_macros[0].text, "echo #1\n");
+    expect("macro q", opts.leader_macros[1].text, "say \"#hi\" \\");
+}
+
+int main(void)
+{
+    test_comments_in_strings();
+
+    if (failures) {
+        printf("%d failed\n", failures);
+        return 1;
+    }
+
+    printf("ok\n");
+    return 0;
+}
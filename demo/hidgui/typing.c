@@ -0,0 +1,965 @@
+#include <ctype.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "typing.h"
+
+#define SHIFT 0x02 // left shift in the hid modifier byte
+#define ALTGR 0x40 // right alt
+
+// what accents combine with, as pairs of characters
+#define ACUTE "aáeéiíoóuúyýAÁEÉIÍOÓUÚYÝ"
+#define GRAVE "aàeèiìoòuùAÀEÈIÌOÒUÙ"
+#define CIRCUMFLEX "aâeêiîoôuûAÂEÊIÎOÔUÛ"
+#define DIAERESIS "aäeëiïoöuüyÿAÄEËIÏOÖUÜ"
+#define TILDE "aãnñoõAÃNÑOÕ"
+
+// what each level of a key is typed with
+static const uint8_t level_modifiers[4] = { 0, SHIFT, ALTGR, SHIFT | ALTGR };
+
+static const layout_key us_keys[] = {
+    { 0x04, { "a", "A" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { "m", "M" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "q", "Q" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y" } },
+    { 0x1d, { "z", "Z" } },
+    { 0x1e, { "1", "!" } },
+    { 0x1f, { "2", "@" } },
+    { 0x20, { "3", "#" } },
+    { 0x21, { "4", "$" } },
+    { 0x22, { "5", "%" } },
+    { 0x23, { "6", "^" } },
+    { 0x24, { "7", "&" } },
+    { 0x25, { "8", "*" } },
+    { 0x26, { "9", "(" } },
+    { 0x27, { "0", ")" } },
+    { 0x2d, { "-", "_" } },
+    { 0x2e, { "=", "+" } },
+    { 0x2f, { "[", "{" } },
+    { 0x30, { "]", "}" } },
+    { 0x31, { "\\", "|" } },
+    { 0x33, { ";", ":" } },
+    { 0x34, { "'", "\"" } },
+    { 0x35, { "`", "~" } },
+    { 0x36, { ",", "<" } },
+    { 0x37, { ".", ">" } },
+    { 0x38, { "/", "?" } },
+    { 0 },
+};
+
+// as on windows, the quotes, ` ~ and ^ are dead keys
+static const layout_key us_intl_keys[] = {
+    { 0x04, { "a", "A", "á", "Á" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C", "©", "¢" } },
+    { 0x07, { "d", "D", "ð", "Ð" } },
+    { 0x08, { "e", "E", "é", "É" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I", "í", "Í" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L", "ø", "Ø" } },
+    { 0x10, { "m", "M", "µ" } },
+    { 0x11, { "n", "N", "ñ", "Ñ" } },
+    { 0x12, { "o", "O", "ó", "Ó" } },
+    { 0x13, { "p", "P", "ö", "Ö" } },
+    { 0x14, { "q", "Q", "ä", "Ä" } },
+    { 0x15, { "r", "R", "®" } },
+    { 0x16, { "s", "S", "ß", "§" } },
+    { 0x17, { "t", "T", "þ", "Þ" } },
+    { 0x18, { "u", "U", "ú", "Ú" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W", "å", "Å" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y", "ü", "Ü" } },
+    { 0x1d, { "z", "Z", "æ", "Æ" } },
+    { 0x1e, { "1", "!", "¡", "¹" } },
+    { 0x1f, { "2", "@", "²" } },
+    { 0x20, { "3", "#", "³" } },
+    { 0x21, { "4", "$", "¤", "£" } },
+    { 0x22, { "5", "%", "€" } },
+    { 0x23, { "6", "^", "¼" } },
+    { 0x24, { "7", "&", "½" } },
+    { 0x25, { "8", "*", "¾" } },
+    { 0x26, { "9", "(", "‘" } },
+    { 0x27, { "0", ")", "’" } },
+    { 0x2d, { "-", "_", "¥" } },
+    { 0x2e, { "=", "+", "×", "÷" } },
+    { 0x2f, { "[", "{", "«" } },
+    { 0x30, { "]", "}", "»" } },
+    { 0x31, { "\\", "|", "¬", "¦" } },
+    { 0x33, { ";", ":", "¶", "°" } },
+    { 0x34, { "'", "\"", "´", "¨" } },
+    { 0x35, { "`", "~" } },
+    { 0x36, { ",", "<", "ç", "Ç" } },
+    { 0x37, { ".", ">" } },
+    { 0x38, { "/", "?", "¿" } },
+    { 0 },
+};
+
+static const layout_dead_key us_intl_dead_keys[] = {
+    { "'", ACUTE "cçCÇ" },
+    { "\"", DIAERESIS },
+    { "`", GRAVE },
+    { "~", TILDE },
+    { "^", CIRCUMFLEX },
+    { NULL },
+};
+
+static const layout_key de_keys[] = {
+    { 0x04, { "a", "A" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E", "€" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { "m", "M", "µ" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "q", "Q", "@" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "z", "Z" } },
+    { 0x1d, { "y", "Y" } },
+    { 0x1e, { "1", "!" } },
+    { 0x1f, { "2", "\"", "²" } },
+    { 0x20, { "3", "§", "³" } },
+    { 0x21, { "4", "$" } },
+    { 0x22, { "5", "%" } },
+    { 0x23, { "6", "&" } },
+    { 0x24, { "7", "/", "{" } },
+    { 0x25, { "8", "(", "[" } },
+    { 0x26, { "9", ")", "]" } },
+    { 0x27, { "0", "=", "}" } },
+    { 0x2d, { "ß", "?", "\\" } },
+    { 0x2e, { "´", "`" } },
+    { 0x2f, { "ü", "Ü" } },
+    { 0x30, { "+", "*", "~" } },
+    { 0x32, { "#", "'" } },
+    { 0x33, { "ö", "Ö" } },
+    { 0x34, { "ä", "Ä" } },
+    { 0x35, { "^", "°" } },
+    { 0x36, { ",", ";" } },
+    { 0x37, { ".", ":" } },
+    { 0x38, { "-", "_" } },
+    { 0x64, { "<", ">", "|" } },
+    { 0 },
+};
+
+static const layout_dead_key de_dead_keys[] = {
+    { "^", CIRCUMFLEX },
+    { "´", ACUTE },
+    { "`", GRAVE },
+    { NULL },
+};
+
+static const layout_key fr_keys[] = {
+    { 0x04, { "q", "Q" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E", "€" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { ",", "?" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "a", "A" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "z", "Z" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y" } },
+    { 0x1d, { "w", "W" } },
+    { 0x1e, { "&", "1" } },
+    { 0x1f, { "é", "2", "~" } },
+    { 0x20, { "\"", "3", "#" } },
+    { 0x21, { "'", "4", "{" } },
+    { 0x22, { "(", "5", "[" } },
+    { 0x23, { "-", "6", "|" } },
+    { 0x24, { "è", "7", "`" } },
+    { 0x25, { "_", "8", "\\" } },
+    { 0x26, { "ç", "9" } },
+    { 0x27, { "à", "0", "@" } },
+    { 0x2d, { ")", "°", "]" } },
+    { 0x2e, { "=", "+", "}" } },
+    { 0x2f, { "^", "¨" } },
+    { 0x30, { "$", "£", "¤" } },
+    { 0x32, { "*", "µ" } },
+    { 0x33, { "m", "M" } },
+    { 0x34, { "ù", "%" } },
+    { 0x35, { "²" } },
+    { 0x36, { ";", "." } },
+    { 0x37, { ":", "/" } },
+    { 0x38, { "!", "§" } },
+    { 0x64, { "<", ">" } },
+    { 0 },
+};
+
+static const layout_dead_key fr_dead_keys[] = {
+    { "^", CIRCUMFLEX },
+    { "¨", DIAERESIS },
+    { "~", TILDE },
+    { "`", GRAVE },
+    { NULL },
+};
+
+static const layout_key uk_keys[] = {
+    { 0x04, { "a", "A", "á", "Á" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E", "é", "É" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I", "í", "Í" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { "m", "M" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O", "ó", "Ó" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "q", "Q" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U", "ú", "Ú" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y" } },
+    { 0x1d, { "z", "Z" } },
+    { 0x1e, { "1", "!" } },
+    { 0x1f, { "2", "\"" } },
+    { 0x20, { "3", "£" } },
+    { 0x21, { "4", "$", "€" } },
+    { 0x22, { "5", "%" } },
+    { 0x23, { "6", "^" } },
+    { 0x24, { "7", "&" } },
+    { 0x25, { "8", "*" } },
+    { 0x26, { "9", "(" } },
+    { 0x27, { "0", ")" } },
+    { 0x2d, { "-", "_" } },
+    { 0x2e, { "=", "+" } },
+    { 0x2f, { "[", "{" } },
+    { 0x30, { "]", "}" } },
+    { 0x32, { "#", "~" } },
+    { 0x33, { ";", ":" } },
+    { 0x34, { "'", "@" } },
+    { 0x35, { "`", "¬", "¦" } },
+    { 0x36, { ",", "<" } },
+    { 0x37, { ".", ">" } },
+    { 0x38, { "/", "?" } },
+    { 0x64, { "\\", "|" } },
+    { 0 },
+};
+
+static const layout_key se_keys[] = {
+    { 0x04, { "a", "A" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E", "€" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { "m", "M", "µ" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "q", "Q" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y" } },
+    { 0x1d, { "z", "Z" } },
+    { 0x1e, { "1", "!" } },
+    { 0x1f, { "2", "\"", "@" } },
+    { 0x20, { "3", "#", "£" } },
+    { 0x21, { "4", "¤", "$" } },
+    { 0x22, { "5", "%", "€" } },
+    { 0x23, { "6", "&" } },
+    { 0x24, { "7", "/", "{" } },
+    { 0x25, { "8", "(", "[" } },
+    { 0x26, { "9", ")", "]" } },
+    { 0x27, { "0", "=", "}" } },
+    { 0x2d, { "+", "?", "\\" } },
+    { 0x2e, { "´", "`" } },
+    { 0x2f, { "å", "Å" } },
+    { 0x30, { "¨", "^", "~" } },
+    { 0x32, { "'", "*" } },
+    { 0x33, { "ö", "Ö" } },
+    { 0x34, { "ä", "Ä" } },
+    { 0x35, { "§", "½" } },
+    { 0x36, { ",", ";" } },
+    { 0x37, { ".", ":" } },
+    { 0x38, { "-", "_" } },
+    { 0x64, { "<", ">", "|" } },
+    { 0 },
+};
+
+static const layout_dead_key se_dead_keys[] = {
+    { "´", ACUTE },
+    { "`", GRAVE },
+    { "¨", DIAERESIS },
+    { "^", CIRCUMFLEX },
+    { "~", TILDE },
+    { NULL },
+};
+
+static const layout_key no_keys[] = {
+    { 0x04, { "a", "A" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E", "€" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { "m", "M", "µ" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "q", "Q" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y" } },
+    { 0x1d, { "z", "Z" } },
+    { 0x1e, { "1", "!" } },
+    { 0x1f, { "2", "\"", "@" } },
+    { 0x20, { "3", "#", "£" } },
+    { 0x21, { "4", "¤", "$" } },
+    { 0x22, { "5", "%", "€" } },
+    { 0x23, { "6", "&" } },
+    { 0x24, { "7", "/", "{" } },
+    { 0x25, { "8", "(", "[" } },
+    { 0x26, { "9", ")", "]" } },
+    { 0x27, { "0", "=", "}" } },
+    { 0x2d, { "+", "?" } },
+    { 0x2e, { "\\", "`", "´" } },
+    { 0x2f, { "å", "Å" } },
+    { 0x30, { "¨", "^", "~" } },
+    { 0x32, { "'", "*" } },
+    { 0x33, { "ø", "Ø" } },
+    { 0x34, { "æ", "Æ" } },
+    { 0x35, { "|", "§" } },
+    { 0x36, { ",", ";" } },
+    { 0x37, { ".", ":" } },
+    { 0x38, { "-", "_" } },
+    { 0x64, { "<", ">" } },
+    { 0 },
+};
+
+static const layout_dead_key no_dead_keys[] = {
+    { "´", ACUTE },
+    { "`", GRAVE },
+    { "¨", DIAERESIS },
+    { "^", CIRCUMFLEX },
+    { "~", TILDE },
+    { NULL },
+};
+
+static const layout_key dk_keys[] = {
+    { 0x04, { "a", "A" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E", "€" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { "m", "M", "µ" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "q", "Q" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y" } },
+    { 0x1d, { "z", "Z" } },
+    { 0x1e, { "1", "!" } },
+    { 0x1f, { "2", "\"", "@" } },
+    { 0x20, { "3", "#", "£" } },
+    { 0x21, { "4", "¤", "$" } },
+    { 0x22, { "5", "%", "€" } },
+    { 0x23, { "6", "&" } },
+    { 0x24, { "7", "/", "{" } },
+    { 0x25, { "8", "(", "[" } },
+    { 0x26, { "9", ")", "]" } },
+    { 0x27, { "0", "=", "}" } },
+    { 0x2d, { "+", "?" } },
+    { 0x2e, { "´", "`", "|" } },
+    { 0x2f, { "å", "Å" } },
+    { 0x30, { "¨", "^", "~" } },
+    { 0x32, { "'", "*" } },
+    { 0x33, { "æ", "Æ" } },
+    { 0x34, { "ø", "Ø" } },
+    { 0x35, { "½", "§" } },
+    { 0x36, { ",", ";" } },
+    { 0x37, { ".", ":" } },
+    { 0x38, { "-", "_" } },
+    { 0x64, { "<", ">", "\\" } },
+    { 0 },
+};
+
+static const layout_dead_key dk_dead_keys[] = {
+    { "´", ACUTE },
+    { "`", GRAVE },
+    { "¨", DIAERESIS },
+    { "^", CIRCUMFLEX },
+    { "~", TILDE },
+    { NULL },
+};
+
+static const layout_key es_keys[] = {
+    { 0x04, { "a", "A" } },
+    { 0x05, { "b", "B" } },
+    { 0x06, { "c", "C" } },
+    { 0x07, { "d", "D" } },
+    { 0x08, { "e", "E", "€" } },
+    { 0x09, { "f", "F" } },
+    { 0x0a, { "g", "G" } },
+    { 0x0b, { "h", "H" } },
+    { 0x0c, { "i", "I" } },
+    { 0x0d, { "j", "J" } },
+    { 0x0e, { "k", "K" } },
+    { 0x0f, { "l", "L" } },
+    { 0x10, { "m", "M" } },
+    { 0x11, { "n", "N" } },
+    { 0x12, { "o", "O" } },
+    { 0x13, { "p", "P" } },
+    { 0x14, { "q", "Q" } },
+    { 0x15, { "r", "R" } },
+    { 0x16, { "s", "S" } },
+    { 0x17, { "t", "T" } },
+    { 0x18, { "u", "U" } },
+    { 0x19, { "v", "V" } },
+    { 0x1a, { "w", "W" } },
+    { 0x1b, { "x", "X" } },
+    { 0x1c, { "y", "Y" } },
+    { 0x1d, { "z", "Z" } },
+    { 0x1e, { "1", "!", "|" } },
+    { 0x1f, { "2", "\"", "@" } },
+    { 0x20, { "3", "·", "#" } },
+    { 0x21, { "4", "$", "~" } },
+    { 0x22, { "5", "%", "€" } },
+    { 0x23, { "6", "&", "¬" } },
+    { 0x24, { "7", "/" } },
+    { 0x25, { "8", "(" } },
+    { 0x26, { "9", ")" } },
+    { 0x27, { "0", "=" } },
+    { 0x2d, { "'", "?" } },
+    { 0x2e, { "¡", "¿" } },
+    { 0x2f, { "`", "^", "[" } },
+    { 0x30, { "+", "*", "]" } },
+    { 0x32, { "ç", "Ç", "}" } },
+    { 0x33, { "ñ", "Ñ" } },
+    { 0x34, { "´", "¨", "{" } },
+    { 0x35, { "º", "ª", "\\" } },
+    { 0x36, { ",", ";" } },
+    { 0x37, { ".", ":" } },
+    { 0x38, { "-", "_" } },
+    { 0x64, { "<", ">" } },
+    { 0 },
+};
+
+static const layout_dead_key es_dead_keys[] = {
+    { "`", GRAVE },
+    { "^", CIRCUMFLEX },
+    { "´", ACUTE },
+    { "¨", DIAERESIS },
+    { "~", TILDE },
+    { NULL },
+};
+
+static const keyboard_layout layouts[] = {
+    { "us", "US", us_keys, NULL },
+    { "us-intl", "US international, with dead keys", us_intl_keys, us_intl_dead_keys },
+    { "de", "German", de_keys, de_dead_keys },
+    { "fr", "French AZERTY", fr_keys, fr_dead_keys },
+    { "uk", "UK", uk_keys, NULL },
+    { "se", "Swedish and Finnish", se_keys, se_dead_keys },
+    { "no", "Norwegian", no_keys, no_dead_keys },
+    { "dk", "Danish", dk_keys, dk_dead_keys },
+    { "es", "Spanish", es_keys, es_dead_keys },
+};
+
+// returns NULL if there's no layout with that name
+const keyboard_layout *find_layout(const char *name)
+{
+    for (size_t i = 0; i < sizeof(layouts) / sizeof(layouts[0]); i++) {
+        if (strcmp(layouts[i].name, name) == 0) {
+            return &layouts[i];
+        }
+    }
+
+    return NULL;
+}
+
+void list_layouts(void)
+{
+    for (size_t i = 0; i < sizeof(layouts) / sizeof(layouts[0]); i++) {
+        printf("  %-10s %s\n", layouts[i].name, layouts[i].description);
+    }
+}
+
+// decode the utf-8 character at *pos and move past it. invalid bytes come
+// out as U+FFFD, one at a time
+uint32_t next_char(const char *text, size_t len, size_t *pos)
+{
+    const uint8_t *s = (const uint8_t *) text + *pos;
+    size_t left = len - *pos;
+    uint32_t c;
+    size_t n;
+
+    if (s[0] < 0x80) {
+        (*pos)++;
+        return s[0];
+    } else if ((s[0] & 0xe0) == 0xc0) {
+        c = s[0] & 0x1f;
+        n = 2;
+    } else if ((s[0] & 0xf0) == 0xe0) {
+        c = s[0] & 0x0f;
+        n = 3;
+    } else if ((s[0] & 0xf8) == 0xf0) {
+        c = s[0] & 0x07;
+        n = 4;
+    } else {
+        (*pos)++;
+        return 0xfffd;
+    }
+
+    for (size_t i = 1; i < n; i++) {
+        if (i >= left || (s[i] & 0xc0) != 0x80) {
+            (*pos)++;
+            return 0xfffd;
+        }
+        c = (c << 6) | (s[i] & 0x3f);
+    }
+
+    *pos += n;
+    return c;
+}
+
+static uint32_t first_char(const char *text)
+{
+    size_t pos = 0;
+
+    return next_char(text, strlen(text), &pos);
+}
+
+// the key typing c without dead keys. returns -1 if there's none
+static int find_key(const keyboard_layout *layout, uint32_t c, keystroke *key)
+{
+    for (const layout_key *k = layout->keys; k->keycode != 0; k++) {
+        for (int level = 0; level < 4; level++) {
+            if (k->levels[level] != NULL && first_char(k->levels[level]) == c) {
+                key->keycode = k->keycode;
+                key->modifiers = level_modifiers[level];
+                return 0;
+            }
+        }
+    }
+
+    return -1;
+}
+
+static int is_dead_key(const keyboard_layout *layout, uint32_t c)
+{
+    for (const layout_dead_key *d = layout->dead_keys; d != NULL && d->key != NULL; d++) {
+        if (first_char(d->key) == c) {
+            return 1;
+        }
+    }
+
+    return 0;
+}
+
+// the keys typing c on the layout, one after another: the key for it, or a
+// dead key and the character it combines with. returns how many, or -1 if
+// the layout can't type c
+int char_to_keys(const keyboard_layout *layout, uint32_t c, keystroke keys[2])
+{
+    static const keystroke space = { 0x2c, 0 };
+
+    // the same everywhere
+    switch (c) {
+        case '\n':
+            keys[0] = (keystroke) { 0x28, 0 }; // enter
+            return 1;
+        case '\t':
+            keys[0] = (keystroke) { 0x2b, 0 };
+            return 1;
+        case ' ':
+            keys[0] = space;
+            return 1;
+    }
+
+    // a dead key on its own is typed with a space after it
+    if (find_key(layout, c, &keys[0]) == 0) {
+        if (is_dead_key(layout, c)) {
+            keys[1] = space;
+            return 2;
+        }
+        return 1;
+    }
+
+    for (const layout_dead_key *d = layout->dead_keys; d != NULL && d->key != NULL; d++) {
+        size_t len = strlen(d->combines);
+        size_t pos = 0;
+
+        while (pos < len) {
+            uint32_t base = next_char(d->combines, len, &pos);
+            uint32_t combined = next_char(d->combines, len, &pos);
+
+            if (combined == c &&
+                    find_key(layout, first_char(d->key), &keys[0]) == 0 &&
+                    find_key(layout, base, &keys[1]) == 0) {
+                return 2;
+            }
+        }
+    }
+
+    return -1;
+}
+
+// keys typed to find out the layout of the target, each dead key is
+// followed by a space or a letter so what they type shows up
+static const keystroke layout_probe_keys[] = {
+    { 0x1c, 0 }, // y or z
+    { 0x14, 0 }, // q or a
+    { 0x10, 0 }, // m or ,
+    { 0x33, 0 },
+    { 0x34, 0 },
+    { 0x08, 0 }, // e, accented by a dead key before it
+    { 0x2c, 0 },
+    { 0x2d, 0 },
+    { 0x2e, 0 },
+    { 0x2c, 0 },
+    { 0x2f, 0 },
+    { 0x2c, 0 },
+    { 0x30, 0 },
+    { 0x2c, 0 },
+    { 0x35, 0 },
+    { 0x2c, 0 },
+    { 0x1f, SHIFT }, // @ or "
+};
+
+size_t layout_probe(const keystroke **keys)
+{
+    *keys = layout_probe_keys;
+    return sizeof(layout_probe_keys) / sizeof(layout_probe_keys[0]);
+}
+
+// the character a key types on the layout, or 0 if none
+static uint32_t key_char(const keyboard_layout *layout, keystroke key)
+{
+    if (key.keycode == 0x2c) {
+        return ' ';
+    }
+
+    for (const layout_key *k = layout->keys; k->keycode != 0; k++) {
+        if (k->keycode != key.keycode) {
+            continue;
+        }
+        for (int level = 0; level < 4; level++) {
+            if (level_modifiers[level] == key.modifiers && k->levels[level] != NULL) {
+                return first_char(k->levels[level]);
+            }
+        }
+    }
+
+    return 0;
+}
+
+// the character a dead key makes of the next one, or 0 if they don't combine
+static uint32_t dead_key_combine(const keyboard_layout *layout, uint32_t dead, uint32_t c)
+{
+    for (const layout_dead_key *d = layout->dead_keys; d != NULL && d->key != NULL; d++) {
+        size_t len = strlen(d->combines);
+        size_t pos = 0;
+
+        if (first_char(d->key) != dead) {
+            continue;
+        }
+
+        while (pos < len) {
+            uint32_t base = next_char(d->combines, len, &pos);
+            uint32_t combined = next_char(d->combines, len, &pos);
+
+            if (base == c) {
+                return combined;
+            }
+        }
+    }
+
+    return 0;
+}
+
+// encode c as utf-8, returns the number of bytes
+static size_t put_char(uint32_t c, char *out)
+{
+    if (c < 0x80) {
+        out[0] = c;
+        return 1;
+    } else if (c < 0x800) {
+        out[0] = 0xc0 | (c >> 6);
+        out[1] = 0x80 | (c & 0x3f);
+        return 2;
+    } else if (c < 0x10000) {
+        out[0] = 0xe0 | (c >> 12);
+        out[1] = 0x80 | ((c >> 6) & 0x3f);
+        out[2] = 0x80 | (c & 0x3f);
+        return 3;
+    }
+
+    out[0] = 0xf0 | (c >> 18);
+    out[1] = 0x80 | ((c >> 12) & 0x3f);
+    out[2] = 0x80 | ((c >> 6) & 0x3f);
+    out[3] = 0x80 | (c & 0x3f);
+    return 4;
+}
+
+// what typing the keys one after another gives on the layout, as utf-8
+// without the spaces.
+// returns -1 if a key types nothing there
+static int layout_output(const keyboard_layout *layout, const keystroke *keys, size_t count, char *out, size_t len)
+{
+    uint32_t dead = 0;
+    size_t used = 0;
+
+    for (size_t i = 0; i < count; i++) {
+        uint32_t c = key_char(layout, keys[i]);
+        uint32_t typed[2];
+        int n = 0;
+
+        if (c == 0) {
+            return -1;
+        }
+
+        // a dead key followed by something it doesn't combine with types
+        // both, and followed by a space just itself
+        if (dead != 0) {
+            uint32_t combined = c == ' ' ? dead : dead_key_combine(layout, dead, c);
+
+            if (combined != 0) {
+                typed[n++] = combined;
+            } else {
+                typed[n++] = dead;
+                typed[n++] = c;
+            }
+            dead = 0;
+        } else if (is_dead_key(layout, c)) {
+            dead = c;
+        } else {
+            typed[n++] = c;
+        }
+
+        for (int j = 0; j < n; j++) {
+            if (typed[j] == ' ') {
+                continue;
+            }
+            if (used + 4 >= len) {
+                return -1;
+            }
+            used += put_char(typed[j], out + used);
+        }
+    }
+
+    out[used] = '\0';
+    return 0;
+}
+
+// the layout typing the probe keys gives what the user saw, spaces aside.
+// returns NULL if it's none of the known ones
+const keyboard_layout *match_layout(const char *seen)
+{
+    char wanted[256];
+    char expected[256];
+    size_t len = 0;
+
+    for (; *seen != '\0' && len < sizeof(wanted) - 1; seen++) {
+        if (!isspace((unsigned char) *seen)) {
+            wanted[len++] = *seen;
+        }
+    }
+    wanted[len] = '\0';
+
+    for (size_t i = 0; i < sizeof(layouts) / sizeof(layouts[0]); i++) {
+        if (layout_output(&layouts[i], layout_probe_keys,
+                    sizeof(layout_probe_keys) / sizeof(layout_probe_keys[0]),
+                    expected, sizeof(expected)) == 0 &&
+                strcmp(expected, wanted) == 0) {
+            return &layouts[i];
+        }
+    }
+
+    return NULL;
+}
+
+// compose sequences, as the two characters typed after the compose key
+static const struct {
+    const char *sequence;
+    const char *result;
+} compose_sequences[] = {
+    { "ss", "ß" },
+    { "ae", "æ" },
+    { "AE", "Æ" },
+    { "oe", "œ" },
+    { "OE", "Œ" },
+    { "e=", "€" },
+    { "l-", "£" },
+    { "y=", "¥" },
+    { "c/", "¢" },
+    { "co", "©" },
+    { "ro", "®" },
+    { "tm", "™" },
+    { "so", "§" },
+    { "oo", "°" },
+    { "+-", "±" },
+    { "xx", "×" },
+    { "-:", "÷" },
+    { "12", "½" },
+    { "14", "¼" },
+    { "34", "¾" },
+    { "^2", "²" },
+    { "^3", "³" },
+    { "!!", "¡" },
+    { "??", "¿" },
+    { "<<", "«" },
+    { ">>", "»" },
+    { "..", "…" },
+};
+
+// accents, typed before or after the letter
+static const struct {
+    uint32_t accent;
+    const char *combines;
+} compose_accents[] = {
+    { '\'', ACUTE "cçCÇ" },
+    { '`', GRAVE },
+    { '^', CIRCUMFLEX },
+    { '"', DIAERESIS },
+    { '~', TILDE },
+    { ',', "cçCÇ" },
+    { 'o', "aåAÅ" },
+    { '/', "oøOØ" },
+};
+
+static uint32_t compose_accent(uint32_t accent, uint32_t letter)
+{
+    for (size_t i = 0; i < sizeof(compose_accents) / sizeof(compose_accents[0]); i++) {
+        const char *combines = compose_accents[i].combines;
+        size_t len = strlen(combines);
+        size_t pos = 0;
+
+        if (compose_accents[i].accent != accent) {
+            continue;
+        }
+
+        while (pos < len) {
+            uint32_t base = next_char(combines, len, &pos);
+            uint32_t combined = next_char(combines, len, &pos);
+
+            if (base == letter) {
+                return combined;
+            }
+        }
+    }
+
+    return 0;
+}
+
+// the character made by typing first and second after the compose key, as
+// with the x11 compose key. returns 0 if they don't make one
+uint32_t compose(uint32_t first, uint32_t second)
+{
+    uint32_t c;
+
+    for (size_t i = 0; i < sizeof(compose_sequences) / sizeof(compose_sequences[0]); i++) {
+        const char *sequence = compose_sequences[i].sequence;
+
+        if ((uint32_t) sequence[0] == first && (uint32_t) sequence[1] == second) {
+            return first_char(compose_sequences[i].result);
+        }
+    }
+
+    c = compose_accent(first, second);
+    if (c == 0) {
+        c = compose_accent(second, first);
+    }
+
+    return c;
+}
@@ -0,0 +1,51 @@
+#ifndef __TYPING_H
+#define __TYPING_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+// text being typed on the target, one character at a time
+typedef struct {
+    char *text;
+    size_t len;
+    size_t pos;
+    uint32_t next; // when to type the next character, in sdl ticks
+} typing_state;
+
+// what a key types on the target: plain, with shift, with altgr and with
+// both, as utf-8. NULL where it types nothing
+typedef struct {
+    uint8_t keycode;
+    const char *levels[4];
+} layout_key;
+
+// a dead key, and the pairs of characters it combines, e.g. "aáeé" for
+// an acute accent
+typedef struct {
+    const char *key;
+    const char *combines;
+} layout_dead_key;
+
+// the keyboard layout set up on the target, text is typed with the keys
+// that give the same characters there
+typedef struct {
+    const char *name;
+    const char *description;
+    const layout_key *keys; // ends with keycode 0
+    const layout_dead_key *dead_keys; // ends with NULL
+} keyboard_layout;
+
+typedef struct {
+    uint8_t keycode;
+    uint8_t modifiers;
+} keystroke;
+
+const keyboard_layout *find_layout(const char *name);
+void list_layouts(void);
+uint32_t next_char(const char *text, size_t len, size_t *pos);
+int char_to_keys(const keyboard_layout *layout, uint32_t c, keystroke keys[2]);
+uint32_t compose(uint32_t first, uint32_t second);
+size_t layout_probe(const keystroke **keys);
+const keyboard_layout *match_layout(const char *seen);
+
+#endif
@@ -1,33 +1,451 @@
 #!/usr/bin/env python3
 
+import os
+import re
 import sys
+import shlex
+import select
 import socket
-from struct import pack
-from time import sleep
+import struct
+from struct import pack, unpack
+from time import sleep, time
 
 import usb_hid as hid
 
 UDP_IP = '192.168.1.10'
 UDP_PORT = 4444
 
+# --ipv4 or --ipv6 limit the target's addresses to one kind
+ADDRESS_FAMILY = socket.AF_UNSPEC
+
+# where the daemon listens for commands
+SOCKET_PATH = os.path.join(os.environ.get('XDG_RUNTIME_DIR', '/tmp'),
+                           'nethid-{}.sock'.format(os.getuid()))
+
 def send_scancode(scancode, pressed):
-    sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
     packet = pack('BBBBB', 0x01, 0x01, pressed, 0x00, scancode)
-    sock.sendto(packet, (UDP_IP, UDP_PORT))
+    send_packet(packet)
 
-def send_move(buttons, x, y, vertical, horizontal):
+def send_keyboard_report(modifiers, keys):
+    keys = (list(keys) + [0] * 6)[:6]
+    packet = pack('BBB6B', 0x04, 0x01, modifiers, *keys)
+    send_packet(packet)
+
+SHIFT = 0x02
+ALTGR = 0x40
+TYPE_DELAY = 0.03
+
+# what typing each level of a key takes
+LEVELS = (0, SHIFT, ALTGR, SHIFT | ALTGR)
+
+# what the keys type on a layout: plain, with shift, altgr and both, a
+# space where they type nothing
+US_KEYS = {}
+for i, c in enumerate('abcdefghijklmnopqrstuvwxyz'):
+    US_KEYS[0x04 + i] = c + c.upper()
+for i, (c, shifted) in enumerate(zip('1234567890', '!@#$%^&*()')):
+    US_KEYS[0x1e + i] = c + shifted
+for keycode, levels in zip(
+        (0x2d, 0x2e, 0x2f, 0x30, 0x31, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38),
+        ('-_', '=+', '[{', ']}', '\\|', ';:', '\'"', '`~', ',<', '.>', '/?')):
+    US_KEYS[keycode] = levels
+
+# as on windows, the quotes, ` ~ and ^ are dead keys
+US_INTL_KEYS = dict(US_KEYS)
+for keycode, levels in {
+        0x04: 'áÁ', 0x06: '©¢', 0x07: 'ðÐ', 0x08: 'éÉ', 0x0c: 'íÍ',
+        0x0f: 'øØ', 0x10: 'µ', 0x11: 'ñÑ', 0x12: 'óÓ', 0x13: 'öÖ',
+        0x14: 'äÄ', 0x15: '®', 0x16: 'ß§', 0x17: 'þÞ', 0x18: 'úÚ',
+        0x1a: 'åÅ', 0x1c: 'üÜ', 0x1d: 'æÆ', 0x1e: '¡¹', 0x1f: '²',
+        0x20: '³', 0x21: '¤£', 0x22: '€', 0x23: '¼', 0x24: '½',
+        0x25: '¾', 0x26: '‘', 0x27: '’', 0x2d: '¥', 0x2e: '×÷',
+        0x2f: '«', 0x30: '»', 0x31: '¬¦', 0x33: '¶°', 0x34: '´¨',
+        0x36: 'çÇ', 0x38: '¿'}.items():
+    US_INTL_KEYS[keycode] += levels
+US_INTL_DEAD_KEYS = {
+    "'": 'aáeéiíoóuúyýcçAÁEÉIÍOÓUÚYÝCÇ',
+    '"': 'aäeëiïoöuüyÿAÄEËIÏOÖUÜ',
+    '`': 'aàeèiìoòuùAÀEÈIÌOÒUÙ',
+    '~': 'aãnñoõAÃNÑOÕ',
+    '^': 'aâeêiîoôuûAÂEÊIÎOÔUÛ',
+}
+
+DE_KEYS = {
+    0x04: 'aA', 0x05: 'bB', 0x06: 'cC', 0x07: 'dD', 0x08: 'eE€', 0x09: 'fF',
+    0x0a: 'gG', 0x0b: 'hH', 0x0c: 'iI', 0x0d: 'jJ', 0x0e: 'kK', 0x0f: 'lL',
+    0x10: 'mMµ', 0x11: 'nN', 0x12: 'oO', 0x13: 'pP', 0x14: 'qQ@', 0x15: 'rR',
+    0x16: 'sS', 0x17: 'tT', 0x18: 'uU', 0x19: 'vV', 0x1a: 'wW', 0x1b: 'xX',
+    0x1c: 'zZ', 0x1d: 'yY', 0x1e: '1!', 0x1f: '2"²', 0x20: '3§³', 0x21: '4$',
+    0x22: '5%', 0x23: '6&', 0x24: '7/{', 0x25: '8([', 0x26: '9)]',
+    0x27: '0=}', 0x2d: 'ß?\\', 0x2e: '´`', 0x2f: 'üÜ', 0x30: '+*~',
+    0x32: "#'", 0x33: 'öÖ', 0x34: 'äÄ', 0x35: '^°', 0x36: ',;', 0x37: '.:',
+    0x38: '-_', 0x64: '<>|',
+}
+DE_DEAD_KEYS = {
+    '^': 'aâeêiîoôuûAÂEÊIÎOÔUÛ',
+    '´': 'aáeéiíoóuúyýAÁEÉIÍOÓUÚYÝ',
+    '`': 'aàeèiìoòuùAÀEÈIÌOÒUÙ',
+}
+FR_KEYS = {
+    0x04: 'qQ', 0x05: 'bB', 0x06: 'cC', 0x07: 'dD', 0x08: 'eE€', 0x09: 'fF',
+    0x0a: 'gG', 0x0b: 'hH', 0x0c: 'iI', 0x0d: 'jJ', 0x0e: 'kK', 0x0f: 'lL',
+    0x10: ',?', 0x11: 'nN', 0x12: 'oO', 0x13: 'pP', 0x14: 'aA', 0x15: 'rR',
+    0x16: 'sS', 0x17: 'tT', 0x18: 'uU', 0x19: 'vV', 0x1a: 'zZ', 0x1b: 'xX',
+    0x1c: 'yY', 0x1d: 'wW', 0x1e: '&1', 0x1f: 'é2~', 0x20: '"3#', 0x21: "'4{",
+    0x22: '(5[', 0x23: '-6|', 0x24: 'è7`', 0x25: '_8\\', 0x26: 'ç9',
+    0x27: 'à0@', 0x2d: ')°]', 0x2e: '=+}', 0x2f: '^¨', 0x30: '$£¤',
+    0x32: '*µ', 0x33: 'mM', 0x34: 'ù%', 0x35: '²', 0x36: ';.', 0x37: ':/',
+    0x38: '!§', 0x64: '<>',
+}
+FR_DEAD_KEYS = {
+    '^': 'aâeêiîoôuûAÂEÊIÎOÔUÛ',
+    '¨': 'aäeëiïoöuüyÿAÄEËIÏOÖUÜ',
+    '~': 'aãnñoõAÃNÑOÕ',
+    '`': 'aàeèiìoòuùAÀEÈIÌOÒUÙ',
+}
+UK_KEYS = {
+    0x04: 'aAáÁ', 0x05: 'bB', 0x06: 'cC', 0x07: 'dD', 0x08: 'eEéÉ',
+    0x09: 'fF', 0x0a: 'gG', 0x0b: 'hH', 0x0c: 'iIíÍ', 0x0d: 'jJ', 0x0e: 'kK',
+    0x0f: 'lL', 0x10: 'mM', 0x11: 'nN', 0x12: 'oOóÓ', 0x13: 'pP', 0x14: 'qQ',
+    0x15: 'rR', 0x16: 'sS', 0x17: 'tT', 0x18: 'uUúÚ', 0x19: 'vV', 0x1a: 'wW',
+    0x1b: 'xX', 0x1c: 'yY', 0x1d: 'zZ', 0x1e: '1!', 0x1f: '2"', 0x20: '3£',
+    0x21: '4$€', 0x22: '5%', 0x23: '6^', 0x24: '7&', 0x25: '8*', 0x26: '9(',
+    0x27: '0)', 0x2d: '-_', 0x2e: '=+', 0x2f: '[{', 0x30: ']}', 0x32: '#~',
+    0x33: ';:', 0x34: "'@", 0x35: '`¬¦', 0x36: ',<', 0x37: '.>', 0x38: '/?',
+    0x64: '\\|',
+}
+SE_KEYS = {
+    0x04: 'aA', 0x05: 'bB', 0x06: 'cC', 0x07: 'dD', 0x08: 'eE€', 0x09: 'fF',
+    0x0a: 'gG', 0x0b: 'hH', 0x0c: 'iI', 0x0d: 'jJ', 0x0e: 'kK', 0x0f: 'lL',
+    0x10: 'mMµ', 0x11: 'nN', 0x12: 'oO', 0x13: 'pP', 0x14: 'qQ', 0x15: 'rR',
+    0x16: 'sS', 0x17: 'tT', 0x18: 'uU', 0x19: 'vV', 0x1a: 'wW', 0x1b: 'xX',
+    0x1c: 'yY', 0x1d: 'zZ', 0x1e: '1!', 0x1f: '2"@', 0x20: '3#£', 0x21: '4¤$',
+    0x22: '5%€', 0x23: '6&', 0x24: '7/{', 0x25: '8([', 0x26: '9)]',
+    0x27: '0=}', 0x2d: '+?\\', 0x2e: '´`', 0x2f: 'åÅ', 0x30: '¨^~',
+    0x32: "'*", 0x33: 'öÖ', 0x34: 'äÄ', 0x35: '§½', 0x36: ',;', 0x37: '.:',
+    0x38: '-_', 0x64: '<>|',
+}
+SE_DEAD_KEYS = {
+    '´': 'aáeéiíoóuúyýAÁEÉIÍOÓUÚYÝ',
+    '`': 'aàeèiìoòuùAÀEÈIÌOÒUÙ',
+    '¨': 'aäeëiïoöuüyÿAÄEËIÏOÖUÜ',
+    '^': 'aâeêiîoôuûAÂEÊIÎOÔUÛ',
+    '~': 'aãnñoõAÃNÑOÕ',
+}
+NO_KEYS = {
+    0x04: 'aA', 0x05: 'bB', 0x06: 'cC', 0x07: 'dD', 0x08: 'eE€', 0x09: 'fF',
+    0x0a: 'gG', 0x0b: 'hH', 0x0c: 'iI', 0x0d: 'jJ', 0x0e: 'kK', 0x0f: 'lL',
+    0x10: 'mMµ', 0x11: 'nN', 0x12: 'oO', 0x13: 'pP', 0x14: 'qQ', 0x15: 'rR',
+    0x16: 'sS', 0x17: 'tT', 0x18: 'uU', 0x19: 'vV', 0x1a: 'wW', 0x1b: 'xX',
+    0x1c: 'yY', 0x1d: 'zZ', 0x1e: '1!', 0x1f: '2"@', 0x20: '3#£', 0x21: '4¤$',
+    0x22: '5%€', 0x23: '6&', 0x24: '7/{', 0x25: '8([', 0x26: '9)]',
+    0x27: '0=}', 0x2d: '+?', 0x2e: '\\`´', 0x2f: 'åÅ', 0x30: '¨^~',
+    0x32: "'*", 0x33: 'øØ', 0x34: 'æÆ', 0x35: '|§', 0x36: ',;', 0x37: '.:',
+    0x38: '-_', 0x64: '<>',
+}
+NO_DEAD_KEYS = {
+    '´': 'aáeéiíoóuúyýAÁEÉIÍOÓUÚYÝ',
+    '`': 'aàeèiìoòuùAÀEÈIÌOÒUÙ',
+    '¨': 'aäeëiïoöuüyÿAÄEËIÏOÖUÜ',
+    '^': 'aâeêiîoôuûAÂEÊIÎOÔUÛ',
+    '~': 'aãnñoõAÃNÑOÕ',
+}
+DK_KEYS = {
+    0x04: 'aA', 0x05: 'bB', 0x06: 'cC', 0x07: 'dD', 0x08: 'eE€', 0x09: 'fF',
+    0x0a: 'gG', 0x0b: 'hH', 0x0c: 'iI', 0x0d: 'jJ', 0x0e: 'kK', 0x0f: 'lL',
+    0x10: 'mMµ', 0x11: 'nN', 0x12: 'oO', 0x13: 'pP', 0x14: 'qQ', 0x15: 'rR',
+    0x16: 'sS', 0x17: 'tT', 0x18: 'uU', 0x19: 'vV', 0x1a: 'wW', 0x1b: 'xX',
+    0x1c: 'yY', 0x1d: 'zZ', 0x1e: '1!', 0x1f: '2"@', 0x20: '3#£', 0x21: '4¤$',
+    0x22: '5%€', 0x23: '6&', 0x24: '7/{', 0x25: '8([', 0x26: '9)]',
+    0x27: '0=}', 0x2d: '+?', 0x2e: '´`|', 0x2f: 'åÅ', 0x30: '¨^~', 0x32: "'*",
+    0x33: 'æÆ', 0x34: 'øØ', 0x35: '½§', 0x36: ',;', 0x37: '.:', 0x38: '-_',
+    0x64: '<>\\',
+}
+DK_DEAD_KEYS = {
+    '´': 'aáeéiíoóuúyýAÁEÉIÍOÓUÚYÝ',
+    '`': 'aàeèiìoòuùAÀEÈIÌOÒUÙ',
+    '¨': 'aäeëiïoöuüyÿAÄEËIÏOÖUÜ',
+    '^': 'aâeêiîoôuûAÂEÊIÎOÔUÛ',
+    '~': 'aãnñoõAÃNÑOÕ',
+}
+ES_KEYS = {
+    0x04: 'aA', 0x05: 'bB', 0x06: 'cC', 0x07: 'dD', 0x08: 'eE€', 0x09: 'fF',
+    0x0a: 'gG', 0x0b: 'hH', 0x0c: 'iI', 0x0d: 'jJ', 0x0e: 'kK', 0x0f: 'lL',
+    0x10: 'mM', 0x11: 'nN', 0x12: 'oO', 0x13: 'pP', 0x14: 'qQ', 0x15: 'rR',
+    0x16: 'sS', 0x17: 'tT', 0x18: 'uU', 0x19: 'vV', 0x1a: 'wW', 0x1b: 'xX',
+    0x1c: 'yY', 0x1d: 'zZ', 0x1e: '1!|', 0x1f: '2"@', 0x20: '3·#',
+    0x21: '4$~', 0x22: '5%€', 0x23: '6&¬', 0x24: '7/', 0x25: '8(', 0x26: '9)',
+    0x27: '0=', 0x2d: "'?", 0x2e: '¡¿', 0x2f: '`^[', 0x30: '+*]', 0x32: 'çÇ}',
+    0x33: 'ñÑ', 0x34: '´¨{', 0x35: 'ºª\\', 0x36: ',;', 0x37: '.:', 0x38: '-_',
+    0x64: '<>',
+}
+ES_DEAD_KEYS = {
+    '`': 'aàeèiìoòuùAÀEÈIÌOÒUÙ',
+    '^': 'aâeêiîoôuûAÂEÊIÎOÔUÛ',
+    '´': 'aáeéiíoóuúyýAÁEÉIÍOÓUÚYÝ',
+    '¨': 'aäeëiïoöuüyÿAÄEËIÏOÖUÜ',
+    '~': 'aãnñoõAÃNÑOÕ',
+}
+
+# keyboard layouts set up on the target, as the keys and the dead keys with
+# the pairs of characters they combine
+LAYOUTS = {
+    'us': (US_KEYS, {}),
+    'us-intl': (US_INTL_KEYS, US_INTL_DEAD_KEYS),
+    'de': (DE_KEYS, DE_DEAD_KEYS),
+    'fr': (FR_KEYS, FR_DEAD_KEYS),
+    'uk': (UK_KEYS, {}),
+    'se': (SE_KEYS, SE_DEAD_KEYS),
+    'no': (NO_KEYS, NO_DEAD_KEYS),
+    'dk': (DK_KEYS, DK_DEAD_KEYS),
+    'es': (ES_KEYS, ES_DEAD_KEYS),
+}
+
+# characters on a layout, as the (keycode, modifiers) typing them, one
+# after another
+def layout_keymap(name):
+    if not name in LAYOUTS:
+        raise Exception('Unknown layout: {}, these are known: {}'.format(
+            name, ', '.join(LAYOUTS)))
+    keys, dead_keys = LAYOUTS[name]
+    keymap = {}
+    for keycode, levels in keys.items():
+        for level, c in enumerate(levels):
+            if c != ' ' and not c in keymap:
+                keymap[c] = [(keycode, LEVELS[level])]
+    for dead, combines in dead_keys.items():
+        for base, combined in zip(combines[::2], combines[1::2]):
+            if not combined in keymap and base in keymap:
+                keymap[combined] = keymap[dead] + keymap[base]
+        # on its own a dead key is typed with a space after it
+        keymap[dead] = keymap[dead] + [(0x2c, 0)]
+    keymap['\n'] = [(0x28, 0)]
+    keymap['\t'] = [(0x2b, 0)]
+    keymap[' '] = [(0x2c, 0)]
+    return keymap
+
+# key names in chords are the ones on a us layout, text is typed for the
+# layout of the target
+KEYMAP = layout_keymap('us')
+keymap = KEYMAP
+
+# what a line break in --stdin input is typed as
+NEWLINES = {
+    'enter': '\n',
+    'space': ' ',
+    'none': '',
+}
+
+def type_text(text, delay=TYPE_DELAY):
+    for c in text:
+        if not c in keymap:
+            print('Skipping {!r}, no key for it'.format(c))
+            continue
+        for keycode, modifiers in keymap[c]:
+            send_keyboard_report(modifiers, [keycode])
+            send_keyboard_report(0x00, [])
+        sleep(delay)
+
+MODIFIERS = {
+    'ctrl': 0x01,
+    'shift': 0x02,
+    'alt': 0x04,
+    'gui': 0x08,
+    'win': 0x08,
+    'super': 0x08,
+    'rctrl': 0x10,
+    'rshift': 0x20,
+    'ralt': 0x40,
+    'altgr': 0x40,
+    'rgui': 0x80,
+}
+
+KEYS = {
+    'enter': 0x28,
+    'return': 0x28,
+    'esc': 0x29,
+    'escape': 0x29,
+    'backspace': 0x2a,
+    'tab': 0x2b,
+    'space': 0x2c,
+    'capslock': 0x39,
+    'printscreen': 0x46,
+    'scrolllock': 0x47,
+    'pause': 0x48,
+    'insert': 0x49,
+    'home': 0x4a,
+    'pageup': 0x4b,
+    'del': 0x4c,
+    'delete': 0x4c,
+    'end': 0x4d,
+    'pagedown': 0x4e,
+    'right': 0x4f,
+    'left': 0x50,
+    'down': 0x51,
+    'up': 0x52,
+    'numlock': 0x53,
+    'menu': 0x65,
+}
+for i in range(12):
+    KEYS['f{}'.format(i + 1)] = 0x3a + i
+
+# ctrl+alt+del -> modifiers, [keycodes]
+def parse_chord(chord):
+    modifiers = 0x00
+    keys = []
+    for name in chord.lower().split('+'):
+        if name in MODIFIERS:
+            modifiers |= MODIFIERS[name]
+        elif name in KEYS:
+            keys.append(KEYS[name])
+        elif len(name) == 1 and name in KEYMAP and not KEYMAP[name][0][1]:
+            keys.append(KEYMAP[name][0][0])
+        else:
+            raise Exception('Invalid key: {}'.format(name))
+    if len(keys) > 6:
+        raise Exception('Too many keys: {}'.format(chord))
+    return modifiers, keys
+
+def send_chord(chord):
+    modifiers, keys = parse_chord(chord)
+    # modifiers go down first and come up last, so the target sees the
+    # chord and not the bare keys
+    if modifiers and len(keys):
+        send_keyboard_report(modifiers, [])
+    send_keyboard_report(modifiers, keys)
+    if modifiers and len(keys):
+        send_keyboard_report(modifiers, [])
+    send_keyboard_report(0x00, [])
+
+def send_system_control(control):
+    packet = pack('BBB', 0x06, 0x01, control)
+    send_packet(packet)
+
+SYSTEM_CONTROLS = {
+    'power': 0x01,
+    'sleep': 0x02,
+    'wake': 0x03,
+}
+
+MDNS_SERVICE = '_nethid._udp.local'
+
+def dns_name(packet, offset):
+    """Returns the name at offset and the offset after it"""
+    labels = []
+    end = None
+    # a bad packet could point back at itself
+    for _ in range(128):
+        length = packet[offset]
+        if length & 0xc0 == 0xc0:
+            if end is None:
+                end = offset + 2
+            offset = ((length & 0x3f) << 8) | packet[offset + 1]
+            continue
+        offset += 1
+        if length == 0:
+            break
+        labels.append(packet[offset:offset + length].decode(errors='replace'))
+        offset += length
+    return '.'.join(labels), end if end is not None else offset
+
+def mdns_services(packet):
+    """Yields (name, port) of the nethid services in an mdns answer"""
+    try:
+        _, _, questions, *records = unpack('>HHHHHH', packet[:12])
+        offset = 12
+        for _ in range(questions):
+            offset = dns_name(packet, offset)[1] + 4
+        for _ in range(sum(records)):
+            name, offset = dns_name(packet, offset)
+            rtype, _, _, length = unpack('>HHIH', packet[offset:offset + 10])
+            offset += 10
+            # srv: priority, weight, port and target
+            if rtype == 33 and name.endswith('.' + MDNS_SERVICE):
+                port, = unpack('>H', packet[offset + 4:offset + 6])
+                yield name[:-len(MDNS_SERVICE) - 1], port
+            offset += length
+    except (IndexError, struct.error):
+        return
+
+def discover(timeout=1.0):
+    """Finds devices with a broadcast and over mdns, returns (name, ip,
+    port) of each"""
+    sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    sock.setsockopt(socket.SOL_SOCKET, socket.SO_BROADCAST, 1)
+    sock.sendto(pack('BB', 0x0a, 0x01), ('255.255.255.255', UDP_PORT))
+
+    # a query from a port other than 5353 is answered straight back to it
+    mdns = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    query = pack('>HHHHHH', 0, 0, 1, 0, 0, 0)
+    for label in MDNS_SERVICE.split('.'):
+        query += pack('B', len(label)) + label.encode()
+    query += pack('>BHH', 0, 12, 1) # ptr, in
+    mdns.sendto(query, ('224.0.0.251', 5353))
+
+    devices = {}
+    deadline = time() + timeout
+    while time() < deadline:
+        ready, _, _ = select.select([sock, mdns], [], [], deadline - time())
+        for s in ready:
+            packet, addr = s.recvfrom(1500)
+            if s is mdns:
+                for name, port in mdns_services(packet):
+                    devices[addr[0]] = (name, addr[0], port)
+            elif len(packet) >= 2 and packet[0] == 0x0b and packet[1] == 0x01:
+                devices.setdefault(addr[0], (
+                    packet[2:].decode(errors='replace'), addr[0], addr[1]))
+    return list(devices.values())
+
+# six hex bytes, all separated by : or all by -, as hidgui takes them
+MAC_PATTERN = re.compile(r'[0-9a-fA-F]{1,2}([:-])(?:[0-9a-fA-F]{1,2}\1){4}[0-9a-fA-F]{1,2}')
+
+# wake on lan: six 0xff bytes, then the mac address sixteen times
+def send_wake(mac):
+    if not MAC_PATTERN.fullmatch(mac):
+        raise Exception('Invalid mac address: {}'.format(mac))
+    address = bytes(int(byte, 16) for byte in re.split('[:-]', mac))
     sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    sock.setsockopt(socket.SOL_SOCKET, socket.SO_BROADCAST, 1)
+    sock.sendto(b'\xff' * 6 + address * 16, ('255.255.255.255', 9))
+
+def send_move(buttons, x, y, vertical, horizontal):
     packet = pack('BBBbbbb', 0x02, 0x01, buttons, x, y, vertical, horizontal)
-    sock.sendto(packet, (UDP_IP, UDP_PORT))
+    send_packet(packet)
 
-if __name__ == '__main__':
-    args = sys.argv[1:]
+MOUSE_BUTTONS = {
+    'left': 0x01,
+    'right': 0x02,
+    'middle': 0x04,
+    'back': 0x08,
+    'forward': 0x10,
+}
+
+def clamp(value):
+    return max(-127, min(127, value))
 
-    mouse_button1 = False
-    mouse_button2 = False
+# deltas beyond what fits in a packet are sent in several steps
+def send_move_by(buttons, x, y, vertical=0, horizontal=0):
+    while True:
+        step_x, step_y = clamp(x), clamp(y)
+        step_vertical, step_horizontal = clamp(vertical), clamp(horizontal)
+        send_move(buttons, step_x, step_y, step_vertical, step_horizontal)
+        x -= step_x
+        y -= step_y
+        vertical -= step_vertical
+        horizontal -= step_horizontal
+        if not (x or y or vertical or horizontal):
+            break
 
-    def mouse_buttons(mouse_button1, mouse_button2):
-        return (mouse_button2 << 1) | mouse_button1
+mouse_button1 = False
+mouse_button2 = False
+
+def mouse_buttons(mouse_button1, mouse_button2):
+    return (mouse_button2 << 1) | mouse_button1
+
+def run(args, speed=1.0):
+    global mouse_button1, mouse_button2
+    global UDP_IP, UDP_PORT
+    global keymap
 
     while len(args):
         arg = args.pop(0)
@@ -53,7 +471,239 @@ if __name__ == '__main__':
             delta_y = int(args.pop(0))
             print('Sending move {} {}'.format(delta_x, delta_y))
             send_move(mouse_buttons(mouse_button1, mouse_button2), delta_x, delta_y, 0, 0)
+        elif arg == 'mouse':
+            action = args.pop(0)
+            buttons = mouse_buttons(mouse_button1, mouse_button2)
+            if action == 'move':
+                delta_x = int(args.pop(0))
+                delta_y = int(args.pop(0))
+                print('Sending mouse move {} {}'.format(delta_x, delta_y))
+                send_move_by(buttons, delta_x, delta_y)
+            elif action == 'click':
+                button = args.pop(0)
+                if not button in MOUSE_BUTTONS:
+                    raise Exception('Invalid mouse button: {}'.format(button))
+                print('Sending mouse click {}'.format(button))
+                send_move(buttons | MOUSE_BUTTONS[button], 0, 0, 0, 0)
+                send_move(buttons, 0, 0, 0, 0)
+            elif action == 'scroll':
+                amount = int(args.pop(0))
+                print('Sending mouse scroll {}'.format(amount))
+                send_move_by(buttons, 0, 0, amount)
+            else:
+                raise Exception('Invalid mouse action: {}'.format(action))
+        elif arg == 'type':
+            stdin = False
+            delay = TYPE_DELAY
+            newline = 'enter'
+            while len(args) and args[0] in ('--stdin', '--delay', '--newline'):
+                option = args.pop(0)
+                if option == '--stdin':
+                    stdin = True
+                elif option == '--delay':
+                    delay = float(args.pop(0))
+                else:
+                    newline = args.pop(0)
+                    if not newline in NEWLINES:
+                        raise Exception('Invalid newline: {}'.format(newline))
+            if stdin:
+                # type each line as it arrives, the delay between
+                # characters keeps us from outrunning the device
+                for line in iter(sys.stdin.readline, ''):
+                    text = line.rstrip('\r\n')
+                    print('Typing {} characters'.format(len(text)))
+                    if line.endswith('\n'):
+                        text += NEWLINES[newline]
+                    type_text(text, delay / speed)
+            else:
+                text = args.pop(0)
+                print('Typing {} characters'.format(len(text)))
+                type_text(text, delay / speed)
+        elif arg == 'key':
+            chord = args.pop(0)
+            print('Sending {}'.format(chord))
+            send_chord(chord)
+        elif arg == 'system':
+            control = args.pop(0)
+            if not control in SYSTEM_CONTROLS:
+                raise Exception('Invalid system control: {}'.format(control))
+            print('Sending system control {}'.format(control))
+            send_system_control(SYSTEM_CONTROLS[control])
+            send_system_control(0x00)
+        elif arg == 'wake':
+            if not len(args):
+                raise Exception('Usage: wake aa:bb:cc:dd:ee:ff')
+            mac = args.pop(0)
+            send_wake(mac)
+            print('Waking {}'.format(mac))
+        elif arg == 'discover':
+            for name, ip, port in discover():
+                print('{} {}:{}'.format(name, ip, port))
+        elif arg == 'target':
+            UDP_IP, UDP_PORT = split_host(args.pop(0), UDP_PORT)
+            print('Sending to {}'.format(join_host(UDP_IP, UDP_PORT)))
+        elif arg == 'layout':
+            name = args.pop(0)
+            keymap = layout_keymap(name)
+            print('Typing for a {} layout'.format(name))
+        elif arg == 'daemon':
+            serve(SOCKET_PATH)
         elif arg == 'sleep':
-            sleep(float(args.pop(0)))
+            sleep(float(args.pop(0)) / speed)
+        elif arg == 'replay':
+            speed_factor = 1.0
+            if len(args) and args[0] == '--speed':
+                args.pop(0)
+                speed_factor = float(args.pop(0))
+            path = args.pop(0)
+            print('Replaying {}'.format(path))
+            count = replay_session(path, speed * speed_factor)
+            print('Sent {} packets'.format(count))
+        elif arg == 'play':
+            speed_factor = 1.0
+            loops = 1
+            while len(args) and args[0] in ('--speed', '--loop'):
+                option = args.pop(0)
+                if option == '--speed':
+                    speed_factor = float(args.pop(0))
+                else:
+                    loops = int(args.pop(0))
+            path = args.pop(0)
+            print('Playing {}'.format(path))
+            play_script(path, speed * speed_factor, loops)
         else:
             raise Exception('Invalid argument: {}'.format(arg))
+
+# host[:port], or [host]:port for ipv6 addresses
+def split_host(host, port):
+    match = re.fullmatch(r'\[([^\]]+)\](?::(\d+))?', host)
+    if match:
+        return match.group(1), int(match.group(2) or port)
+    # more than one colon is an ipv6 address without a port
+    if host.count(':') == 1:
+        host, port = host.split(':', 1)
+        return host, int(port)
+    return host, port
+
+def join_host(host, port):
+    return '[{}]:{}'.format(host, port) if ':' in host else '{}:{}'.format(host, port)
+
+def send_packet(packet):
+    family, _, _, _, addr = socket.getaddrinfo(
+        UDP_IP, UDP_PORT, ADDRESS_FAMILY, socket.SOCK_DGRAM)[0]
+    sock = socket.socket(family, socket.SOCK_DGRAM)
+    sock.sendto(packet, addr)
+
+# sends a session recorded with hidgui --record (or a hidgui macro), one
+# packet per line: milliseconds from the start, then the packet in hex
+def replay_session(path, speed=1.0):
+    started = time()
+    count = 0
+    with open(path) as f:
+        for line in f:
+            fields = line.split()
+            if not len(fields) or fields[0].startswith('#'):
+                continue
+            due = started + int(fields[0]) / 1000.0 / speed
+            if due > time():
+                sleep(due - time())
+            send_packet(bytes(int(byte, 16) for byte in fields[1:]))
+            count += 1
+    return count
+
+# a script has one or more commands per line, just like on the command
+# line, and # starts a comment
+def play_script(path, speed=1.0, loops=1):
+    commands = []
+    with open(path) as f:
+        for line in f:
+            args = shlex.split(line, comments=True)
+            if len(args):
+                commands.append(args)
+    # 0 loops forever
+    count = 0
+    while not loops or count < loops:
+        for args in commands:
+            run(list(args), speed)
+        count += 1
+
+# runs commands sent to the unix socket, one line at a time, so scripts
+# calling keycli often don't need to start it up every time. each line is
+# answered with ok or the error
+def serve(path):
+    if os.path.exists(path):
+        os.unlink(path)
+
+    # the socket takes keystrokes for the target, so only we may connect,
+    # from the moment it's there
+    server = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    umask = os.umask(0o177)
+    try:
+        server.bind(path)
+    finally:
+        os.umask(umask)
+    server.listen(1)
+    print('Listening on {}'.format(path))
+
+    while True:
+        conn, _ = server.accept()
+        with conn, conn.makefile('rw') as f:
+            for line in f:
+                try:
+                    run(shlex.split(line))
+                    f.write('ok\n')
+                except Exception as e:
+                    f.write('error: {}\n'.format(e))
+                f.flush()
+
+# hands the commands over to a running daemon
+def send_to_daemon(path, args):
+    client = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    client.connect(path)
+    with client, client.makefile('rw') as f:
+        f.write(' '.join(shlex.quote(arg) for arg in args) + '\n')
+        f.flush()
+        reply = f.readline().strip()
+    if reply != 'ok':
+        raise Exception('Daemon: {}'.format(reply))
+
+if __name__ == '__main__':
+    args = sys.argv[1:]
+    use_daemon = False
+    local_options = []
+
+    while len(args) and args[0] in ('--host', '--port', '--socket', '--daemon', '--target-layout',
+                                    '--ipv4', '--ipv6', '-4', '-6'):
+        option = args.pop(0)
+        if option in ('--host', '--port', '--target-layout', '--ipv4', '--ipv6', '-4', '-6'):
+            local_options.append(option)
+        if option in ('--ipv4', '-4'):
+            ADDRESS_FAMILY = socket.AF_INET
+        elif option in ('--ipv6', '-6'):
+            ADDRESS_FAMILY = socket.AF_INET6
+        elif option == '--target-layout':
+            keymap = layout_keymap(args.pop(0))
+        elif option == '--socket':
+            SOCKET_PATH = args.pop(0)
+        elif option == '--daemon':
+            use_daemon = True
+        elif option == '--host':
+            UDP_IP, UDP_PORT = split_host(args.pop(0), UDP_PORT)
+        else:
+            UDP_PORT = int(args.pop(0))
+
+    # the daemon has its own target and layout, and reads its own stdin
+    if use_daemon and local_options:
+        sys.exit('{} would be ignored by the daemon, give it when starting the '
+                 'daemon or use the target and layout commands'.format(', '.join(local_options)))
+    if use_daemon and '--stdin' in args:
+        sys.exit('type --stdin can\'t go through the daemon, it would read the '
+                 'daemon\'s stdin')
+
+    if use_daemon:
+        send_to_daemon(SOCKET_PATH, args)
+    else:
+        try:
+            run(args)
+        except Exception as e:
+            sys.exit('error: {}'.format(e))
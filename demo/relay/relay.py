@@ -0,0 +1,90 @@
+#!/usr/bin/env python3
+#
+# Forwards NetHID packets to a device on another network, e.g. when the device
+# sits on an isolated VLAN and the relay has a leg on both sides. Packets from
+# the device (led state, pongs) are sent back to the client that last sent
+# something.
+#
+#   ./relay.py [-4|-6] device[:port] [listen_port]
+#
+# IPv6 devices with a port are given as [device]:port.
+
+import re
+import sys
+import socket
+import select
+
+UDP_IP = '::'
+UDP_PORT = 4444
+
+# device[:port], or [device]:port for ipv6 addresses
+def split_host(host, port):
+    match = re.fullmatch(r'\[([^\]]+)\](?::(\d+))?', host)
+    if match:
+        return match.group(1), int(match.group(2) or port)
+    # more than one colon is an ipv6 address without a port
+    if host.count(':') == 1:
+        host, port = host.split(':', 1)
+        return host, int(port)
+    return host, port
+
+def join_host(host, port):
+    return '[{}]:{}'.format(host, port) if ':' in host else '{}:{}'.format(host, port)
+
+# clients of either kind can reach a dual stack socket, systems without ipv6
+# get an ipv4 one
+def listen(port):
+    try:
+        sock = socket.socket(socket.AF_INET6, socket.SOCK_DGRAM)
+        sock.setsockopt(socket.IPPROTO_IPV6, socket.IPV6_V6ONLY, 0)
+        sock.bind((UDP_IP, port))
+    except OSError:
+        sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        sock.bind(('0.0.0.0', port))
+    return sock
+
+if __name__ == '__main__':
+    args = sys.argv[1:]
+    family = socket.AF_UNSPEC
+
+    if len(args) and args[0] in ('-4', '-6'):
+        family = socket.AF_INET if args.pop(0) == '-4' else socket.AF_INET6
+
+    if not len(args):
+        print('Usage: {} [-4|-6] device[:port] [listen_port]'.format(sys.argv[0]))
+        sys.exit(1)
+
+    device, device_port = split_host(args.pop(0), UDP_PORT)
+    device_family, _, _, _, device_addr = socket.getaddrinfo(
+        device, device_port, family, socket.SOCK_DGRAM)[0]
+
+    listen_port = int(args.pop(0)) if len(args) else UDP_PORT
+
+    # clients talk to us here..
+    client_sock = listen(listen_port)
+
+    # ..and we talk to the device from here
+    device_sock = socket.socket(device_family, socket.SOCK_DGRAM)
+
+    print('Relaying port {} -> {}'.format(listen_port, join_host(*device_addr[:2])))
+
+    client_addr = None
+
+    while True:
+        readable, _, _ = select.select([client_sock, device_sock], [], [])
+
+        if client_sock in readable:
+            packet, addr = client_sock.recvfrom(1024)
+            if addr != client_addr:
+                print('Client {}'.format(join_host(*addr[:2])))
+                client_addr = addr
+            device_sock.sendto(packet, device_addr)
+
+        if device_sock in readable:
+            try:
+                packet, addr = device_sock.recvfrom(1024)
+            except ConnectionRefusedError:
+                continue
+            # ipv6 addresses come with flow info and scope too
+            if addr[:2] == device_addr[:2] and client_addr is not None:
+                client_sock.sendto(packet, client_addr)
@@ -0,0 +1,906 @@
+#!/usr/bin/env python3
+#
+# Pretends to be a NetHID device: listens for packets, decodes them and prints
+# what the device would do. Useful for trying out the clients without the
+# hardware.
+#
+# With --uinput the events are also injected to a virtual keyboard and mouse,
+# turning the machine into a NetHID target (needs python-evdev and write
+# access to /dev/uinput).
+#
+# --sendinput does the same on windows with SendInput. Keys are sent as scan
+# codes, so the layout selected on the windows side decides what gets typed.
+#
+# --quartz does it on macos by posting CGEvents (needs pyobjc and the
+# accessibility permission, which is asked for on the first run).
+#
+# With --pcap the packets are read from a capture file instead, including the
+# ones sent by the device, e.g. from: tcpdump -w nethid.pcap udp port 4444
+#
+#   ./simulator.py [--uinput | --sendinput | --quartz] [port]
+#   ./simulator.py --pcap file [port]
+#
+# It listens on both ipv4 and ipv6 where the system has ipv6.
+
+import sys
+import socket
+from struct import pack, unpack
+
+UDP_IP = '::'
+UDP_PORT = 4444
+
+PACKET_TYPE_KEYBOARD = 1
+PACKET_TYPE_MOUSE = 2
+PACKET_TYPE_GAMEPAD = 3
+PACKET_TYPE_KEYBOARD_REPORT = 4
+PACKET_TYPE_CONSUMER = 5
+PACKET_TYPE_SYSTEM_CONTROL = 6
+PACKET_TYPE_LEDS = 7
+PACKET_TYPE_PING = 8
+PACKET_TYPE_PONG = 9
+PACKET_TYPE_DISCOVER = 10
+PACKET_TYPE_DISCOVER_REPLY = 11
+PACKET_TYPE_ABSOLUTE_MOUSE = 12
+PACKET_TYPE_PEN = 13
+
+SYSTEM_CONTROLS = {
+    0x00: 'released',
+    0x01: 'power',
+    0x02: 'sleep',
+    0x03: 'wake',
+}
+
+# hid keyboard usage -> linux key code, same as hid_keyboard[] in the kernel
+HID_TO_LINUX = [
+      0,  0,  0,  0, 30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38,
+     50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45, 21, 44,  2,  3,
+      4,  5,  6,  7,  8,  9, 10, 11, 28,  1, 14, 15, 57, 12, 13, 26,
+     27, 43, 43, 39, 40, 41, 51, 52, 53, 58, 59, 60, 61, 62, 63, 64,
+     65, 66, 67, 68, 87, 88, 99, 70,119,110,102,104,111,107,109,106,
+    105,108,103, 69, 98, 55, 74, 78, 96, 79, 80, 81, 75, 76, 77, 71,
+     72, 73, 82, 83, 86,127,116,117,183,184,185,186,187,188,189,190,
+    191,192,193,194,134,138,130,132,128,129,131,137,133,135,136,113,
+    115,114,  0,  0,  0,121,  0, 89, 93,124, 92, 94, 95,  0,  0,  0,
+    122,123, 90, 91, 85,  0,  0,  0,  0,  0,  0,  0,111,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,179,180,  0,  0,  0,  0,  0,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+      0,  0,  0,  0,  0,  0,  0,  0,111,  0,  0,  0,  0,  0,  0,  0,
+     29, 42, 56,125, 97, 54,100,126,164,166,165,163,161,115,114,113,
+    150,158,159,128,136,177,178,176,142,152,173,140,  0,  0,  0,  0,
+]
+
+# consumer usage -> linux key code
+CONSUMER_TO_LINUX = {
+    0x00b3: 208, # KEY_FASTFORWARD
+    0x00b4: 168, # KEY_REWIND
+    0x00b5: 163, # KEY_NEXTSONG
+    0x00b6: 165, # KEY_PREVIOUSSONG
+    0x00b7: 166, # KEY_STOPCD
+    0x00b8: 161, # KEY_EJECTCD
+    0x00cd: 164, # KEY_PLAYPAUSE
+    0x00e2: 113, # KEY_MUTE
+    0x00e9: 115, # KEY_VOLUMEUP
+    0x00ea: 114, # KEY_VOLUMEDOWN
+    0x006f: 225, # KEY_BRIGHTNESSUP
+    0x0070: 224, # KEY_BRIGHTNESSDOWN
+    0x0183: 226, # KEY_MEDIA
+    0x018a: 155, # KEY_MAIL
+    0x0192: 140, # KEY_CALC
+    0x0194: 157, # KEY_COMPUTER
+    0x0196: 150, # KEY_WWW
+    0x0221: 217, # KEY_SEARCH
+    0x0223: 172, # KEY_HOMEPAGE
+    0x0224: 158, # KEY_BACK
+    0x0225: 159, # KEY_FORWARD
+    0x0226: 128, # KEY_STOP
+    0x0227: 173, # KEY_REFRESH
+    0x022a: 156, # KEY_BOOKMARKS
+}
+
+# system control -> linux key code
+SYSTEM_CONTROL_TO_LINUX = {
+    0x01: 116, # KEY_POWER
+    0x02: 142, # KEY_SLEEP
+    0x03: 143, # KEY_WAKEUP
+}
+
+class UinputTarget:
+    """Replays decoded packets as a virtual keyboard and mouse"""
+
+    def __init__(self):
+        from evdev import UInput, AbsInfo, ecodes
+
+        self.e = ecodes
+
+        keys = set(k for k in HID_TO_LINUX if k)
+        keys |= set(CONSUMER_TO_LINUX.values())
+        keys |= set(SYSTEM_CONTROL_TO_LINUX.values())
+        keys |= set([ecodes.BTN_LEFT, ecodes.BTN_RIGHT, ecodes.BTN_MIDDLE,
+                     ecodes.BTN_SIDE, ecodes.BTN_EXTRA])
+
+        self.ui = UInput({
+            ecodes.EV_KEY: sorted(keys),
+            ecodes.EV_REL: [ecodes.REL_X, ecodes.REL_Y,
+                            ecodes.REL_WHEEL, ecodes.REL_HWHEEL],
+        }, name='nethid')
+
+        # absolute positions come from a tablet of its own, a pointer with
+        # both relative and absolute axes confuses the desktop
+        self.tablet = UInput({
+            ecodes.EV_KEY: [ecodes.BTN_LEFT],
+            ecodes.EV_ABS: [(ecodes.ABS_X, AbsInfo(0, 0, 32767, 0, 0, 0)),
+                            (ecodes.ABS_Y, AbsInfo(0, 0, 32767, 0, 0, 0))],
+        }, name='nethid absolute')
+
+        self.pen_device = UInput({
+            ecodes.EV_KEY: [ecodes.BTN_TOOL_PEN, ecodes.BTN_TOOL_RUBBER,
+                            ecodes.BTN_TOUCH, ecodes.BTN_STYLUS],
+            ecodes.EV_ABS: [(ecodes.ABS_X, AbsInfo(0, 0, 32767, 0, 0, 0)),
+                            (ecodes.ABS_Y, AbsInfo(0, 0, 32767, 0, 0, 0)),
+                            (ecodes.ABS_PRESSURE, AbsInfo(0, 0, 4095, 0, 0, 0)),
+                            (ecodes.ABS_TILT_X, AbsInfo(0, -90, 90, 0, 0, 0)),
+                            (ecodes.ABS_TILT_Y, AbsInfo(0, -90, 90, 0, 0, 0))],
+        }, name='nethid pen')
+
+        self.held = set()
+        self.buttons = 0
+        self.consumer = 0
+        self.system_control = 0
+
+    def key(self, code, pressed):
+        if code:
+            self.ui.write(self.e.EV_KEY, code, 1 if pressed else 0)
+
+    def keyboard_report(self, modifiers, keys):
+        held = set(HID_TO_LINUX[0xe0 + i] for i in range(8) if modifiers & (1 << i))
+        held |= set(HID_TO_LINUX[k] for k in keys if k)
+        for code in self.held - held:
+            self.key(code, False)
+        for code in held - self.held:
+            self.key(code, True)
+        self.held = held
+        self.ui.syn()
+
+    def keyboard(self, pressed, key):
+        code = HID_TO_LINUX[key]
+        if pressed:
+            self.held.add(code)
+        else:
+            self.held.discard(code)
+        self.key(code, pressed)
+        self.ui.syn()
+
+    def mouse(self, buttons, x, y, vertical, horizontal):
+        codes = [self.e.BTN_LEFT, self.e.BTN_RIGHT, self.e.BTN_MIDDLE,
+                 self.e.BTN_SIDE, self.e.BTN_EXTRA]
+        for i, code in enumerate(codes):
+            if (buttons ^ self.buttons) & (1 << i):
+                self.key(code, buttons & (1 << i))
+        self.buttons = buttons
+        if x:
+            self.ui.write(self.e.EV_REL, self.e.REL_X, x)
+        if y:
+            self.ui.write(self.e.EV_REL, self.e.REL_Y, y)
+        if vertical:
+            self.ui.write(self.e.EV_REL, self.e.REL_WHEEL, vertical)
+        if horizontal:
+            self.ui.write(self.e.EV_REL, self.e.REL_HWHEEL, horizontal)
+        self.ui.syn()
+
+    def mouse_to(self, buttons, x, y, vertical, horizontal):
+        self.tablet.write(self.e.EV_ABS, self.e.ABS_X, x)
+        self.tablet.write(self.e.EV_ABS, self.e.ABS_Y, y)
+        self.tablet.syn()
+        self.mouse(buttons, 0, 0, vertical, horizontal)
+
+    def pen(self, flags, x, y, pressure, tilt_x, tilt_y):
+        in_range = 1 if flags & 0x08 else 0
+        eraser = flags & 0x04
+        write = self.pen_device.write
+        write(self.e.EV_KEY, self.e.BTN_TOOL_RUBBER, in_range if eraser else 0)
+        write(self.e.EV_KEY, self.e.BTN_TOOL_PEN, 0 if eraser else in_range)
+        write(self.e.EV_ABS, self.e.ABS_X, x)
+        write(self.e.EV_ABS, self.e.ABS_Y, y)
+        write(self.e.EV_ABS, self.e.ABS_PRESSURE, pressure)
+        write(self.e.EV_ABS, self.e.ABS_TILT_X, tilt_x)
+        write(self.e.EV_ABS, self.e.ABS_TILT_Y, tilt_y)
+        write(self.e.EV_KEY, self.e.BTN_TOUCH, 1 if flags & 0x01 else 0)
+        write(self.e.EV_KEY, self.e.BTN_STYLUS, 1 if flags & 0x02 else 0)
+        self.pen_device.syn()
+
+    def consumer_control(self, usage):
+        # only one consumer key can be down at a time
+        self.key(CONSUMER_TO_LINUX.get(self.consumer), False)
+        self.key(CONSUMER_TO_LINUX.get(usage), True)
+        self.consumer = usage
+        self.ui.syn()
+
+    def system(self, control):
+        self.key(SYSTEM_CONTROL_TO_LINUX.get(self.system_control), False)
+        self.key(SYSTEM_CONTROL_TO_LINUX.get(control), True)
+        self.system_control = control
+        self.ui.syn()
+
+    def inject(self, packet):
+        ptype, body = packet[0], packet[2:]
+
+        if ptype == PACKET_TYPE_KEYBOARD:
+            pressed, modifiers, key = unpack('BBB', body)
+            self.keyboard(pressed, key)
+        elif ptype == PACKET_TYPE_KEYBOARD_REPORT:
+            self.keyboard_report(body[0], body[1:])
+        elif ptype == PACKET_TYPE_MOUSE:
+            self.mouse(*unpack('Bbbbb', body))
+        elif ptype == PACKET_TYPE_ABSOLUTE_MOUSE:
+            self.mouse_to(*unpack('<BHHbb', body))
+        elif ptype == PACKET_TYPE_PEN:
+            self.pen(*unpack('<BHHHbb', body))
+        elif ptype == PACKET_TYPE_CONSUMER:
+            self.consumer_control(unpack('<H', body)[0])
+        elif ptype == PACKET_TYPE_SYSTEM_CONTROL:
+            self.system(body[0])
+
+# hid keyboard usage -> pc scan code (set 1), 0xe0xx for extended keys.
+# the virtual keys are looked up from the layout of the focused window
+HID_TO_SCANCODE = {
+    0x04: 0x1e, 0x05: 0x30, 0x06: 0x2e, 0x07: 0x20, 0x08: 0x12, 0x09: 0x21,
+    0x0a: 0x22, 0x0b: 0x23, 0x0c: 0x17, 0x0d: 0x24, 0x0e: 0x25, 0x0f: 0x26,
+    0x10: 0x32, 0x11: 0x31, 0x12: 0x18, 0x13: 0x19, 0x14: 0x10, 0x15: 0x13,
+    0x16: 0x1f, 0x17: 0x14, 0x18: 0x16, 0x19: 0x2f, 0x1a: 0x11, 0x1b: 0x2d,
+    0x1c: 0x15, 0x1d: 0x2c, 0x1e: 0x02, 0x1f: 0x03, 0x20: 0x04, 0x21: 0x05,
+    0x22: 0x06, 0x23: 0x07, 0x24: 0x08, 0x25: 0x09, 0x26: 0x0a, 0x27: 0x0b,
+    0x28: 0x1c, 0x29: 0x01, 0x2a: 0x0e, 0x2b: 0x0f, 0x2c: 0x39, 0x2d: 0x0c,
+    0x2e: 0x0d, 0x2f: 0x1a, 0x30: 0x1b, 0x31: 0x2b, 0x32: 0x2b, 0x33: 0x27,
+    0x34: 0x28, 0x35: 0x29, 0x36: 0x33, 0x37: 0x34, 0x38: 0x35, 0x39: 0x3a,
+    0x3a: 0x3b, 0x3b: 0x3c, 0x3c: 0x3d, 0x3d: 0x3e, 0x3e: 0x3f, 0x3f: 0x40,
+    0x40: 0x41, 0x41: 0x42, 0x42: 0x43, 0x43: 0x44, 0x44: 0x57, 0x45: 0x58,
+    0x46: 0xe037, 0x47: 0x46, 0x49: 0xe052, 0x4a: 0xe047, 0x4b: 0xe049,
+    0x4c: 0xe053, 0x4d: 0xe04f, 0x4e: 0xe051, 0x4f: 0xe04d, 0x50: 0xe04b,
+    0x51: 0xe050, 0x52: 0xe048, 0x53: 0xe045, 0x54: 0xe035, 0x55: 0x37,
+    0x56: 0x4a, 0x57: 0x4e, 0x58: 0xe01c, 0x59: 0x4f, 0x5a: 0x50, 0x5b: 0x51,
+    0x5c: 0x4b, 0x5d: 0x4c, 0x5e: 0x4d, 0x5f: 0x47, 0x60: 0x48, 0x61: 0x49,
+    0x62: 0x52, 0x63: 0x53, 0x64: 0x56, 0x65: 0xe05d, 0x66: 0xe05e,
+    0x67: 0x59, 0x68: 0x64, 0x69: 0x65, 0x6a: 0x66, 0x6b: 0x67, 0x6c: 0x68,
+    0x6d: 0x69, 0x6e: 0x6a, 0x6f: 0x6b, 0x70: 0x6c, 0x71: 0x6d, 0x72: 0x6e,
+    0x73: 0x76, 0x7f: 0xe020, 0x80: 0xe030, 0x81: 0xe02e, 0x87: 0x73,
+    0x88: 0x70, 0x89: 0x7d, 0x8a: 0x79, 0x8b: 0x7b,
+    0xe0: 0x1d, 0xe1: 0x2a, 0xe2: 0x38, 0xe3: 0xe05b,
+    0xe4: 0xe01d, 0xe5: 0x36, 0xe6: 0xe038, 0xe7: 0xe05c,
+}
+
+# keys without a plain scan code, sent as virtual keys
+HID_TO_VK = {
+    0x48: 0x13, # VK_PAUSE
+}
+
+# consumer usage -> windows virtual key
+CONSUMER_TO_VK = {
+    0x00b5: 0xb0, # VK_MEDIA_NEXT_TRACK
+    0x00b6: 0xb1, # VK_MEDIA_PREV_TRACK
+    0x00b7: 0xb2, # VK_MEDIA_STOP
+    0x00cd: 0xb3, # VK_MEDIA_PLAY_PAUSE
+    0x00e2: 0xad, # VK_VOLUME_MUTE
+    0x00e9: 0xaf, # VK_VOLUME_UP
+    0x00ea: 0xae, # VK_VOLUME_DOWN
+    0x0183: 0xb5, # VK_LAUNCH_MEDIA_SELECT
+    0x018a: 0xb4, # VK_LAUNCH_MAIL
+    0x0192: 0xb7, # VK_LAUNCH_APP2
+    0x0194: 0xb6, # VK_LAUNCH_APP1
+    0x0221: 0xaa, # VK_BROWSER_SEARCH
+    0x0223: 0xac, # VK_BROWSER_HOME
+    0x0224: 0xa6, # VK_BROWSER_BACK
+    0x0225: 0xa7, # VK_BROWSER_FORWARD
+    0x0226: 0xa9, # VK_BROWSER_STOP
+    0x0227: 0xa8, # VK_BROWSER_REFRESH
+    0x022a: 0xab, # VK_BROWSER_FAVORITES
+}
+
+# system control -> windows virtual key, power and wake have none
+SYSTEM_CONTROL_TO_VK = {
+    0x02: 0x5f, # VK_SLEEP
+}
+
+class SendInputTarget:
+    """Replays decoded packets on windows with SendInput"""
+
+    INPUT_MOUSE = 0
+    INPUT_KEYBOARD = 1
+
+    KEYEVENTF_EXTENDEDKEY = 0x0001
+    KEYEVENTF_KEYUP = 0x0002
+    KEYEVENTF_SCANCODE = 0x0008
+
+    MOUSEEVENTF_MOVE = 0x0001
+    MOUSEEVENTF_WHEEL = 0x0800
+    MOUSEEVENTF_HWHEEL = 0x1000
+    MOUSEEVENTF_VIRTUALDESK = 0x4000
+    MOUSEEVENTF_ABSOLUTE = 0x8000
+
+    # (down, up, mouseData) for left, right, middle, back and forward
+    BUTTONS = [(0x0002, 0x0004, 0), (0x0008, 0x0010, 0), (0x0020, 0x0040, 0),
+               (0x0080, 0x0100, 1), (0x0080, 0x0100, 2)]
+
+    MAPVK_VSC_TO_VK_EX = 3
+    WHEEL_DELTA = 120
+
+    def __init__(self):
+        import ctypes
+        from ctypes import wintypes
+
+        class MOUSEINPUT(ctypes.Structure):
+            _fields_ = [('dx', wintypes.LONG), ('dy', wintypes.LONG),
+                        ('mouseData', wintypes.DWORD),
+                        ('dwFlags', wintypes.DWORD), ('time', wintypes.DWORD),
+                        ('dwExtraInfo', ctypes.c_void_p)]
+
+        class KEYBDINPUT(ctypes.Structure):
+            _fields_ = [('wVk', wintypes.WORD), ('wScan', wintypes.WORD),
+                        ('dwFlags', wintypes.DWORD), ('time', wintypes.DWORD),
+                        ('dwExtraInfo', ctypes.c_void_p)]
+
+        # HARDWAREINPUT is never sent, but it's part of the union's size
+        class HARDWAREINPUT(ctypes.Structure):
+            _fields_ = [('uMsg', wintypes.DWORD), ('wParamL', wintypes.WORD),
+                        ('wParamH', wintypes.WORD)]
+
+        class UNION(ctypes.Union):
+            _fields_ = [('mi', MOUSEINPUT), ('ki', KEYBDINPUT),
+                        ('hi', HARDWAREINPUT)]
+
+        class INPUT(ctypes.Structure):
+            _fields_ = [('type', wintypes.DWORD), ('u', UNION)]
+
+        self.ctypes = ctypes
+        self.INPUT = INPUT
+        self.user32 = ctypes.WinDLL('user32', use_last_error=True)
+        self.user32.GetKeyboardLayout.restype = ctypes.c_void_p
+        self.user32.MapVirtualKeyExW.argtypes = [wintypes.UINT, wintypes.UINT,
+                                                 ctypes.c_void_p]
+
+        self.held = set()
+        self.buttons = 0
+        self.consumer = 0
+        self.system_control = 0
+
+    def send(self, inputs):
+        if not inputs:
+            return
+        array = (self.INPUT * len(inputs))(*inputs)
+        if not self.user32.SendInput(len(inputs), array,
+                                     self.ctypes.sizeof(self.INPUT)):
+            # fails when a higher integrity window (uac, admin apps) has focus
+            print('SendInput failed: {}'.format(
+                self.ctypes.WinError(self.ctypes.get_last_error())))
+
+    def layout(self):
+        # the layout of the focused window, it's the one doing the typing
+        window = self.user32.GetForegroundWindow()
+        thread = self.user32.GetWindowThreadProcessId(window, None)
+        return self.user32.GetKeyboardLayout(thread)
+
+    def vk_input(self, vk, pressed):
+        event = self.INPUT(type=self.INPUT_KEYBOARD)
+        event.u.ki.wVk = vk
+        event.u.ki.dwFlags = 0 if pressed else self.KEYEVENTF_KEYUP
+        return event
+
+    def key_input(self, key, pressed):
+        if key in HID_TO_VK:
+            return self.vk_input(HID_TO_VK[key], pressed)
+        scancode = HID_TO_SCANCODE.get(key)
+        if not scancode:
+            return None
+        event = self.INPUT(type=self.INPUT_KEYBOARD)
+        # pass the virtual key along with the scan code, apps reading either
+        # one see the same key
+        event.u.ki.wVk = self.user32.MapVirtualKeyExW(
+            scancode, self.MAPVK_VSC_TO_VK_EX, self.layout())
+        event.u.ki.wScan = scancode & 0xff
+        flags = 0 if pressed else self.KEYEVENTF_KEYUP
+        if scancode & 0xff00:
+            flags |= self.KEYEVENTF_EXTENDEDKEY
+        if not event.u.ki.wVk:
+            flags |= self.KEYEVENTF_SCANCODE
+        event.u.ki.dwFlags = flags
+        return event
+
+    def mouse_input(self, flags, x=0, y=0, data=0):
+        event = self.INPUT(type=self.INPUT_MOUSE)
+        event.u.mi.dx = x
+        event.u.mi.dy = y
+        event.u.mi.mouseData = data & 0xffffffff
+        event.u.mi.dwFlags = flags
+        return event
+
+    def keyboard_report(self, modifiers, keys):
+        held = set(0xe0 + i for i in range(8) if modifiers & (1 << i))
+        held |= set(k for k in keys if k)
+        # modifiers go down before the keys and come up after them
+        inputs = [self.key_input(k, False)
+                  for k in sorted(self.held - held, key=lambda k: k >= 0xe0)]
+        inputs += [self.key_input(k, True)
+                   for k in sorted(held - self.held, key=lambda k: k < 0xe0)]
+        self.held = held
+        self.send([i for i in inputs if i])
+
+    def keyboard(self, pressed, key):
+        if pressed:
+            self.held.add(key)
+        else:
+            self.held.discard(key)
+        self.send([i for i in [self.key_input(key, pressed)] if i])
+
+    def mouse(self, buttons, x, y, vertical, horizontal, flags=0):
+        inputs = []
+        if x or y or flags:
+            inputs.append(self.mouse_input(self.MOUSEEVENTF_MOVE | flags, x, y))
+        for i, (down, up, data) in enumerate(self.BUTTONS):
+            if (buttons ^ self.buttons) & (1 << i):
+                inputs.append(self.mouse_input(
+                    down if buttons & (1 << i) else up, data=data))
+        self.buttons = buttons
+        if vertical:
+            inputs.append(self.mouse_input(self.MOUSEEVENTF_WHEEL,
+                                           data=vertical * self.WHEEL_DELTA))
+        if horizontal:
+            inputs.append(self.mouse_input(self.MOUSEEVENTF_HWHEEL,
+                                           data=horizontal * self.WHEEL_DELTA))
+        self.send(inputs)
+
+    def mouse_to(self, buttons, x, y, vertical, horizontal):
+        # absolute coordinates are 0-65535 across all monitors
+        self.mouse(buttons, x * 65535 // 32767, y * 65535 // 32767,
+                   vertical, horizontal,
+                   self.MOUSEEVENTF_ABSOLUTE | self.MOUSEEVENTF_VIRTUALDESK)
+
+    def pen(self, flags, x, y, pressure, tilt_x, tilt_y):
+        # without a pen driver the pen moves the pointer, tip is the left
+        # button and barrel the right one
+        buttons = (1 if flags & 0x01 else 0) | (2 if flags & 0x02 else 0)
+        if flags & 0x08:
+            self.mouse_to(buttons, x, y, 0, 0)
+        else:
+            self.mouse(0, 0, 0, 0, 0)
+
+    def consumer_control(self, usage):
+        # only one consumer key can be down at a time
+        inputs = []
+        if self.consumer in CONSUMER_TO_VK:
+            inputs.append(self.vk_input(CONSUMER_TO_VK[self.consumer], False))
+        if usage in CONSUMER_TO_VK:
+            inputs.append(self.vk_input(CONSUMER_TO_VK[usage], True))
+        self.consumer = usage
+        self.send(inputs)
+
+    def system(self, control):
+        inputs = []
+        if self.system_control in SYSTEM_CONTROL_TO_VK:
+            inputs.append(self.vk_input(
+                SYSTEM_CONTROL_TO_VK[self.system_control], False))
+        if control in SYSTEM_CONTROL_TO_VK:
+            inputs.append(self.vk_input(SYSTEM_CONTROL_TO_VK[control], True))
+        self.system_control = control
+        self.send(inputs)
+
+    inject = UinputTarget.inject
+
+
+# hid keyboard usage -> macos virtual key code (kVK_*), these follow the key
+# positions like hid usages do. print screen, scroll lock and pause become
+# f13-f15 like on apple keyboards
+HID_TO_MAC = {
+    0x04: 0x00, 0x05: 0x0b, 0x06: 0x08, 0x07: 0x02, 0x08: 0x0e, 0x09: 0x03,
+    0x0a: 0x05, 0x0b: 0x04, 0x0c: 0x22, 0x0d: 0x26, 0x0e: 0x28, 0x0f: 0x25,
+    0x10: 0x2e, 0x11: 0x2d, 0x12: 0x1f, 0x13: 0x23, 0x14: 0x0c, 0x15: 0x0f,
+    0x16: 0x01, 0x17: 0x11, 0x18: 0x20, 0x19: 0x09, 0x1a: 0x0d, 0x1b: 0x07,
+    0x1c: 0x10, 0x1d: 0x06, 0x1e: 0x12, 0x1f: 0x13, 0x20: 0x14, 0x21: 0x15,
+    0x22: 0x17, 0x23: 0x16, 0x24: 0x1a, 0x25: 0x1c, 0x26: 0x19, 0x27: 0x1d,
+    0x28: 0x24, 0x29: 0x35, 0x2a: 0x33, 0x2b: 0x30, 0x2c: 0x31, 0x2d: 0x1b,
+    0x2e: 0x18, 0x2f: 0x21, 0x30: 0x1e, 0x31: 0x2a, 0x32: 0x2a, 0x33: 0x29,
+    0x34: 0x27, 0x35: 0x32, 0x36: 0x2b, 0x37: 0x2f, 0x38: 0x2c, 0x39: 0x39,
+    0x3a: 0x7a, 0x3b: 0x78, 0x3c: 0x63, 0x3d: 0x76, 0x3e: 0x60, 0x3f: 0x61,
+    0x40: 0x62, 0x41: 0x64, 0x42: 0x65, 0x43: 0x6d, 0x44: 0x67, 0x45: 0x6f,
+    0x46: 0x69, 0x47: 0x6b, 0x48: 0x71, 0x49: 0x72, 0x4a: 0x73, 0x4b: 0x74,
+    0x4c: 0x75, 0x4d: 0x77, 0x4e: 0x79, 0x4f: 0x7c, 0x50: 0x7b, 0x51: 0x7d,
+    0x52: 0x7e, 0x53: 0x47, 0x54: 0x4b, 0x55: 0x43, 0x56: 0x4e, 0x57: 0x45,
+    0x58: 0x4c, 0x59: 0x53, 0x5a: 0x54, 0x5b: 0x55, 0x5c: 0x56, 0x5d: 0x57,
+    0x5e: 0x58, 0x5f: 0x59, 0x60: 0x5b, 0x61: 0x5c, 0x62: 0x52, 0x63: 0x41,
+    0x64: 0x0a, 0x65: 0x6e, 0x67: 0x51, 0x68: 0x69, 0x69: 0x6b, 0x6a: 0x71,
+    0x6b: 0x6a, 0x6c: 0x40, 0x6d: 0x4f, 0x6e: 0x50, 0x6f: 0x5a, 0x7f: 0x4a,
+    0x80: 0x48, 0x81: 0x49, 0x85: 0x5f, 0x87: 0x5e, 0x89: 0x5d, 0x90: 0x68,
+    0x91: 0x66,
+    0xe0: 0x3b, 0xe1: 0x38, 0xe2: 0x3a, 0xe3: 0x37,
+    0xe4: 0x3e, 0xe5: 0x3c, 0xe6: 0x3d, 0xe7: 0x36,
+}
+
+# modifier bit -> CGEventFlags, left and right share a flag
+MAC_MODIFIER_FLAGS = [
+    0x040000, 0x020000, 0x080000, 0x100000, # control, shift, option, command
+    0x040000, 0x020000, 0x080000, 0x100000,
+]
+
+# consumer usage -> NX_KEYTYPE_*, sent as system defined media key events
+CONSUMER_TO_MAC = {
+    0x00b3: 19, # NX_KEYTYPE_FAST
+    0x00b4: 20, # NX_KEYTYPE_REWIND
+    0x00b5: 17, # NX_KEYTYPE_NEXT
+    0x00b6: 18, # NX_KEYTYPE_PREVIOUS
+    0x00b8: 14, # NX_KEYTYPE_EJECT
+    0x00cd: 16, # NX_KEYTYPE_PLAY
+    0x00e2: 7,  # NX_KEYTYPE_MUTE
+    0x00e9: 0,  # NX_KEYTYPE_SOUND_UP
+    0x00ea: 1,  # NX_KEYTYPE_SOUND_DOWN
+    0x006f: 2,  # NX_KEYTYPE_BRIGHTNESS_UP
+    0x0070: 3,  # NX_KEYTYPE_BRIGHTNESS_DOWN
+}
+
+# system control -> NX_KEYTYPE_*, sleep and wake have none
+SYSTEM_CONTROL_TO_MAC = {
+    0x01: 6, # NX_POWER_KEY
+}
+
+class QuartzTarget:
+    """Replays decoded packets on macos by posting CGEvents"""
+
+    NSSystemDefined = 14
+    NX_SUBTYPE_AUX_CONTROL_BUTTONS = 8
+
+    def __init__(self):
+        import Quartz
+        from AppKit import NSEvent
+        from ApplicationServices import (AXIsProcessTrustedWithOptions,
+                                         kAXTrustedCheckOptionPrompt)
+
+        # posting events needs the accessibility permission. asking for it
+        # opens the prompt, but it only applies to processes started after
+        # it has been granted
+        if not AXIsProcessTrustedWithOptions({kAXTrustedCheckOptionPrompt: True}):
+            raise Exception('Allow the terminal running the simulator in '
+                            'System Settings > Privacy & Security > '
+                            'Accessibility, then start it again')
+
+        self.q = Quartz
+        self.NSEvent = NSEvent
+
+        self.held = set()
+        self.modifiers = 0
+        self.buttons = 0
+        self.consumer = 0
+        self.system_control = 0
+
+    def flags(self):
+        flags = 0
+        for i, flag in enumerate(MAC_MODIFIER_FLAGS):
+            if self.modifiers & (1 << i):
+                flags |= flag
+        return flags
+
+    def key(self, key, pressed):
+        code = HID_TO_MAC.get(key)
+        if code is None:
+            return
+        if key >= 0xe0:
+            if pressed:
+                self.modifiers |= 1 << (key - 0xe0)
+            else:
+                self.modifiers &= ~(1 << (key - 0xe0))
+        event = self.q.CGEventCreateKeyboardEvent(None, code, bool(pressed))
+        # apps look at the flags, not at which modifier keys are down
+        self.q.CGEventSetFlags(event, self.flags())
+        self.q.CGEventPost(self.q.kCGHIDEventTap, event)
+
+    def keyboard_report(self, modifiers, keys):
+        held = set(0xe0 + i for i in range(8) if modifiers & (1 << i))
+        held |= set(k for k in keys if k)
+        # modifiers go down before the keys and come up after them
+        for key in sorted(self.held - held, key=lambda k: k >= 0xe0):
+            self.key(key, False)
+        for key in sorted(held - self.held, key=lambda k: k < 0xe0):
+            self.key(key, True)
+        self.held = held
+
+    def keyboard(self, pressed, key):
+        if pressed:
+            self.held.add(key)
+        else:
+            self.held.discard(key)
+        self.key(key, pressed)
+
+    def location(self):
+        return self.q.CGEventGetLocation(self.q.CGEventCreate(None))
+
+    def move(self, position, dx=0, dy=0):
+        q = self.q
+        # with a button down the move has to be a drag, or apps miss it
+        if self.buttons & 0x01:
+            kind, button = q.kCGEventLeftMouseDragged, q.kCGMouseButtonLeft
+        elif self.buttons & 0x02:
+            kind, button = q.kCGEventRightMouseDragged, q.kCGMouseButtonRight
+        elif self.buttons:
+            kind, button = q.kCGEventOtherMouseDragged, q.kCGMouseButtonCenter
+        else:
+            kind, button = q.kCGEventMouseMoved, q.kCGMouseButtonLeft
+        event = q.CGEventCreateMouseEvent(None, kind, position, button)
+        # games read the deltas instead of the position
+        q.CGEventSetIntegerValueField(event, q.kCGMouseEventDeltaX, dx)
+        q.CGEventSetIntegerValueField(event, q.kCGMouseEventDeltaY, dy)
+        q.CGEventSetFlags(event, self.flags())
+        q.CGEventPost(q.kCGHIDEventTap, event)
+
+    def set_buttons(self, buttons, position):
+        q = self.q
+        kinds = [(q.kCGEventLeftMouseDown, q.kCGEventLeftMouseUp),
+                 (q.kCGEventRightMouseDown, q.kCGEventRightMouseUp)]
+        for i in range(5):
+            if not (buttons ^ self.buttons) & (1 << i):
+                continue
+            down, up = kinds[i] if i < 2 else (q.kCGEventOtherMouseDown,
+                                                q.kCGEventOtherMouseUp)
+            event = q.CGEventCreateMouseEvent(
+                None, down if buttons & (1 << i) else up, position, i)
+            q.CGEventSetIntegerValueField(event, q.kCGMouseEventButtonNumber, i)
+            q.CGEventSetFlags(event, self.flags())
+            q.CGEventPost(q.kCGHIDEventTap, event)
+        self.buttons = buttons
+
+    def scroll(self, vertical, horizontal):
+        if vertical or horizontal:
+            # the second wheel scrolls left with positive values
+            event = self.q.CGEventCreateScrollWheelEvent(
+                None, self.q.kCGScrollEventUnitLine, 2, vertical, -horizontal)
+            self.q.CGEventPost(self.q.kCGHIDEventTap, event)
+
+    def mouse(self, buttons, x, y, vertical, horizontal):
+        position = self.location()
+        if x or y:
+            # there's no relative motion to post, move from where the
+            # pointer is and keep it on the screens
+            bounds = self.q.CGDisplayBounds(self.q.CGMainDisplayID())
+            position = self.q.CGPointMake(position.x + x, position.y + y)
+            displays = self.q.CGGetDisplaysWithPoint(position, 1, None, None)[2]
+            if not displays:
+                position = self.q.CGPointMake(
+                    min(max(position.x, bounds.origin.x),
+                        bounds.origin.x + bounds.size.width - 1),
+                    min(max(position.y, bounds.origin.y),
+                        bounds.origin.y + bounds.size.height - 1))
+            self.move(position, x, y)
+        self.set_buttons(buttons, position)
+        self.scroll(vertical, horizontal)
+
+    def point(self, x, y):
+        # absolute positions cover the main display
+        bounds = self.q.CGDisplayBounds(self.q.CGMainDisplayID())
+        return self.q.CGPointMake(
+            bounds.origin.x + x * (bounds.size.width - 1) / 32767,
+            bounds.origin.y + y * (bounds.size.height - 1) / 32767)
+
+    def mouse_to(self, buttons, x, y, vertical, horizontal):
+        position = self.point(x, y)
+        self.move(position)
+        self.set_buttons(buttons, position)
+        self.scroll(vertical, horizontal)
+
+    def pen(self, flags, x, y, pressure, tilt_x, tilt_y):
+        # without a tablet driver the pen drives the pointer, tip is the left
+        # button and barrel the right one
+        if not flags & 0x08:
+            self.set_buttons(0, self.location())
+            return
+        buttons = (1 if flags & 0x01 else 0) | (2 if flags & 0x02 else 0)
+        position = self.point(x, y)
+        self.move(position)
+        self.set_buttons(buttons, position)
+
+    def media_key(self, key, pressed):
+        # media keys aren't keyboard events but NSSystemDefined ones, with
+        # the key and its state packed in data1
+        event = self.NSEvent.otherEventWithType_location_modifierFlags_timestamp_windowNumber_context_subtype_data1_data2_(
+            self.NSSystemDefined, (0, 0), 0xa00 if pressed else 0xb00, 0, 0,
+            None, self.NX_SUBTYPE_AUX_CONTROL_BUTTONS,
+            (key << 16) | ((0x0a if pressed else 0x0b) << 8), -1)
+        self.q.CGEventPost(self.q.kCGHIDEventTap, event.CGEvent())
+
+    def consumer_control(self, usage):
+        # only one consumer key can be down at a time
+        if self.consumer in CONSUMER_TO_MAC:
+            self.media_key(CONSUMER_TO_MAC[self.consumer], False)
+        if usage in CONSUMER_TO_MAC:
+            self.media_key(CONSUMER_TO_MAC[usage], True)
+        self.consumer = usage
+
+    def system(self, control):
+        if self.system_control in SYSTEM_CONTROL_TO_MAC:
+            self.media_key(SYSTEM_CONTROL_TO_MAC[self.system_control], False)
+        if control in SYSTEM_CONTROL_TO_MAC:
+            self.media_key(SYSTEM_CONTROL_TO_MAC[control], True)
+        self.system_control = control
+
+    inject = UinputTarget.inject
+
+
+def decode(packet):
+    ptype, version = packet[0], packet[1]
+    body = packet[2:]
+
+    if ptype == PACKET_TYPE_KEYBOARD and len(body) == 3:
+        pressed, modifiers, key = unpack('BBB', body)
+        return 'keyboard {} {:02x}'.format('down' if pressed else 'up', key)
+    elif ptype == PACKET_TYPE_KEYBOARD_REPORT and len(body) == 7:
+        modifiers = body[0]
+        keys = ' '.join('{:02x}'.format(k) for k in body[1:] if k)
+        return 'keyboard modifiers {:08b} keys [{}]'.format(modifiers, keys)
+    elif ptype == PACKET_TYPE_MOUSE and len(body) == 5:
+        buttons, x, y, vertical, horizontal = unpack('Bbbbb', body)
+        return 'mouse buttons {:05b} x {} y {} wheel {} {}'.format(
+            buttons, x, y, vertical, horizontal)
+    elif ptype == PACKET_TYPE_ABSOLUTE_MOUSE and len(body) == 7:
+        buttons, x, y, vertical, horizontal = unpack('<BHHbb', body)
+        return 'mouse buttons {:05b} to {} {} wheel {} {}'.format(
+            buttons, x, y, vertical, horizontal)
+    elif ptype == PACKET_TYPE_PEN and len(body) == 9:
+        flags, x, y, pressure, tilt_x, tilt_y = unpack('<BHHHbb', body)
+        return 'pen {:04b} at {} {} pressure {} tilt {} {}'.format(
+            flags, x, y, pressure, tilt_x, tilt_y)
+    elif ptype == PACKET_TYPE_GAMEPAD and len(body) == 11:
+        x, y, z, rz, rx, ry, hat, buttons = unpack('<bbbbbbBI', body)
+        return 'gamepad axes {} {} {} {} {} {} hat {} buttons {:032b}'.format(
+            x, y, z, rz, rx, ry, hat, buttons)
+    elif ptype == PACKET_TYPE_CONSUMER and len(body) == 2:
+        usage, = unpack('<H', body)
+        return 'consumer {:04x}'.format(usage)
+    elif ptype == PACKET_TYPE_SYSTEM_CONTROL and len(body) == 1:
+        return 'system control {}'.format(SYSTEM_CONTROLS.get(body[0], body[0]))
+    elif ptype == PACKET_TYPE_LEDS and len(body) == 1:
+        return 'leds {:03b}'.format(body[0])
+    elif ptype == PACKET_TYPE_PING and len(body) == 4:
+        return 'ping {}'.format(unpack('<I', body)[0])
+    elif ptype == PACKET_TYPE_PONG and len(body) == 4:
+        return 'pong {}'.format(unpack('<I', body)[0])
+    elif ptype == PACKET_TYPE_DISCOVER and len(body) == 0:
+        return 'discover'
+    elif ptype == PACKET_TYPE_DISCOVER_REPLY:
+        return 'discover reply {}'.format(body.decode(errors='replace'))
+    else:
+        return None
+
+def read_pcap(filename, port):
+    """Yields (timestamp, source, destination, payload) of udp packets to or
+    from the given port"""
+    with open(filename, 'rb') as f:
+        header = f.read(24)
+        magic = header[:4]
+        if magic in (b'\xd4\xc3\xb2\xa1', b'\x4d\x3c\xb2\xa1'):
+            endian = '<'
+        elif magic in (b'\xa1\xb2\xc3\xd4', b'\xa1\xb2\x3c\x4d'):
+            endian = '>'
+        else:
+            raise Exception('Not a pcap file: {}'.format(filename))
+        nanoseconds = magic in (b'\x4d\x3c\xb2\xa1', b'\xa1\xb2\x3c\x4d')
+        linktype, = unpack(endian + 'I', header[20:24])
+
+        while True:
+            record = f.read(16)
+            if len(record) < 16:
+                break
+            seconds, fraction, length, _ = unpack(endian + 'IIII', record)
+            frame = f.read(length)
+            timestamp = seconds + fraction / (1e9 if nanoseconds else 1e6)
+
+            # skip to the ip header
+            if linktype == 1: # ethernet
+                if unpack('>H', frame[12:14])[0] not in (0x0800, 0x86dd):
+                    continue
+                ip = frame[14:]
+            elif linktype == 113: # linux cooked capture
+                if unpack('>H', frame[14:16])[0] not in (0x0800, 0x86dd):
+                    continue
+                ip = frame[16:]
+            elif linktype == 101: # raw ip
+                ip = frame
+            else:
+                raise Exception('Unsupported link type: {}'.format(linktype))
+
+            if len(ip) >= 20 and ip[0] >> 4 == 4 and ip[9] == 17:
+                udp = ip[(ip[0] & 0x0f) * 4:]
+                source = socket.inet_ntoa(ip[12:16])
+                destination = socket.inet_ntoa(ip[16:20])
+            # ipv6 without extension headers, udp straight after
+            elif len(ip) >= 40 and ip[0] >> 4 == 6 and ip[6] == 17:
+                udp = ip[40:]
+                source = '[{}]'.format(socket.inet_ntop(socket.AF_INET6, ip[8:24]))
+                destination = '[{}]'.format(socket.inet_ntop(socket.AF_INET6, ip[24:40]))
+            else:
+                continue
+            sport, dport, udp_length = unpack('>HHH', udp[:6])
+            if port not in (sport, dport):
+                continue
+
+            yield (timestamp,
+                   '{}:{}'.format(source, sport),
+                   '{}:{}'.format(destination, dport),
+                   udp[8:udp_length])
+
+def dump_pcap(filename, port):
+    start = None
+    for timestamp, source, destination, packet in read_pcap(filename, port):
+        if start is None:
+            start = timestamp
+        if len(packet) < 2:
+            event = 'packet too short'
+        elif packet[1] != 1:
+            event = 'unknown packet version {}'.format(packet[1])
+        else:
+            event = decode(packet) or 'unknown packet type {}'.format(packet[0])
+        print('{:10.6f} {} -> {}: {} [{}]'.format(
+            timestamp - start, source, destination, event, packet.hex(' ')))
+
+if __name__ == '__main__':
+    args = sys.argv[1:]
+
+    target = None
+    if len(args) and args[0] == '--uinput':
+        args.pop(0)
+        target = UinputTarget()
+    elif len(args) and args[0] == '--sendinput':
+        args.pop(0)
+        target = SendInputTarget()
+    elif len(args) and args[0] == '--quartz':
+        args.pop(0)
+        target = QuartzTarget()
+
+    pcap = None
+    if len(args) and args[0] == '--pcap':
+        args.pop(0)
+        pcap = args.pop(0)
+
+    port = int(args.pop(0)) if len(args) else UDP_PORT
+
+    if pcap:
+        dump_pcap(pcap, port)
+        sys.exit(0)
+
+    # dual stack, or ipv4 only on systems without ipv6
+    try:
+        sock = socket.socket(socket.AF_INET6, socket.SOCK_DGRAM)
+        sock.setsockopt(socket.IPPROTO_IPV6, socket.IPV6_V6ONLY, 0)
+        sock.bind((UDP_IP, port))
+        print('Listening on [{}]:{}'.format(UDP_IP, port))
+    except OSError:
+        sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+        sock.bind(('0.0.0.0', port))
+        print('Listening on 0.0.0.0:{}'.format(port))
+
+    while True:
+        packet, addr = sock.recvfrom(1024)
+        # ipv4 clients show up as ::ffff:a.b.c.d on the dual stack socket
+        client = addr[0][7:] if addr[0].startswith('::ffff:') else addr[0]
+
+        if len(packet) < 2:
+            print('{}: packet too short'.format(client))
+            continue
+
+        if packet[1] != 1:
+            print('{}: unknown packet version {}'.format(client, packet[1]))
+            continue
+
+        event = decode(packet)
+        if event is None:
+            print('{}: unknown packet type {} ({} bytes)'.format(
+                client, packet[0], len(packet)))
+            continue
+
+        if packet[0] == PACKET_TYPE_PING:
+            sock.sendto(pack('BB', PACKET_TYPE_PONG, 1) + packet[2:], addr)
+            # keepalives arrive every second, don't flood the output
+            continue
+
+        if packet[0] == PACKET_TYPE_DISCOVER:
+            sock.sendto(pack('BB', PACKET_TYPE_DISCOVER_REPLY, 1) +
+                        socket.gethostname().encode()[:15], addr)
+
+        print('{}: {}'.format(client, event))
+
+        if target:
+            target.inject(packet)
@@ -42,6 +42,9 @@ bool usb_mounted = false;
 bool usb_suspended = false;
 bool capslock_on = false;
 
+// led state as reported by the host, sent back to the client
+uint8_t keyboard_leds = 0;
+
 void update_blink_state(void)
 {
     static uint16_t prev_blink_state = 0;
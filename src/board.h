@@ -58,6 +58,7 @@ extern bool wifi_up;
 extern bool usb_mounted;
 extern bool usb_suspended;
 extern bool capslock_on;
+extern uint8_t keyboard_leds;
 
 void update_blink_state(void);
 
@@ -28,4 +28,7 @@
 
 #define VIRTUAL_BLINKY 1
 
+// udp port to listen for packets on
+#define UDP_PORT 4444
+
 #endif
\ No newline at end of file
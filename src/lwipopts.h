@@ -41,6 +41,8 @@
 #define LWIP_CHKSUM_ALGORITHM       3
 #define LWIP_DHCP                   1
 #define LWIP_IPV4                   1
+// clients can reach us over the link local or autoconfigured address too
+#define LWIP_IPV6                   1
 #define LWIP_TCP                    1
 #define LWIP_UDP                    1
 #define LWIP_DNS                    1
@@ -49,6 +51,19 @@
 #define DHCP_DOES_ARP_CHECK         0
 #define LWIP_DHCP_DOES_ACD_CHECK    0
 
+// advertise the device as _nethid._udp over mdns, set to 0 to leave it out
+#ifndef LWIP_MDNS_RESPONDER
+#define LWIP_MDNS_RESPONDER         1
+#endif
+#if LWIP_MDNS_RESPONDER
+#define LWIP_IGMP                   1
+#define LWIP_NUM_NETIF_CLIENT_DATA  1
+#define LWIP_NETIF_EXT_STATUS_CALLBACK 1
+#define MDNS_MAX_SERVICES           1
+// the responder has a pcb of its own next to ours, dhcp's and dns's
+#define MEMP_NUM_UDP_PCB            5
+#endif
+
 #ifndef NDEBUG
 #define LWIP_DEBUG                  1
 #define LWIP_STATS                  1
@@ -93,5 +108,10 @@
 #define LWIP_ALTCP               1
 #define LWIP_ALTCP_TLS           1
 #define LWIP_ALTCP_TLS_MBEDTLS   1
+#if LWIP_MDNS_RESPONDER
+// probing and delayed answers each run on a timeout
+#define MEMP_NUM_SYS_TIMEOUT     LWIP_NUM_SYS_TIMEOUT_INTERNAL + 1 + 5
+#else
 #define MEMP_NUM_SYS_TIMEOUT     LWIP_NUM_SYS_TIMEOUT_INTERNAL + 1
 #endif
+#endif
@@ -26,6 +26,12 @@
 #include <pico/stdlib.h>
 #include <pico/stdio.h>
 #include <pico/cyw43_arch.h>
+#include <string.h>
+
+#if LWIP_MDNS_RESPONDER
+#include <lwip/init.h>
+#include <lwip/apps/mdns.h>
+#endif
 
 #include "bsp/board.h"
 #include "tusb.h"
@@ -46,8 +52,28 @@
 bool key_toggle = false;
 struct udp_pcb *pcb;
 
+// netif keeps a pointer to this, so it can't live on the stack
+char hostname[16];
+
+// where keyboard led changes are reported, the latest client to send us
+// something
+bool client_known = false;
+ip_addr_t client_addr;
+u16_t client_port;
+
 #define PACKET_TYPE_KEYBOARD 1
 #define PACKET_TYPE_MOUSE 2
+#define PACKET_TYPE_GAMEPAD 3
+#define PACKET_TYPE_KEYBOARD_REPORT 4
+#define PACKET_TYPE_CONSUMER 5
+#define PACKET_TYPE_SYSTEM_CONTROL 6
+#define PACKET_TYPE_LEDS 7
+#define PACKET_TYPE_PING 8
+#define PACKET_TYPE_PONG 9
+#define PACKET_TYPE_DISCOVER 10
+#define PACKET_TYPE_DISCOVER_REPLY 11
+#define PACKET_TYPE_ABSOLUTE_MOUSE 12
+#define PACKET_TYPE_PEN 13
 
 // header determines the second part of the packet
 typedef struct {
@@ -62,18 +88,76 @@ typedef struct {
     uint8_t key;
 } packet_keyboard;
 
+// ..or full keyboard state
+typedef struct {
+    uint8_t modifiers;
+    uint8_t keys[6];
+} packet_keyboard_report;
+
 // ..or mouse
 typedef struct {
-    uint8_t buttons;
+    uint8_t buttons; // 0x01 left, 0x02 right, 0x04 middle, 0x08 back, 0x10 forward
     int8_t x;
     int8_t y;
     int8_t vertical;
     int8_t horizontal;
 } packet_mouse;
 
+// ..or mouse moved to a position, 0 - 32767 across the whole desktop
+typedef struct {
+    uint8_t buttons;
+    uint8_t x[2]; // little endian
+    uint8_t y[2];
+    int8_t vertical;
+    int8_t horizontal;
+} packet_absolute_mouse;
+
+// ..or pen, for drawing tablets
+typedef struct {
+    uint8_t flags; // 0x01 tip, 0x02 barrel button, 0x04 eraser, 0x08 in range
+    uint8_t x[2]; // little endian, 0 - 32767 across the whole desktop
+    uint8_t y[2];
+    uint8_t pressure[2]; // little endian, 0 - 4095
+    int8_t tilt_x; // degrees, -90 - 90
+    int8_t tilt_y;
+} packet_pen;
+
+// ..or consumer control (media keys)
+typedef struct {
+    uint8_t usage[2]; // little endian, 0 == released
+} packet_consumer;
+
+// ..or system control (1 == power down, 2 == sleep, 3 == wake up)
+typedef struct {
+    uint8_t control; // 0 == released
+} packet_system_control;
+
+// ..or keyboard leds, sent from us to the client
+typedef struct {
+    uint8_t leds;
+} packet_leds;
+
+// ..or ping, which is answered with an identical pong
+typedef struct {
+    uint8_t id[4];
+} packet_ping;
+
+// ..or gamepad
+typedef struct {
+    int8_t x;
+    int8_t y;
+    int8_t z;
+    int8_t rz;
+    int8_t rx;
+    int8_t ry;
+    uint8_t hat;
+    uint8_t buttons[4]; // little endian, kept as bytes to avoid unaligned access
+} packet_gamepad;
+
 void led_blinking_task(void);
 void hid_task(void);
 void wifi_task(void);
+void leds_task(void);
 int setup_wifi(uint32_t country, const char *ssid, const char *pass, uint32_t auth);
 
 int main()
@@ -115,6 +199,9 @@ int main()
         // send usb hid report if needed, and stuff
         hid_task();
 
+        // report keyboard led changes back to the client
+        leds_task();
+
         // blink led or do other periodic status display
         led_blinking_task();
 
@@ -134,7 +221,6 @@ int setup_wifi(uint32_t country, const char *ssid, const char *pass, uint32_t au
     printf("cyw43_arch_enable_sta_mode()\r\n");
     cyw43_arch_enable_sta_mode();
 
-    char hostname[16];
     uint8_t mac[6];
     cyw43_wifi_get_mac(&cyw43_state, CYW43_ITF_STA, mac);
     sprintf(hostname, "picow-%02x%02x%02x", mac[3], mac[4], mac[5]);
@@ -152,16 +238,42 @@ int setup_wifi(uint32_t country, const char *ssid, const char *pass, uint32_t au
     return 0;
 }
 
+// packets that make their sender the client
+static bool from_client(uint8_t type)
+{
+    switch (type) {
+        case PACKET_TYPE_KEYBOARD:
+        case PACKET_TYPE_MOUSE:
+        case PACKET_TYPE_GAMEPAD:
+        case PACKET_TYPE_KEYBOARD_REPORT:
+        case PACKET_TYPE_CONSUMER:
+        case PACKET_TYPE_SYSTEM_CONTROL:
+        case PACKET_TYPE_PING:
+        case PACKET_TYPE_ABSOLUTE_MOUSE:
+        case PACKET_TYPE_PEN:
+            return true;
+        default:
+            return false;
+    }
+}
+
 static void udp_receive(
     void *arg,
     struct udp_pcb *pcb,
     struct pbuf *p,
-    const struct ip4_addr *addr,
+    const ip_addr_t *addr,
     unsigned short port)
 {
     packet_header *hdr;
     packet_keyboard *kbd;
+    packet_keyboard_report *kbd_report;
     packet_mouse *mou;
+    packet_absolute_mouse *abs_mou;
+    packet_pen *pen;
+    packet_gamepad *pad;
+    packet_consumer *con;
+    packet_system_control *sys;
+    struct pbuf *reply;
 
     if (p == NULL) {
         return;
@@ -183,6 +295,17 @@ static void udp_receive(
         return;
     }
 
+    // the leds go to whoever sends input or pings, not to anyone looking
+    // for devices on the network
+    if (from_client(hdr->type) &&
+            (!client_known ||
+             !ip_addr_cmp(&client_addr, addr) ||
+             client_port != port)) {
+        ip_addr_copy(client_addr, *addr);
+        client_port = port;
+        client_known = true;
+    }
+
     if (hdr->type == PACKET_TYPE_KEYBOARD) {
         if (p->len != sizeof(packet_header) + sizeof(packet_keyboard)) {
             printf("Keyboard packet too short (%d)\r\n", p->len);
@@ -200,6 +323,14 @@ static void udp_receive(
             // printf("depressing key\r\n");
             depress_key(kbd->key);
         }
+    } else if (hdr->type == PACKET_TYPE_KEYBOARD_REPORT) {
+        if (p->len != sizeof(packet_header) + sizeof(packet_keyboard_report)) {
+            printf("Keyboard report packet too short (%d)\r\n", p->len);
+            pbuf_free(p);
+            return;
+        }
+        kbd_report = (packet_keyboard_report *) (p->payload + sizeof(packet_header));
+        set_keyboard(kbd_report->modifiers, kbd_report->keys);
     } else if (hdr->type == PACKET_TYPE_MOUSE) {
         if (p->len != sizeof(packet_header) + sizeof(packet_mouse)) {
             printf("Mouse packet too short (%d)\r\n", p->len);
@@ -214,6 +345,95 @@ static void udp_receive(
         //         mou->vertical,
         //         mou->horizontal);
         move_mouse(mou->buttons, mou->x, mou->y, mou->vertical, mou->horizontal);
+    } else if (hdr->type == PACKET_TYPE_ABSOLUTE_MOUSE) {
+        if (p->len != sizeof(packet_header) + sizeof(packet_absolute_mouse)) {
+            printf("Absolute mouse packet too short (%d)\r\n", p->len);
+            pbuf_free(p);
+            return;
+        }
+        abs_mou = (packet_absolute_mouse *) (p->payload + sizeof(packet_header));
+        move_mouse_to(
+                abs_mou->buttons,
+                abs_mou->x[0] | (abs_mou->x[1] << 8),
+                abs_mou->y[0] | (abs_mou->y[1] << 8),
+                abs_mou->vertical,
+                abs_mou->horizontal);
+    } else if (hdr->type == PACKET_TYPE_PEN) {
+        if (p->len != sizeof(packet_header) + sizeof(packet_pen)) {
+            printf("Pen packet too short (%d)\r\n", p->len);
+            pbuf_free(p);
+            return;
+        }
+        pen = (packet_pen *) (p->payload + sizeof(packet_header));
+        set_pen(
+                pen->flags,
+                pen->x[0] | (pen->x[1] << 8),
+                pen->y[0] | (pen->y[1] << 8),
+                pen->pressure[0] | (pen->pressure[1] << 8),
+                pen->tilt_x,
+                pen->tilt_y);
+    } else if (hdr->type == PACKET_TYPE_CONSUMER) {
+        if (p->len != sizeof(packet_header) + sizeof(packet_consumer)) {
+            printf("Consumer packet too short (%d)\r\n", p->len);
+            pbuf_free(p);
+            return;
+        }
+        con = (packet_consumer *) (p->payload + sizeof(packet_header));
+        press_consumer(con->usage[0] | (con->usage[1] << 8));
+    } else if (hdr->type == PACKET_TYPE_SYSTEM_CONTROL) {
+        if (p->len != sizeof(packet_header) + sizeof(packet_system_control)) {
+            printf("System control packet too short (%d)\r\n", p->len);
+            pbuf_free(p);
+            return;
+        }
+        sys = (packet_system_control *) (p->payload + sizeof(packet_header));
+        printf("Received system control: %02x\r\n", sys->control);
+        press_system_control(sys->control);
+    } else if (hdr->type == PACKET_TYPE_PING) {
+        if (p->len != sizeof(packet_header) + sizeof(packet_ping)) {
+            printf("Ping packet too short (%d)\r\n", p->len);
+            pbuf_free(p);
+            return;
+        }
+        reply = pbuf_alloc(PBUF_TRANSPORT, p->len, PBUF_RAM);
+        if (reply != NULL) {
+            memcpy(reply->payload, p->payload, p->len);
+            ((packet_header *) reply->payload)->type = PACKET_TYPE_PONG;
+            udp_sendto(pcb, reply, addr, port);
+            pbuf_free(reply);
+        }
+    } else if (hdr->type == PACKET_TYPE_DISCOVER) {
+        // answer with our hostname, usually to a broadcast
+        reply = pbuf_alloc(
+                PBUF_TRANSPORT,
+                sizeof(packet_header) + strlen(hostname),
+                PBUF_RAM);
+        if (reply != NULL) {
+            ((packet_header *) reply->payload)->type = PACKET_TYPE_DISCOVER_REPLY;
+            ((packet_header *) reply->payload)->version = 1;
+            memcpy(reply->payload + sizeof(packet_header), hostname, strlen(hostname));
+            udp_sendto(pcb, reply, addr, port);
+            pbuf_free(reply);
+        }
+    } else if (hdr->type == PACKET_TYPE_GAMEPAD) {
+        if (p->len != sizeof(packet_header) + sizeof(packet_gamepad)) {
+            printf("Gamepad packet too short (%d)\r\n", p->len);
+            pbuf_free(p);
+            return;
+        }
+        pad = (packet_gamepad *) (p->payload + sizeof(packet_header));
+        set_gamepad(
+                pad->x,
+                pad->y,
+                pad->z,
+                pad->rz,
+                pad->rx,
+                pad->ry,
+                pad->hat,
+                pad->buttons[0] |
+                    (pad->buttons[1] << 8) |
+                    (pad->buttons[2] << 16) |
+                    ((uint32_t) pad->buttons[3] << 24));
     } else {
         printf("Unknown packet type: %d\r\n", hdr->type);
         pbuf_free(p);
@@ -221,7 +441,7 @@ static void udp_receive(
     }
 
     /*
-    printf("Received %d bytes from %s:%d\r\n", p->len, ip4addr_ntoa(addr), port);
+    printf("Received %d bytes from %s:%d\r\n", p->len, ipaddr_ntoa(addr), port);
     for (int i = 0; i < p->len; i++) {
         printf("%02x ", req_data[i]);
     }
@@ -240,16 +460,96 @@ static void udp_receive(
     pbuf_free(p);
 }
 
+#if LWIP_MDNS_RESPONDER
+static void mdns_txt(struct mdns_service *service, void *userdata)
+{
+    mdns_resp_add_service_txtitem(service, "version=1", 9);
+}
+
+// answer mdns queries for <hostname>.local, and list us when someone browses
+// for _nethid._udp. lwip 2.2 dropped the ttl arguments
+void setup_mdns(void)
+{
+    static bool started = false;
+
+    if (started) {
+        // the address may have changed while the link was down
+        mdns_resp_announce(netif_default);
+        return;
+    }
+
+    mdns_resp_init();
+#if LWIP_VERSION_MAJOR == 2 && LWIP_VERSION_MINOR < 2
+    mdns_resp_add_netif(netif_default, hostname, 3600);
+    mdns_resp_add_service(netif_default, hostname, "_nethid",
+            DNSSD_PROTO_UDP, UDP_PORT, 3600, mdns_txt, NULL);
+#else
+    mdns_resp_add_netif(netif_default, hostname);
+    mdns_resp_add_service(netif_default, hostname, "_nethid",
+            DNSSD_PROTO_UDP, UDP_PORT, mdns_txt, NULL);
+#endif
+    started = true;
+}
+#endif
+
 int setup_server()
 {
     cyw43_arch_lwip_begin();
 
     printf("IP address: %s\n", ip4addr_ntoa(netif_ip4_addr(netif_list)));
+    printf("IPv6 address: %s\n", ip6addr_ntoa(netif_ip6_addr(netif_list, 0)));
 
-    pcb = udp_new();
-    udp_bind(pcb, IP_ADDR_ANY, 4444);
+    // ipv4 and ipv6 both
+    pcb = udp_new_ip_type(IPADDR_TYPE_ANY);
+    udp_bind(pcb, IP_ANY_TYPE, UDP_PORT);
     udp_recv(pcb, udp_receive, 0);
 
+#if LWIP_MDNS_RESPONDER
+    setup_mdns();
+#endif
+
+    cyw43_arch_lwip_end();
+}
+
+// send keyboard leds to the client whenever they change, or we get a new client
+void leds_task(void)
+{
+    static uint8_t prev_leds = 0;
+    static ip_addr_t prev_addr;
+    static u16_t prev_port = 0;
+
+    struct {
+        packet_header hdr;
+        packet_leds leds;
+    } packet;
+
+    if (!client_known || !wifi_up) {
+        return;
+    }
+
+    if (keyboard_leds == prev_leds &&
+            ip_addr_cmp(&client_addr, &prev_addr) &&
+            client_port == prev_port) {
+        return;
+    }
+
+    prev_leds = keyboard_leds;
+    ip_addr_copy(prev_addr, client_addr);
+    prev_port = client_port;
+
+    packet.hdr.type = PACKET_TYPE_LEDS;
+    packet.hdr.version = 1;
+    packet.leds.leds = keyboard_leds;
+
+    cyw43_arch_lwip_begin();
+
+    struct pbuf *p = pbuf_alloc(PBUF_TRANSPORT, sizeof(packet), PBUF_RAM);
+    if (p != NULL) {
+        memcpy(p->payload, &packet, sizeof(packet));
+        udp_sendto(pcb, p, &client_addr, client_port);
+        pbuf_free(p);
+    }
+
     cyw43_arch_lwip_end();
 }
 
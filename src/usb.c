@@ -39,16 +39,44 @@
 
 queue_t fifo_keyboard;
 queue_t fifo_mouse;
+queue_t fifo_consumer;
+queue_t fifo_system_control;
+queue_t fifo_pen;
 
-uint8_t keycodes[6] = { 0, 0, 0, 0, 0, 0 };
+typedef struct {
+    uint8_t modifiers;
+    uint8_t keycodes[6];
+} keyboard_data;
+
+keyboard_data keyboard = {
+    .modifiers = 0,
+    .keycodes = { 0, 0, 0, 0, 0, 0 }
+};
+
+// relative and absolute movement share the queue, so they stay in order
 typedef struct {
     uint8_t buttons;
-    int8_t x;
-    int8_t y;
+    bool absolute;
+    int16_t x;
+    int16_t y;
     int8_t vertical;
     int8_t horizontal;
 } mouse_data;
 
+// a pen report, as in the descriptor
+typedef struct TU_ATTR_PACKED {
+    uint8_t flags; // 0x01 tip, 0x02 barrel button, 0x04 eraser, 0x08 in range
+    uint16_t x;
+    uint16_t y;
+    uint16_t pressure;
+    int8_t tilt_x;
+    int8_t tilt_y;
+} pen_data;
+
+// gamepad is state based, so only the latest state is kept
+hid_gamepad_report_t gamepad_state = { 0 };
+bool gamepad_changed = false;
+
 //
 // Device callbacks
 //
@@ -57,8 +85,11 @@ typedef struct {
 void tud_mount_cb(void)
 {
     // initialize a fifo queue of hid reports
-    queue_init(&fifo_keyboard, sizeof(uint8_t[6]), 32);
+    queue_init(&fifo_keyboard, sizeof(keyboard_data), 32);
     queue_init(&fifo_mouse, sizeof(mouse_data), 128);
+    queue_init(&fifo_consumer, sizeof(uint16_t), 32);
+    queue_init(&fifo_system_control, sizeof(uint8_t), 8);
+    queue_init(&fifo_pen, sizeof(pen_data), 64);
     usb_mounted = true;
     update_blink_state();
 }
@@ -68,6 +99,9 @@ void tud_umount_cb(void)
 {
     queue_free(&fifo_keyboard);
     queue_free(&fifo_mouse);
+    queue_free(&fifo_consumer);
+    queue_free(&fifo_system_control);
+    queue_free(&fifo_pen);
     usb_mounted = false;
     update_blink_state();
 }
@@ -99,15 +133,15 @@ void press_key(uint16_t key)
 
     // check that key isn't already pressed
     for (int i = 0; i < 6; i++) {
-        if (keycodes[i] == key) {
+        if (keyboard.keycodes[i] == key) {
             return;
         }
     }
 
     // find a slot for they and set it as pressed
     for (int i = 0; i < 6; i++) {
-        if (keycodes[i] == 0) {
-            keycodes[i] = key;
+        if (keyboard.keycodes[i] == 0) {
+            keyboard.keycodes[i] = key;
             keys_changed = true;
             break;
         }
@@ -116,10 +150,10 @@ void press_key(uint16_t key)
     if (keys_changed) {
         // printf("Adding press to queue (%d): ", queue_get_level(&report_fifo));
         // for (int i = 0; i < 6; i++) {
-        //     printf("%02x ", keycodes[i]);
+        //     printf("%02x ", keyboard.keycodes[i]);
         // }
         // printf("\r\n");
-        if (!queue_try_add(&fifo_keyboard, keycodes)) {
+        if (!queue_try_add(&fifo_keyboard, &keyboard)) {
             printf("HID report queue full!\r\n");
         }
     }
@@ -131,8 +165,8 @@ void depress_key(uint16_t key)
 
     // find the key and set it as released
     for (int i = 0; i < 6; i++) {
-        if (keycodes[i] == key) {
-            keycodes[i] = 0;
+        if (keyboard.keycodes[i] == key) {
+            keyboard.keycodes[i] = 0;
             keys_changed = true;
             break;
         }
@@ -141,20 +175,47 @@ void depress_key(uint16_t key)
     if (keys_changed) {
         // printf("Adding depress to queue (%d): ", queue_get_level(&report_fifo));
         // for (int i = 0; i < 6; i++) {
-        //     printf("%02x ", keycodes[i]);
+        //     printf("%02x ", keyboard.keycodes[i]);
         // }
         // printf("\r\n");
 
-        if (!queue_try_add(&fifo_keyboard, keycodes)) {
+        if (!queue_try_add(&fifo_keyboard, &keyboard)) {
             printf("HID report queue full!\r\n");
         }
     }
 }
 
+void set_keyboard(uint8_t modifiers, const uint8_t keycodes[6])
+{
+    keyboard.modifiers = modifiers;
+    memcpy(keyboard.keycodes, keycodes, sizeof(keyboard.keycodes));
+
+    if (!queue_try_add(&fifo_keyboard, &keyboard)) {
+        printf("HID report queue full!\r\n");
+    }
+}
+
+// usage 0 releases the key
+void press_consumer(uint16_t usage)
+{
+    if (!queue_try_add(&fifo_consumer, &usage)) {
+        printf("Consumer report queue full!\r\n");
+    }
+}
+
+// control 0 releases the key
+void press_system_control(uint8_t control)
+{
+    if (!queue_try_add(&fifo_system_control, &control)) {
+        printf("System control report queue full!\r\n");
+    }
+}
+
 void move_mouse(uint8_t buttons, int8_t x, int8_t y, int8_t vertical, int8_t horizontal)
 {
     mouse_data data = {
         .buttons = buttons,
+        .absolute = false,
         .x = x,
         .y = y,
         .vertical = vertical,
@@ -165,6 +226,63 @@ void move_mouse(uint8_t buttons, int8_t x, int8_t y, int8_t vertical, int8_t hor
     }
 }
 
+// the logical maximum of x and y in the absolute mouse descriptor
+#define ABSOLUTE_MOUSE_MAX 0x7fff
+
+// x and y are 0 - 32767 across the whole desktop, anything past that is
+// taken as the far edge
+void move_mouse_to(uint8_t buttons, uint16_t x, uint16_t y, int8_t vertical, int8_t horizontal)
+{
+    mouse_data data = {
+        .buttons = buttons,
+        .absolute = true,
+        .x = x > ABSOLUTE_MOUSE_MAX ? ABSOLUTE_MOUSE_MAX : x,
+        .y = y > ABSOLUTE_MOUSE_MAX ? ABSOLUTE_MOUSE_MAX : y,
+        .vertical = vertical,
+        .horizontal = horizontal
+    };
+    if (!queue_try_add(&fifo_mouse, &data)) {
+        printf("Mouse report queue full!\r\n");
+    }
+}
+
+// x and y are 0 - 32767 across the whole desktop, pressure 0 - 4095
+void set_pen(uint8_t flags, uint16_t x, uint16_t y, uint16_t pressure, int8_t tilt_x, int8_t tilt_y)
+{
+    pen_data data = {
+        .flags = flags,
+        .x = x,
+        .y = y,
+        .pressure = pressure,
+        .tilt_x = tilt_x,
+        .tilt_y = tilt_y
+    };
+    if (!queue_try_add(&fifo_pen, &data)) {
+        printf("Pen report queue full!\r\n");
+    }
+}
+
+void set_gamepad(
+    int8_t x,
+    int8_t y,
+    int8_t z,
+    int8_t rz,
+    int8_t rx,
+    int8_t ry,
+    uint8_t hat,
+    uint32_t buttons)
+{
+    gamepad_state.x = x;
+    gamepad_state.y = y;
+    gamepad_state.z = z;
+    gamepad_state.rz = rz;
+    gamepad_state.rx = rx;
+    gamepad_state.ry = ry;
+    gamepad_state.hat = hat;
+    gamepad_state.buttons = buttons;
+    gamepad_changed = true;
+}
+
 //
 // private function for sending updated usb packet
 //
@@ -178,7 +296,11 @@ static void send_events()
 
     if (tud_suspended() &&
             (!queue_is_empty(&fifo_keyboard) ||
-             !queue_is_empty(&fifo_mouse))) {
+             !queue_is_empty(&fifo_mouse) ||
+             !queue_is_empty(&fifo_consumer) ||
+             !queue_is_empty(&fifo_system_control) ||
+             !queue_is_empty(&fifo_pen) ||
+             gamepad_changed)) {
         // Wake up host if we are in suspend mode
         // and REMOTE_WAKEUP feature is enabled by host
         tud_remote_wakeup();
@@ -187,24 +309,36 @@ static void send_events()
     }
 
     // send the next report in the queue
-    uint8_t new_keycodes[6] = { 0, 0, 0, 0, 0, 0 };
+    keyboard_data new_keyboard = {
+        .modifiers = 0,
+        .keycodes = { 0, 0, 0, 0, 0, 0 }
+    };
     mouse_data new_mouse_data = { 
         .buttons = 0,
+        .absolute = false,
         .x = 0,
         .y = 0,
         .vertical = 0,
         .horizontal = 0
     };
-
-    if (queue_is_empty(&fifo_keyboard) && queue_is_empty(&fifo_mouse)) {
+    uint16_t new_consumer_usage = 0;
+    uint8_t new_system_control = 0;
+    pen_data new_pen;
+
+    if (queue_is_empty(&fifo_keyboard) &&
+            queue_is_empty(&fifo_mouse) &&
+            queue_is_empty(&fifo_consumer) &&
+            queue_is_empty(&fifo_system_control) &&
+            queue_is_empty(&fifo_pen) &&
+            !gamepad_changed) {
         return;
     }
 
     // printf("Removing from queue (%d)\r\n", queue_get_level(&report_fifo));
-    if (queue_try_remove(&fifo_keyboard, &new_keycodes)) {
+    if (queue_try_remove(&fifo_keyboard, &new_keyboard)) {
         // printf("Sending report: ");
         // for (int i = 0; i < 6; i++) {
-        //     printf("%02x ", new_keycodes[i]);
+        //     printf("%02x ", new_keyboard.keycodes[i]);
         // }
         // printf("\r\n");
         /*
@@ -218,20 +352,57 @@ static void send_events()
         */
         tud_hid_keyboard_report(
                 REPORT_ID_KEYBOARD,
-                0,  // modifiers
-                new_keycodes);
+                new_keyboard.modifiers,
+                new_keyboard.keycodes);
     } else if (queue_try_remove(&fifo_mouse, &new_mouse_data)) {
         // printf("Sending mouse data: xrel: %d, yrel: %d\r\n",
         //         new_mouse_data.x,
         //         new_mouse_data.y);
         // printf("\r\n");
-        tud_hid_mouse_report(
-                REPORT_ID_MOUSE,
-                new_mouse_data.buttons,
-                new_mouse_data.x,
-                new_mouse_data.y,
-                new_mouse_data.vertical,
-                new_mouse_data.horizontal);
+        if (new_mouse_data.absolute) {
+            tud_hid_abs_mouse_report(
+                    REPORT_ID_ABSOLUTE_MOUSE,
+                    new_mouse_data.buttons,
+                    new_mouse_data.x,
+                    new_mouse_data.y,
+                    new_mouse_data.vertical,
+                    new_mouse_data.horizontal);
+        } else {
+            tud_hid_mouse_report(
+                    REPORT_ID_MOUSE,
+                    new_mouse_data.buttons,
+                    new_mouse_data.x,
+                    new_mouse_data.y,
+                    new_mouse_data.vertical,
+                    new_mouse_data.horizontal);
+        }
+    } else if (queue_try_remove(&fifo_consumer, &new_consumer_usage)) {
+        tud_hid_report(
+                REPORT_ID_CONSUMER_CONTROL,
+                &new_consumer_usage,
+                sizeof(new_consumer_usage));
+    } else if (queue_try_remove(&fifo_system_control, &new_system_control)) {
+        tud_hid_report(
+                REPORT_ID_SYSTEM_CONTROL,
+                &new_system_control,
+                sizeof(new_system_control));
+    } else if (queue_try_remove(&fifo_pen, &new_pen)) {
+        tud_hid_report(
+                REPORT_ID_PEN,
+                &new_pen,
+                sizeof(new_pen));
+    } else if (gamepad_changed) {
+        gamepad_changed = false;
+        tud_hid_gamepad_report(
+                REPORT_ID_GAMEPAD,
+                gamepad_state.x,
+                gamepad_state.y,
+                gamepad_state.z,
+                gamepad_state.rz,
+                gamepad_state.rx,
+                gamepad_state.ry,
+                gamepad_state.hat,
+                gamepad_state.buttons);
     }
 }
 
@@ -305,6 +476,8 @@ void tud_hid_set_report_cb(
 
             uint8_t const kbd_leds = buffer[0];
 
+            keyboard_leds = kbd_leds;
+
             if (kbd_leds & KEYBOARD_LED_CAPSLOCK) {
                 capslock_on = true;
                 update_blink_state();
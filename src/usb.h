@@ -39,6 +39,20 @@ void tud_suspend_cb(bool remote_wakeup_en);
 void tud_resume_cb(void);
 void press_key(uint16_t key);
 void depress_key(uint16_t key);
+void set_keyboard(uint8_t modifiers, const uint8_t keycodes[6]);
+void press_consumer(uint16_t usage);
+void press_system_control(uint8_t control);
 void move_mouse(uint8_t buttons, int8_t x, int8_t y, int8_t vertical, int8_t horizontal);
+void move_mouse_to(uint8_t buttons, uint16_t x, uint16_t y, int8_t vertical, int8_t horizontal);
+void set_pen(uint8_t flags, uint16_t x, uint16_t y, uint16_t pressure, int8_t tilt_x, int8_t tilt_y);
+void set_gamepad(
+    int8_t x,
+    int8_t y,
+    int8_t z,
+    int8_t rz,
+    int8_t rx,
+    int8_t ry,
+    uint8_t hat,
+    uint32_t buttons);
 
 #endif
\ No newline at end of file
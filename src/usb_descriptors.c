@@ -74,12 +74,65 @@ uint8_t const * tud_descriptor_device_cb(void)
 // HID Report Descriptor
 //--------------------------------------------------------------------+
 
+// Pen: tip, barrel, eraser and in range bits, x and y from 0 to 32767,
+// pressure from 0 to 4095, x and y tilt in degrees
+#define TUD_HID_REPORT_DESC_PEN(...) \
+  HID_USAGE_PAGE ( HID_USAGE_PAGE_DIGITIZER               ) ,\
+  HID_USAGE      ( 0x02 /* pen */                         ) ,\
+  HID_COLLECTION ( HID_COLLECTION_APPLICATION             ) ,\
+    /* Report ID if any */\
+    __VA_ARGS__ \
+    HID_USAGE      ( 0x20 /* stylus */                    ) ,\
+    HID_COLLECTION ( HID_COLLECTION_PHYSICAL              ) ,\
+      HID_USAGE          ( 0x42 /* tip switch */          ) ,\
+      HID_USAGE          ( 0x44 /* barrel switch */       ) ,\
+      HID_USAGE          ( 0x45 /* eraser */              ) ,\
+      HID_USAGE          ( 0x32 /* in range */            ) ,\
+      HID_LOGICAL_MIN    ( 0                              ) ,\
+      HID_LOGICAL_MAX    ( 1                              ) ,\
+      HID_REPORT_COUNT   ( 4                              ) ,\
+      HID_REPORT_SIZE    ( 1                              ) ,\
+      HID_INPUT          ( HID_DATA | HID_VARIABLE | HID_ABSOLUTE ) ,\
+      /* 4 bit padding */ \
+      HID_REPORT_COUNT   ( 1                              ) ,\
+      HID_REPORT_SIZE    ( 4                              ) ,\
+      HID_INPUT          ( HID_CONSTANT                   ) ,\
+      HID_USAGE_PAGE     ( HID_USAGE_PAGE_DESKTOP         ) ,\
+      HID_USAGE          ( HID_USAGE_DESKTOP_X            ) ,\
+      HID_USAGE          ( HID_USAGE_DESKTOP_Y            ) ,\
+      HID_LOGICAL_MIN    ( 0                              ) ,\
+      HID_LOGICAL_MAX_N  ( 0x7fff, 2                      ) ,\
+      HID_REPORT_COUNT   ( 2                              ) ,\
+      HID_REPORT_SIZE    ( 16                             ) ,\
+      HID_INPUT          ( HID_DATA | HID_VARIABLE | HID_ABSOLUTE ) ,\
+      HID_USAGE_PAGE     ( HID_USAGE_PAGE_DIGITIZER       ) ,\
+      HID_USAGE          ( 0x30 /* tip pressure */        ) ,\
+      HID_LOGICAL_MAX_N  ( 4095, 2                        ) ,\
+      HID_REPORT_COUNT   ( 1                              ) ,\
+      HID_REPORT_SIZE    ( 16                             ) ,\
+      HID_INPUT          ( HID_DATA | HID_VARIABLE | HID_ABSOLUTE ) ,\
+      HID_USAGE          ( 0x3d /* x tilt */              ) ,\
+      HID_USAGE          ( 0x3e /* y tilt */              ) ,\
+      HID_LOGICAL_MIN    ( 0xa6 /* -90 */                 ) ,\
+      HID_LOGICAL_MAX    ( 0x5a /* 90 */                  ) ,\
+      HID_PHYSICAL_MIN   ( 0xa6                           ) ,\
+      HID_PHYSICAL_MAX   ( 0x5a                           ) ,\
+      HID_UNIT           ( 0x14 /* degrees */             ) ,\
+      HID_REPORT_COUNT   ( 2                              ) ,\
+      HID_REPORT_SIZE    ( 8                              ) ,\
+      HID_INPUT          ( HID_DATA | HID_VARIABLE | HID_ABSOLUTE ) ,\
+    HID_COLLECTION_END , \
+  HID_COLLECTION_END
+
 uint8_t const desc_hid_report[] =
 {
   TUD_HID_REPORT_DESC_KEYBOARD( HID_REPORT_ID(REPORT_ID_KEYBOARD         )),
   TUD_HID_REPORT_DESC_MOUSE   ( HID_REPORT_ID(REPORT_ID_MOUSE            )),
   TUD_HID_REPORT_DESC_CONSUMER( HID_REPORT_ID(REPORT_ID_CONSUMER_CONTROL )),
-  TUD_HID_REPORT_DESC_GAMEPAD ( HID_REPORT_ID(REPORT_ID_GAMEPAD          ))
+  TUD_HID_REPORT_DESC_GAMEPAD ( HID_REPORT_ID(REPORT_ID_GAMEPAD          )),
+  TUD_HID_REPORT_DESC_SYSTEM_CONTROL( HID_REPORT_ID(REPORT_ID_SYSTEM_CONTROL )),
+  TUD_HID_REPORT_DESC_ABSMOUSE( HID_REPORT_ID(REPORT_ID_ABSOLUTE_MOUSE )),
+  TUD_HID_REPORT_DESC_PEN     ( HID_REPORT_ID(REPORT_ID_PEN             ))
 };
 
 // Invoked when received GET HID REPORT DESCRIPTOR
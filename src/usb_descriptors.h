@@ -31,6 +31,9 @@ enum
   REPORT_ID_MOUSE,
   REPORT_ID_CONSUMER_CONTROL,
   REPORT_ID_GAMEPAD,
+  REPORT_ID_SYSTEM_CONTROL,
+  REPORT_ID_ABSOLUTE_MOUSE,
+  REPORT_ID_PEN,
   REPORT_ID_COUNT
 };
 